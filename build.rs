@@ -70,7 +70,56 @@ fn lookup_instr(opcode: u8) -> Instruction {
         )
         .unwrap();
 
+    build_rom_database(Path::new(&out_dir));
+
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/nes/cartridge/database.csv");
+}
+
+/// Turns the ROM database into a table of `RomEntry`s
+fn build_rom_database(out_dir: &Path) {
+    let database = include_str!("src/nes/cartridge/database.csv");
+    let mut rdr = ReaderBuilder::new().from_reader(database.as_bytes());
+    let mut file = fs::File::create(out_dir.join("rom_database.rs")).unwrap();
+
+    file.write_all(b"const ROM_DATABASE: &[RomEntry] = &[\n")
+        .unwrap();
+    rdr.records().for_each(|it| {
+        let line = format!("{},\n", csv_to_rom_entry(&it.unwrap()));
+        file.write_all(line.as_bytes()).unwrap();
+    });
+    file.write_all(b"];\n").unwrap();
+}
+
+fn csv_to_rom_entry(record: &csv::StringRecord) -> String {
+    let crc32 = u32::from_str_radix(&record[0], 16).unwrap();
+    let sha1: Vec<String> = (0..20)
+        .map(|i| format!("0x{}", &record[1][i * 2..i * 2 + 2]))
+        .collect();
+
+    let mirror = match &record[4] {
+        "V" => "MirrorDirection::Vertical",
+        "4" => "MirrorDirection::FourScreen",
+        _ => "MirrorDirection::Horizontal",
+    };
+    let region = match &record[6] {
+        "PAL" => "CartridgeRegion::PAL",
+        "Dendy" => "CartridgeRegion::Dendy",
+        _ => "CartridgeRegion::NTSC",
+    };
+
+    format!(
+        "RomEntry {{ crc32: 0x{:08x}, sha1: [{}], mapper: {}, submapper: {}, mirror: {}, \
+         has_battery: {}, region: {}, title: {:?} }}",
+        crc32,
+        sha1.join(", "),
+        record[2].parse::<u8>().unwrap(),
+        record[3].parse::<u8>().unwrap(),
+        mirror,
+        &record[5] == "1",
+        region,
+        &record[7],
+    )
 }
 
 fn csv_to_instr(record: &csv::StringRecord, is_legal: bool) -> (u8, String, String) {
@@ -0,0 +1,91 @@
+#!/usr/bin/env python3
+"""
+Regenerates src/nes/cartridge/database.csv from the NES 2.0 XML database
+(nes20db.xml, maintained by NewRisingSun and published on the NESdev forums).
+
+    python3 scripts/import_nes20db.py path/to/nes20db.xml
+
+Only boards the emulator has a mapper for are kept, since correcting a header
+to a board we can't run doesn't help anyone. Add the mapper number to
+SUPPORTED_MAPPERS when a new mapper lands and re-run the import.
+
+Every game's <rom> element hashes PRG and CHR ROM together, without the header
+or trainer, which is what Cartridge::identify matches on.
+
+Rows already in the CSV that the XML doesn't have, like the test ROMs under
+tests/ that the database tests load, are carried over.
+"""
+
+import csv
+import os
+import sys
+import xml.etree.ElementTree as ET
+
+# Keep in sync with mapper::from_cartridge
+SUPPORTED_MAPPERS = {
+    0, 4, 11, 13, 15, 16, 18, 19, 21, 22, 23, 24, 25, 26, 32, 33, 34, 48, 64,
+    65, 66, 68, 71, 85, 91, 118, 119, 159, 200, 201, 203, 206,
+}
+
+# <pcb mirroring>: H, V, 4 for four screen, 1 when the mapper controls it.
+# Mapper controlled boards ignore the header bit, so call it horizontal like
+# the iNES header would.
+MIRRORING = {"H": "H", "V": "V", "4": "4", "1": "H"}
+
+# <console region>: 0 NTSC, 1 PAL, 2 either, 3 Dendy
+REGIONS = {"0": "NTSC", "1": "PAL", "2": "NTSC", "3": "Dendy"}
+
+HEADER = ["crc32", "sha1", "mapper", "submapper", "mirroring", "battery", "region", "title"]
+DATABASE = os.path.join(os.path.dirname(__file__), "..", "src", "nes", "cartridge", "database.csv")
+
+
+def title(comment):
+    # Comments are the file's path in the set, e.g. "\NES\Licensed\Game (USA).nes"
+    name = comment.strip().replace("\\", "/").rsplit("/", 1)[-1]
+    return name[:-4] if name.lower().endswith(".nes") else name
+
+
+def main(path):
+    parser = ET.XMLParser(target=ET.TreeBuilder(insert_comments=True))
+    root = ET.parse(path, parser).getroot()
+
+    rows = []
+    for game in root.iter("game"):
+        rom, pcb, console = game.find("rom"), game.find("pcb"), game.find("console")
+        if rom is None or pcb is None:
+            continue
+        mapper = int(pcb.get("mapper", "0"))
+        if mapper not in SUPPORTED_MAPPERS:
+            continue
+        # Vs. System, PlayChoice and the like need more than a cartridge
+        if console is not None and console.get("type", "0") != "0":
+            continue
+
+        comment = next((c.text for c in game if c.tag is ET.Comment), "")
+        rows.append([
+            rom.get("crc32").lower(),
+            rom.get("sha1").lower(),
+            mapper,
+            int(pcb.get("submapper", "0")),
+            MIRRORING.get(pcb.get("mirroring", "H"), "H"),
+            1 if pcb.get("battery", "0") == "1" else 0,
+            REGIONS.get(console.get("region", "0") if console is not None else "0", "NTSC"),
+            title(comment),
+        ])
+
+    imported = {(row[0], row[1]) for row in rows}
+    with open(DATABASE, newline="") as f:
+        old = list(csv.reader(f))[1:]
+    kept = [row for row in old if (row[0], row[1]) not in imported]
+
+    with open(DATABASE, "w", newline="") as f:
+        out = csv.writer(f, lineterminator="\n")
+        out.writerow(HEADER)
+        out.writerows(sorted(rows + kept, key=lambda r: str(r[7]).lower()))
+    print("Imported {} games, kept {} rows".format(len(rows), len(kept)))
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        sys.exit("usage: import_nes20db.py nes20db.xml")
+    main(sys.argv[1])
@@ -52,6 +52,26 @@ pub fn run_debugger<'a>(nes: &'a mut IronNes, debugger: &'a mut IronNesDebugger)
         Ok(())
     });
 
+    shell.new_command("disk", "insert disk side, or eject", 1, |io, (_, nes), s| {
+        if s[0] == "eject" {
+            nes.insert_disk(None);
+            writeln!(io, "disk ejected")?;
+            return Ok(());
+        }
+
+        match s[0].parse::<usize>() {
+            Ok(side) if side >= nes.disk_sides() => {
+                writeln!(io, "no side {}, disk has {}", side, nes.disk_sides())?;
+            }
+            Ok(side) => {
+                nes.insert_disk(Some(side));
+                writeln!(io, "disk side {}", side)?;
+            }
+            Err(e) => writeln!(io, "bad disk side '{}': {}, or use eject", s[0], e)?,
+        }
+        Ok(())
+    });
+
     shell.new_command("p", "print addr -> range", 2, |io, (_, nes), s| {
         let addr = Addr::from_str_radix(s[0], 16).unwrap();
         let range = Addr::from_str_radix(s[1], 10).unwrap();
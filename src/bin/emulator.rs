@@ -1,10 +1,19 @@
 use clap;
 use simplelog::*;
 use std::fs::File;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 
 mod debugger;
 
 use iron_nes::error::*;
+use iron_nes::nes::cartridge::CartridgeRegion;
+use iron_nes::nes::ppu::{ntsc, Frame, Overscan, Palette};
 use iron_nes::nes::IronNes;
 
 fn main() -> IronNesResult<()> {
@@ -38,14 +47,152 @@ fn main() -> IronNesResult<()> {
     CombinedLogger::init(loggers).unwrap();
 
     let mut nes = IronNes::new();
+    if let Some(bios) = matches.value_of("bios") {
+        nes.set_fds_bios(bios);
+    }
+    for patch in matches.values_of("patch").into_iter().flatten() {
+        nes.add_patch(patch);
+    }
+    match matches.value_of("region") {
+        Some("ntsc") => nes.set_region(CartridgeRegion::NTSC),
+        Some("pal") => nes.set_region(CartridgeRegion::PAL),
+        Some("dendy") => nes.set_region(CartridgeRegion::Dendy),
+        _ => (),
+    }
+    if let Some(palette) = matches.value_of("palette") {
+        match Palette::builtin(palette) {
+            Some(palette) => nes.set_palette(palette),
+            None => nes.set_palette(Palette::open(palette)?),
+        }
+    }
+    if matches.is_present("palette-gen") {
+        let params: ntsc::NtscParams = matches.value_of("palette-gen").unwrap_or("").parse()?;
+        nes.set_palette(ntsc::generate_palette(&params));
+    }
+    let filter = match matches.is_present("ntsc") {
+        true => {
+            let params: ntsc::NtscParams = matches.value_of("ntsc").unwrap_or("").parse()?;
+            Some(ntsc::NtscFilter::new(params))
+        }
+        false => None,
+    };
+    let overscan: Overscan = matches.value_of("overscan").unwrap().parse()?;
     nes.boot(rom)?;
 
-    match is_debug {
+    let result = match is_debug {
         true => {
             let mut debugger = debugger::IronNesDebugger::new();
             debugger::run_debugger(&mut nes, &mut debugger);
             Ok(())
         }
-        _ => nes.run(),
+        _ => run_window(&mut nes, filter, overscan),
+    };
+
+    nes.save()?;
+    result
+}
+
+/**
+ * Shows the NES in a window, a frame at a time, until it's closed. With a
+ * Disk System game in, D turns the disk to its next side and E ejects it.
+ */
+fn run_window(
+    nes: &mut IronNes,
+    mut filter: Option<ntsc::NtscFilter>,
+    overscan: Overscan,
+) -> IronNesResult<()> {
+    const SCALE: u32 = 2;
+    let frame_time = Duration::from_secs_f64(1.0 / nes.timing().frame_rate());
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            "IronNES",
+            overscan.width() as u32 * SCALE,
+            overscan.height() as u32 * SCALE,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let mut canvas = window.into_canvas().accelerated().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    // The filter's wider picture is squeezed back into the same window, so
+    // its overscan is cropped when it's drawn rather than by the frame
+    let (width, height) = match filter {
+        Some(_) => (ntsc::NtscFilter::WIDTH, Frame::HEIGHT),
+        None => (overscan.width(), overscan.height()),
+    };
+    let crop = match filter {
+        Some(_) => {
+            let scale = |x: usize| (x * ntsc::NtscFilter::WIDTH / Frame::WIDTH) as i32;
+            let left = scale(overscan.left);
+            let right = scale(Frame::WIDTH - overscan.right);
+            Rect::new(
+                left,
+                overscan.top as i32,
+                (right - left) as u32,
+                overscan.height() as u32,
+            )
+        }
+        None => Rect::new(0, 0, width as u32, height as u32),
+    };
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .unwrap();
+
+    let mut side = nes.disk_side();
+    loop {
+        let start = Instant::now();
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return Ok(()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::D),
+                    repeat: false,
+                    ..
+                } if nes.disk_sides() > 0 => {
+                    // After an eject the first side goes back in
+                    side = Some(side.map_or(0, |s| (s + 1) % nes.disk_sides()));
+                    nes.insert_disk(side);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::E),
+                    repeat: false,
+                    ..
+                } if nes.disk_sides() > 0 => {
+                    side = None;
+                    nes.insert_disk(side);
+                }
+                _ => (),
+            }
+        }
+
+        nes.step_frame()?;
+        let frame = nes.frame();
+        match filter.as_mut() {
+            Some(filter) => {
+                let image = filter.apply(frame);
+                texture.update(None, &image.pixels, image.pitch())
+            }
+            None => {
+                let pixels = frame.to_rgb24(nes.ppu().colours(), &overscan);
+                texture.update(None, &pixels, width * 3)
+            }
+        }
+        .unwrap();
+        canvas.copy(&texture, crop, None).unwrap();
+        canvas.present();
+
+        if let Some(rest) = frame_time.checked_sub(start.elapsed()) {
+            thread::sleep(rest);
+        }
     }
 }
@@ -0,0 +1,205 @@
+use clap::value_t;
+use simplelog::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::thread;
+use std::time::Duration;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+use iron_nes::error::*;
+use iron_nes::nes::nsf::Nsf;
+use iron_nes::nes::IronNes;
+
+fn main() -> IronNesResult<()> {
+    let yaml = clap::load_yaml!("nsfplay.yml");
+    let matches = clap::App::from_yaml(yaml)
+        .version(&*format!("v{}", clap::crate_version!()))
+        .get_matches();
+
+    let file = matches.value_of("file").unwrap();
+    let log_level = match matches.occurrences_of("v") {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        _ => LevelFilter::Trace,
+    };
+    TermLogger::init(log_level, Config::default(), TerminalMode::Mixed).unwrap();
+
+    let mut nes = IronNes::new();
+    nes.boot(file)?;
+    let nsf = nes.nsf().unwrap().clone();
+
+    if matches.is_present("list") {
+        list_tracks(&nsf);
+        return Ok(());
+    }
+
+    let track = match matches.value_of("track") {
+        Some(_) => value_t!(matches, "track", u8).unwrap_or_else(|e| e.exit()),
+        None => nsf.start_song + 1,
+    };
+    let song = track.saturating_sub(1);
+    nes.start_song(song)?;
+
+    let rate = value_t!(matches, "rate", u32).unwrap_or_else(|e| e.exit());
+    let seconds = match matches.value_of("seconds") {
+        Some(_) => Some(value_t!(matches, "seconds", f64).unwrap_or_else(|e| e.exit())),
+        None => nsf.track_time(song).map(|ms| ms as f64 / 1000.0),
+    };
+    let samples = seconds.map(|s| (s * rate as f64) as usize);
+    let mut sampler = Sampler::new(rate, nes.timing().cpu_frequency());
+
+    match matches.value_of("wav") {
+        Some(wav) => {
+            // Two and a half minutes is plenty for most game tunes
+            let samples = samples.unwrap_or(150 * rate as usize);
+            let mut audio = vec![0.0; samples];
+            sampler.render(&mut nes, &mut audio)?;
+            write_wav(wav, rate, &audio)
+        }
+        None => play(&mut nes, &mut sampler, samples),
+    }
+}
+
+fn list_tracks(nsf: &Nsf) {
+    println!("Title:     {}", nsf.title);
+    println!("Artist:    {}", nsf.artist);
+    println!("Copyright: {}", nsf.copyright);
+
+    const CHIPS: [(u8, &str); 6] = [
+        (Nsf::CHIP_VRC6, "VRC6"),
+        (Nsf::CHIP_VRC7, "VRC7"),
+        (Nsf::CHIP_FDS, "FDS"),
+        (Nsf::CHIP_MMC5, "MMC5"),
+        (Nsf::CHIP_N163, "N163"),
+        (Nsf::CHIP_5B, "5B"),
+    ];
+    let chips: Vec<_> = CHIPS
+        .iter()
+        .filter(|(flag, _)| (nsf.chips & flag) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if !chips.is_empty() {
+        println!("Chips:     {}", chips.join(", "));
+    }
+    println!("Region:    {}", if nsf.pal { "PAL" } else { "NTSC" });
+    println!();
+
+    for song in 0..nsf.songs {
+        let time = match nsf.track_time(song) {
+            Some(ms) => format!("{}:{:02}", ms / 60_000, (ms / 1000) % 60),
+            None => String::from("-:--"),
+        };
+        let first = if song == nsf.start_song { "*" } else { " " };
+        let name = nsf.track_name(song).unwrap_or("");
+        println!("{}{:3}  {:>5}  {}", first, song + 1, time, name);
+    }
+}
+
+/// Plays through SDL, until the track's length when it's known
+fn play(nes: &mut IronNes, sampler: &mut Sampler, samples: Option<usize>) -> IronNesResult<()> {
+    const CHUNK: usize = 1024;
+
+    let sdl_context = sdl2::init().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let spec = AudioSpecDesired {
+        freq: Some(sampler.rate as i32),
+        channels: Some(1),
+        samples: Some(CHUNK as u16),
+    };
+    let queue: AudioQueue<f32> = audio_subsystem.open_queue(None, &spec).unwrap();
+    queue.resume();
+
+    // Keep about a fifth of a second queued up
+    let buffered = sampler.rate as usize / 5 * std::mem::size_of::<f32>();
+    let mut chunk = vec![0.0; CHUNK];
+    let mut played = 0;
+
+    while samples.is_none_or(|s| played < s) {
+        if queue.size() as usize >= buffered {
+            thread::sleep(Duration::from_millis(5));
+            continue;
+        }
+        sampler.render(nes, &mut chunk)?;
+        queue.queue(&chunk);
+        played += chunk.len();
+    }
+
+    while queue.size() > 0 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    Ok(())
+}
+
+/**
+ * Takes samples of the emulator's audio at an output sample rate, with a high
+ * pass filter taking out the DC offset the way the NES's output stage does.
+ */
+struct Sampler {
+    rate: u32,
+    cycles_per_sample: f64,
+    next: f64,
+    last_in: f32,
+    last_out: f32,
+}
+
+impl Sampler {
+    /// Roughly 30Hz at 44.1kHz
+    const HIGH_PASS: f32 = 0.996;
+
+    /// Samples a CPU running at `cpu_frequency` Hz
+    fn new(rate: u32, cpu_frequency: f64) -> Self {
+        Self {
+            rate,
+            cycles_per_sample: cpu_frequency / rate as f64,
+            next: 0.0,
+            last_in: 0.0,
+            last_out: 0.0,
+        }
+    }
+
+    fn render(&mut self, nes: &mut IronNes, out: &mut [f32]) -> IronNesResult<()> {
+        if self.next == 0.0 {
+            self.next = nes.get_cycles() as f64;
+        }
+
+        for sample in out.iter_mut() {
+            self.next += self.cycles_per_sample;
+            while (nes.get_cycles() as f64) < self.next {
+                nes.step()?;
+            }
+
+            let level = nes.audio_sample();
+            self.last_out = Self::HIGH_PASS * (self.last_out + level - self.last_in);
+            self.last_in = level;
+            *sample = self.last_out.clamp(-1.0, 1.0);
+        }
+        Ok(())
+    }
+}
+
+/// Writes mono 16 bit PCM
+fn write_wav(path: &str, rate: u32, samples: &[f32]) -> IronNesResult<()> {
+    let data_size = samples.len() as u32 * 2;
+    let mut out = BufWriter::new(File::create(path)?);
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&rate.to_le_bytes())?;
+    out.write_all(&(rate * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+
+    for sample in samples {
+        out.write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use clap::{value_t, ArgMatches};
+use log::*;
+use simplelog::*;
+use std::fs;
+use std::path::Path;
+
+use iron_nes::error::*;
+use iron_nes::hash;
+use iron_nes::nes::archive::RomFile;
+use iron_nes::nes::cartridge::{which_mapper, Cartridge, CartridgeRegion, MirrorDirection};
+
+/// A ROM file split into its parts, with the cartridge its header describes
+struct Rom {
+    file: RomFile,
+    cartridge: Cartridge,
+    prog_rom: Vec<u8>,
+    ppu_rom: Vec<u8>,
+}
+
+impl Rom {
+    fn open(path: &str) -> IronNesResult<Self> {
+        let file = RomFile::open(path)?;
+        let (cartridge, prog_rom, ppu_rom) = Cartridge::from_bytes(&file.data)?;
+        Ok(Self {
+            file,
+            cartridge,
+            prog_rom,
+            ppu_rom,
+        })
+    }
+
+    fn is_unif(&self) -> bool {
+        self.file.data.starts_with(b"UNIF")
+    }
+
+    /// The file's header, or a NES 2.0 one for UNIF files which have none
+    fn header(&self) -> IronNesResult<Vec<u8>> {
+        let header = match self.is_unif() {
+            true => self
+                .cartridge
+                .to_bytes(&self.prog_rom, &self.ppu_rom, None, true)?,
+            false => self.file.data.clone(),
+        };
+        Ok(header[..Cartridge::NES_FILE_HEADER_SIZE].to_vec())
+    }
+
+    fn trainer(&self) -> Option<&[u8]> {
+        let start = Cartridge::NES_FILE_HEADER_SIZE;
+        match self.cartridge.has_trainer {
+            true => Some(&self.file.data[start..start + Cartridge::TRAINER_SIZE]),
+            false => None,
+        }
+    }
+}
+
+fn main() -> IronNesResult<()> {
+    let yaml = clap::load_yaml!("romtool.yml");
+    let matches = clap::App::from_yaml(yaml)
+        .version(&*format!("v{}", clap::crate_version!()))
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("v") {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        _ => LevelFilter::Trace,
+    };
+    TermLogger::init(log_level, Config::default(), TerminalMode::Mixed).unwrap();
+
+    match matches.subcommand() {
+        ("info", Some(m)) => info(m),
+        ("hash", Some(m)) => hashes(m),
+        ("split", Some(m)) => split(m),
+        ("merge", Some(m)) => merge(m),
+        ("nes2", Some(m)) => rewrite(m, Some(true)),
+        ("clean", Some(m)) => rewrite(m, None),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+fn info(m: &ArgMatches) -> IronNesResult<()> {
+    let rom = Rom::open(m.value_of("rom").unwrap())?;
+    let c = &rom.cartridge;
+
+    let format = match (rom.is_unif(), c.is_nes2) {
+        (true, _) => "UNIF",
+        (false, true) => "NES 2.0",
+        (false, false) => "iNES",
+    };
+    let mirror = match c.mirror {
+        MirrorDirection::Horizontal => "horizontal",
+        MirrorDirection::Vertical => "vertical",
+        MirrorDirection::FourScreen => "four screen",
+        MirrorDirection::SingleScreenLower => "single screen lower",
+        MirrorDirection::SingleScreenUpper => "single screen upper",
+    };
+    let region = match c.region {
+        CartridgeRegion::NTSC => "NTSC",
+        CartridgeRegion::PAL => "PAL",
+        CartridgeRegion::Dendy => "Dendy",
+    };
+
+    if m.is_present("json") {
+        let title = match &c.title {
+            Some(title) => format!("\"{}\"", json_escape(title)),
+            None => "null".to_string(),
+        };
+        println!("{{");
+        println!("  \"format\": \"{}\",", format);
+        println!("  \"mapper\": {},", c.mapper);
+        println!("  \"submapper\": {},", c.submapper);
+        println!("  \"board\": \"{}\",", json_escape(which_mapper(c.mapper)));
+        println!("  \"prg_rom_size\": {},", c.get_prog_size());
+        println!("  \"chr_rom_size\": {},", c.get_ppu_size());
+        println!("  \"prg_ram_size\": {},", c.get_ram_size());
+        println!("  \"mirroring\": \"{}\",", mirror);
+        println!("  \"battery\": {},", c.has_battery);
+        println!("  \"trainer\": {},", c.has_trainer);
+        println!("  \"region\": \"{}\",", region);
+        println!("  \"title\": {},", title);
+        println!("  \"crc32\": \"{:08x}\",", c.crc32);
+        println!("  \"sha1\": \"{}\"", hash::to_hex(&c.sha1));
+        println!("}}");
+    } else {
+        println!("Format:     {}", format);
+        println!(
+            "Mapper:     {}.{} ({})",
+            c.mapper,
+            c.submapper,
+            which_mapper(c.mapper)
+        );
+        println!("PRG ROM:    {} kB", c.get_prog_size() / 1024);
+        println!("CHR ROM:    {} kB", c.get_ppu_size() / 1024);
+        println!("PRG RAM:    {} kB", c.get_ram_size() / 1024);
+        println!("Mirroring:  {}", mirror);
+        println!("Battery:    {}", c.has_battery);
+        println!("Trainer:    {}", c.has_trainer);
+        println!("Region:     {}", region);
+        println!("Title:      {}", c.title.as_deref().unwrap_or("unknown"));
+        println!("CRC32:      {:08x}", c.crc32);
+        println!("SHA-1:      {}", hash::to_hex(&c.sha1));
+    }
+    Ok(())
+}
+
+fn json_escape(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32).chars().collect(),
+            c => vec![c],
+        })
+        .collect()
+}
+
+fn hashes(m: &ArgMatches) -> IronNesResult<()> {
+    let rom = Rom::open(m.value_of("rom").unwrap())?;
+    let parts: [(&str, &[u8]); 4] = [
+        ("ROM", &[rom.prog_rom.as_slice(), &rom.ppu_rom].concat()),
+        ("PRG", &rom.prog_rom),
+        ("CHR", &rom.ppu_rom),
+        ("File", &rom.file.data),
+    ];
+    for (name, data) in parts.iter() {
+        println!(
+            "{:<5} crc32 {:08x} sha1 {}",
+            name,
+            hash::crc32(data),
+            hash::to_hex(&hash::sha1(data))
+        );
+    }
+    Ok(())
+}
+
+fn split(m: &ArgMatches) -> IronNesResult<()> {
+    let rom = Rom::open(m.value_of("rom").unwrap())?;
+    let dir = match m.value_of("out") {
+        Some(dir) => Path::new(dir).to_path_buf(),
+        None => rom.file.path.with_file_name(""),
+    };
+    let stem = Path::new(&rom.file.name).with_extension("");
+
+    let header = rom.header()?;
+    let mut parts = vec![("hdr", header.as_slice()), ("prg", &rom.prog_rom)];
+    if let Some(trainer) = rom.trainer() {
+        parts.push(("trainer", trainer));
+    }
+    if !rom.ppu_rom.is_empty() {
+        parts.push(("chr", &rom.ppu_rom));
+    }
+
+    for (ext, data) in parts {
+        let path = dir.join(stem.with_extension(ext));
+        info!("Writing {}", path.display());
+        fs::write(&path, data)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+fn merge(m: &ArgMatches) -> IronNesResult<()> {
+    let read = |arg: &str| m.value_of(arg).map(fs::read).transpose();
+    let prog_rom = read("prg")?.unwrap_or_default();
+    let ppu_rom = read("chr")?.unwrap_or_default();
+    let trainer = read("trainer")?;
+
+    let mut cartridge = match read("header")? {
+        Some(header) => Cartridge::from_header(&header)?,
+        None => {
+            let mut cartridge = Cartridge::default();
+            cartridge.region = CartridgeRegion::NTSC;
+            cartridge
+        }
+    };
+    if m.is_present("mapper") {
+        cartridge.mapper = value_t!(m, "mapper", u8).unwrap_or_else(|e| e.exit());
+    }
+    cartridge.mirror = match m.value_of("mirroring") {
+        Some("v") => MirrorDirection::Vertical,
+        Some("4") => MirrorDirection::FourScreen,
+        Some(_) => MirrorDirection::Horizontal,
+        None => cartridge.mirror,
+    };
+    cartridge.has_battery |= m.is_present("battery");
+
+    let nes2 = m.is_present("nes2");
+    let data = cartridge.to_bytes(&prog_rom, &ppu_rom, trainer.as_deref(), nes2)?;
+    fs::write(m.value_of("out").unwrap(), data)?;
+    Ok(())
+}
+
+/**
+ * Writes the ROM back out with a fresh header, NES 2.0 or iNES, or in the
+ * format it's already in
+ */
+fn rewrite(m: &ArgMatches, nes2: Option<bool>) -> IronNesResult<()> {
+    let rom = Rom::open(m.value_of("rom").unwrap())?;
+    let c = &rom.cartridge;
+    let nes2 = nes2.unwrap_or(c.is_nes2);
+
+    let data = c.to_bytes(&rom.prog_rom, &rom.ppu_rom, rom.trainer(), nes2)?;
+    let header = rom.header()?;
+    if data[..Cartridge::NES_FILE_HEADER_SIZE] != *header {
+        info!("Header changed from {:02x?}", header);
+    }
+    fs::write(m.value_of("out").unwrap(), data)?;
+    Ok(())
+}
@@ -11,7 +11,7 @@ mod debugger;
 
 use iron_nes::error::*;
 use iron_nes::nes::cartridge::Cartridge;
-use iron_nes::nes::ppu::{Frame, Ppu};
+use iron_nes::nes::ppu;
 
 fn main() {
     let yaml = clap::load_yaml!("spritemap.yml");
@@ -42,7 +42,7 @@ fn main() {
     CombinedLogger::init(loggers).unwrap();
 
     let (_, _, ppu_vram) = Cartridge::load(rom).unwrap();
-    let mut ppu = Ppu::new(ppu_vram);
+    let frame = ppu::pattern_tables(&ppu_vram);
 
     // SDL STUFF
 
@@ -53,8 +53,8 @@ fn main() {
     let window = video_subsystem
         .window(
             "IronNES",
-            (256.0 * 2.0 * SCALE) as u32,
-            (240.0 * SCALE) as u32,
+            (frame.width() as f32 * SCALE) as u32,
+            (frame.height() as f32 * SCALE) as u32,
         )
         .position_centered()
         .build()
@@ -66,7 +66,11 @@ fn main() {
 
     let creator = canvas.texture_creator();
     let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 256 * 2, 240)
+        .create_texture_target(
+            PixelFormatEnum::RGB24,
+            frame.width() as u32,
+            frame.height() as u32,
+        )
         .unwrap();
 
     let mut timer = sdl_context.timer().unwrap();
@@ -88,8 +92,7 @@ fn main() {
 
         let ticks = timer.ticks() as i32;
 
-        let frame = ppu.render();
-        texture.update(None, &frame.0, 256 * 2 * 3);
+        texture.update(None, &frame.pixels, frame.pitch());
         canvas.clear();
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
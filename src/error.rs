@@ -6,8 +6,30 @@ pub type IronNesResult<T> = std::result::Result<T, IronNesError>;
 pub enum IronNesError {
     #[error("Error reading cartridge contents")]
     CartridgeError,
+    #[error("Not a NES ROM, the header doesn't start with NES\\x1a")]
+    BadMagic,
+    #[error("Header is truncated, expected {expected} bytes, found {found}")]
+    TruncatedHeader { expected: usize, found: usize },
+    #[error("Header byte {byte} has reserved bits set: {value:#04x}")]
+    ReservedBits { byte: usize, value: u8 },
+    #[error("Bad UNIF chunk: {0}")]
+    BadUnifChunk(String),
+    #[error("PRG ROM is truncated, expected {expected} bytes, found {found}")]
+    TruncatedPrg { expected: usize, found: usize },
+    #[error("CHR ROM is truncated, expected {expected} bytes, found {found}")]
+    TruncatedChr { expected: usize, found: usize },
+    #[error("Mapper {0} is not supported")]
+    UnsupportedMapper(u16),
+    #[error("UNIF board {0} is not supported")]
+    UnsupportedBoard(String),
     #[error("MemError: {0}")]
     MemoryError(String),
+    #[error("Patch failed: {0}")]
+    PatchError(String),
+    #[error("Bad palette: {0}")]
+    PaletteError(String),
+    #[error("Bad overscan: {0}, give one number or top,bottom,left,right")]
+    OverscanError(String),
     #[error("Instruction is not supported")]
     IllegalInstruction,
     #[error(transparent)]
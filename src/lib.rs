@@ -1,3 +1,4 @@
 pub mod bitset;
 pub mod error;
+pub mod hash;
 pub mod nes;
@@ -1,8 +1,17 @@
+pub mod apu;
+pub mod archive;
 pub mod cartridge;
 pub mod cpu;
+pub mod disk;
+pub mod mapper;
 pub mod memory;
+pub mod nsf;
+pub mod patch;
 pub mod ppu;
+pub mod timing;
 use log::*;
+use std::fs;
+use std::path::PathBuf;
 
 use crate::error::*;
 
@@ -10,6 +19,18 @@ pub struct IronNes {
     cpu: cpu::Cpu,
     cartridge: cartridge::Cartridge,
     pub mem: memory::Memory,
+    /// Where battery backed cartridge memory is kept between runs
+    save_file: Option<PathBuf>,
+    /// Disk System BIOS, looked for next to the disk image when not set
+    fds_bios: Option<PathBuf>,
+    /// Set when playing an NSF instead of running a game
+    nsf: Option<nsf::NsfPlayback>,
+    /// IPS/UPS/BPS patches, applied in order, instead of any found next to the ROM
+    patches: Vec<PathBuf>,
+    /// Region to run as, instead of the one the cartridge says
+    region: Option<cartridge::CartridgeRegion>,
+    /// Colours to show, instead of those of the region's PPU
+    palette: Option<ppu::Palette>,
 }
 
 impl IronNes {
@@ -19,20 +40,324 @@ impl IronNes {
             cpu: cpu::Cpu::new(),
             cartridge: cartridge::Cartridge::default(),
             mem: memory::Memory::new(),
+            save_file: None,
+            fds_bios: None,
+            nsf: None,
+            patches: Vec::new(),
+            region: None,
+            palette: None,
         }
     }
 
-    pub fn boot(&mut self, cartridge: &str) -> IronNesResult<()> {
-        info!("Loading cartridge {}", cartridge);
-        let (cartridge, prog_rom, ppu_rom) = cartridge::Cartridge::load(cartridge)?;
+    /// Sets where the Disk System BIOS (disksys.rom) is loaded from
+    pub fn set_fds_bios(&mut self, path: &str) {
+        self.fds_bios = Some(PathBuf::from(path));
+    }
+
+    /**
+     * Adds a patch to apply to the ROM on boot. Without any, a rom.ips, rom.ups
+     * or rom.bps next to the ROM is applied.
+     */
+    pub fn add_patch(&mut self, path: &str) {
+        self.patches.push(PathBuf::from(path));
+    }
+
+    /**
+     * Runs games as a console from another region, for PAL games with headers
+     * that say NTSC and the other way round. Takes effect on the next boot.
+     */
+    pub fn set_region(&mut self, region: cartridge::CartridgeRegion) {
+        self.region = Some(region);
+    }
+
+    /**
+     * Sets the colours the PPU's output is shown in, instead of those of the
+     * region's PPU
+     */
+    pub fn set_palette(&mut self, palette: ppu::Palette) {
+        self.palette = Some(palette.clone());
+        self.mem.ppu_mut().set_palette(palette);
+    }
+
+    /// Timing of the console being emulated
+    pub fn timing(&self) -> &timing::Timing {
+        self.mem.ppu().timing()
+    }
+
+    /**
+     * Sets the console up for the cartridge's region, or the one asked for,
+     * with its PPU's colours unless a palette was asked for
+     */
+    fn set_timing(&mut self) {
+        let region = self.region.unwrap_or(self.cartridge.region);
+        self.mem.set_timing(timing::Timing::from_region(region));
+
+        let palette = match &self.palette {
+            Some(palette) => palette.clone(),
+            None => ppu::Palette::from_region(region),
+        };
+        self.mem.ppu_mut().set_palette(palette);
+    }
+
+    /// Reads the ROM image, out of its archive if need be, and patches it
+    fn read_image(&self, rom: &str) -> IronNesResult<archive::RomFile> {
+        let mut file = archive::RomFile::open(rom)?;
+
+        let patches = match self.patches.is_empty() {
+            false => self.patches.clone(),
+            true => patch::PatchFormat::EXTENSIONS
+                .iter()
+                .map(|ext| file.path.with_extension(ext))
+                .filter(|p| p.exists())
+                .collect(),
+        };
+
+        for path in patches {
+            info!("Applying patch {}", path.display());
+            file.data = patch::apply(&fs::read(&path)?, &file.data)?;
+        }
+        Ok(file)
+    }
+
+    /**
+     * Boots a ROM, disk or NSF file, which can be inside a .zip or .gz. Saves
+     * are kept next to the file, or the archive.
+     */
+    pub fn boot(&mut self, rom: &str) -> IronNesResult<()> {
+        let file = self.read_image(rom)?;
+        if file.has_extension("nsf") || file.has_extension("nsfe") {
+            return self.load_nsf(&file);
+        }
+        self.nsf = None;
+
+        let cartridge = match file.has_extension("fds") {
+            true => self.load_disk(&file)?,
+            false => {
+                info!("Loading cartridge {}", file.name);
+                self.load_cartridge(&file.data)?
+            }
+        };
+
+        // The board knows whether it has anything worth saving, which isn't
+        // always PRG RAM with the header's battery bit set
+        self.save_file = Some(file.path.with_extension("sav"));
         self.cartridge = cartridge;
-        self.mem.load_rom(&prog_rom)?;
+        self.load_save()?;
 
+        self.set_timing();
         self.reset()
     }
 
+    /**
+     * Boots an iNES or UNIF image already in memory. There's no file to keep
+     * saves in, so battery backed memory starts empty and isn't saved.
+     */
+    pub fn boot_bytes(&mut self, image: &[u8]) -> IronNesResult<()> {
+        self.nsf = None;
+        self.save_file = None;
+        self.cartridge = self.load_cartridge(image)?;
+        self.set_timing();
+        self.reset()
+    }
+
+    /// Plugs in the board for an iNES or UNIF image
+    fn load_cartridge(&mut self, image: &[u8]) -> IronNesResult<cartridge::Cartridge> {
+        let (cartridge, prog_rom, ppu_rom) = cartridge::Cartridge::from_bytes(image)?;
+        let mapper = mapper::from_cartridge(&cartridge, prog_rom, ppu_rom)?;
+        self.mem.load_mapper(mapper);
+        Ok(cartridge)
+    }
+
+    /**
+     * Plugs in the Disk System RAM adapter with the disk's first side in the
+     * drive. Writes to the disk go to the .sav file, as a whole disk image.
+     */
+    fn load_disk(&mut self, file: &archive::RomFile) -> IronNesResult<cartridge::Cartridge> {
+        info!("Loading disk {}", file.name);
+        let image = disk::FdsImage::from_bytes(&file.data)?;
+
+        let bios_path = match &self.fds_bios {
+            Some(path) => path.clone(),
+            None => file.path.with_file_name("disksys.rom"),
+        };
+        if !bios_path.exists() {
+            error!("Disk System BIOS '{}' not found", bios_path.display());
+            return Err(IronNesError::CartridgeError);
+        }
+
+        let bios = fs::read(&bios_path)?;
+        if bios.len() != mapper::Fds::BIOS_SIZE {
+            error!("Disk System BIOS should be 8kB, got {} bytes", bios.len());
+            return Err(IronNesError::CartridgeError);
+        }
+
+        self.mem.load_mapper(Box::new(mapper::Fds::new(bios, image)));
+
+        let mut cartridge = cartridge::Cartridge::default();
+        cartridge.mapper = 20;
+        cartridge.region = cartridge::CartridgeRegion::NTSC;
+        Ok(cartridge)
+    }
+
+    /// Plugs in an NSF player for the tune and starts its first song
+    fn load_nsf(&mut self, file: &archive::RomFile) -> IronNesResult<()> {
+        info!("Loading NSF {}", file.name);
+        let nsf = nsf::Nsf::from_bytes(&file.data)?;
+        let song = nsf.start_song;
+
+        self.save_file = None;
+        self.cartridge = cartridge::Cartridge::default();
+        self.cartridge.region = match nsf.pal {
+            true => cartridge::CartridgeRegion::PAL,
+            false => cartridge::CartridgeRegion::NTSC,
+        };
+        self.set_timing();
+        self.nsf = Some(nsf::NsfPlayback::new(nsf, self.timing()));
+        self.start_song(song)
+    }
+
+    /// The NSF being played, if any
+    pub fn nsf(&self) -> Option<&nsf::Nsf> {
+        self.nsf.as_ref().map(|p| &p.nsf)
+    }
+
+    /// The NSF song playing, counting from 0
+    pub fn song(&self) -> Option<u8> {
+        self.nsf.as_ref().map(|p| p.song)
+    }
+
+    /**
+     * Starts one of the NSF's songs, counting from 0. RAM is cleared and the
+     * APU silenced, then the init routine gets the song in A and the region
+     * in X, and the play routine follows at the tune's play rate.
+     */
+    pub fn start_song(&mut self, song: u8) -> IronNesResult<()> {
+        let playback = match self.nsf.as_mut() {
+            Some(playback) if song < playback.nsf.songs => playback,
+            Some(_) => {
+                error!("NSF has no song {}", song + 1);
+                return Err(IronNesError::CartridgeError);
+            }
+            None => {
+                error!("No NSF is loaded");
+                return Err(IronNesError::CartridgeError);
+            }
+        };
+        info!("Starting NSF song {}", song + 1);
+
+        playback.song = song;
+        playback.next_play = self.cpu.cycle as f64 + playback.period;
+        let mapper = mapper::NsfMapper::new(&playback.nsf);
+        let (init, region) = (playback.nsf.init_addr, playback.pal as u8);
+        self.mem.load_mapper(Box::new(mapper));
+
+        for addr in 0x0000..0x0800 {
+            self.mem.store(addr, 0)?;
+        }
+        for addr in 0x4000..=0x4013 {
+            self.mem.store(addr, 0)?;
+        }
+        self.mem.store(0x4015, 0x0f)?;
+        self.mem.store(0x4017, 0x40)?;
+
+        self.call(init, song, region)
+    }
+
+    /// Calls a subroutine of an NSF, which returns to the idle address
+    fn call(&mut self, addr: memory::Addr, a: u8, x: u8) -> IronNesResult<()> {
+        let regs = self.cpu.registers_mut();
+        regs.a = a;
+        regs.x = x;
+        regs.sp = 0xfd;
+        regs.set_flag(cpu::Flags::I, true);
+        self.mem
+            .stack_push_addr(&mut regs.sp, nsf::NsfPlayback::RETURN_ADDR - 1)?;
+        regs.pc = addr;
+        Ok(())
+    }
+
+    /**
+     * Calls the NSF's play routine when it's due, and idles between calls.
+     * Returns true when it has done the step, false when the CPU is running
+     * the tune's code.
+     */
+    fn step_nsf(&mut self) -> IronNesResult<bool> {
+        let playback = match self.nsf.as_mut() {
+            Some(playback) => playback,
+            None => return Ok(false),
+        };
+        let cycle = self.cpu.cycle;
+        let idle = self.cpu.get_registers().pc == nsf::NsfPlayback::RETURN_ADDR;
+
+        if cycle as f64 >= playback.next_play {
+            playback.next_play += playback.period;
+            // A routine still running when the next call is due carries on,
+            // the call is skipped
+            if idle {
+                let play = playback.nsf.play_addr;
+                self.call(play, 0, 0)?;
+            } else {
+                trace!("NSF skipped a play call");
+            }
+            return Ok(false);
+        }
+
+        if !idle {
+            return Ok(false);
+        }
+        let due = (playback.next_play - cycle as f64).ceil() as usize;
+        let cycles = due.clamp(1, nsf::NsfPlayback::IDLE_CYCLES);
+        self.cpu.cycle += cycles;
+        self.mem.tick(cycles);
+        Ok(true)
+    }
+
+    /// Number of sides of the disk in the drive, 0 for cartridges
+    pub fn disk_sides(&self) -> usize {
+        self.mem.mapper().disk_sides()
+    }
+
+    pub fn disk_side(&self) -> Option<usize> {
+        self.mem.mapper().disk_side()
+    }
+
+    /// Flips or swaps the disk, `None` ejects it
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        info!("Inserting disk side {:?}", side);
+        self.mem.mapper_mut().insert_disk(side);
+    }
+
+    fn load_save(&mut self) -> IronNesResult<()> {
+        if let Some(path) = self.save_file.as_ref().filter(|p| p.exists()) {
+            info!("Loading save {}", path.display());
+            let data = fs::read(path)?;
+            self.mem.mapper_mut().restore_battery_ram(&data);
+        }
+        Ok(())
+    }
+
+    /**
+     * Writes battery backed cartridge memory next to the ROM, as a .sav file
+     */
+    pub fn save(&self) -> IronNesResult<()> {
+        let data = self.mem.mapper().battery_ram();
+        if let (Some(path), Some(data)) = (&self.save_file, data) {
+            info!("Saving {}", path.display());
+            fs::write(path, data)?;
+        }
+        Ok(())
+    }
+
+    /// Resets the CPU, or restarts the song when playing an NSF
     pub fn reset(&mut self) -> IronNesResult<()> {
-        self.cpu.reset(&self.mem)
+        match self.song() {
+            Some(song) => self.start_song(song),
+            None => {
+                self.cpu.reset(&self.mem)?;
+                self.mem.tick(self.cpu.cycle);
+                Ok(())
+            }
+        }
     }
 
     pub fn run(&mut self) -> IronNesResult<()> {
@@ -42,15 +367,62 @@ impl IronNes {
     }
 
     pub fn step(&mut self) -> IronNesResult<()> {
+        if self.step_nsf()? {
+            return Ok(());
+        }
+
         self.log_state()?;
+        let cycles = self.cpu.cycle;
         self.cpu.step(&mut self.mem)?;
+        let stall = self.mem.take_stall();
+        if stall > 0 {
+            self.cpu.cycle += stall + (self.cpu.cycle % 2);
+        }
+        // Whatever the instruction's PPU accesses already ran is not run twice
+        let caught_up = self.mem.take_caught_up();
+        self.mem.tick(self.cpu.cycle - cycles - caught_up);
+
+        let cycles = self.cpu.cycle;
+        if self.mem.nmi() {
+            self.cpu.nmi(&mut self.mem)?;
+        } else if self.mem.irq() {
+            self.cpu.irq(&mut self.mem)?;
+        }
+        self.mem.tick(self.cpu.cycle - cycles);
         Ok(())
     }
 
+    /// Runs until the PPU finishes a frame, and returns it
+    pub fn step_frame(&mut self) -> IronNesResult<&ppu::Frame> {
+        let frame = self.mem.ppu().frame_count();
+        while self.mem.ppu().frame_count() == frame {
+            self.step()?;
+        }
+        Ok(self.mem.ppu().frame())
+    }
+
+    /// The PPU, for where it is in the frame
+    pub fn ppu(&self) -> &ppu::Ppu {
+        self.mem.ppu()
+    }
+
+    /// The last whole frame the PPU drew
+    pub fn frame(&self) -> &ppu::Frame {
+        self.mem.ppu().frame()
+    }
+
     pub fn get_cycles(&self) -> usize {
         self.cpu.cycle
     }
 
+    /**
+     * Current audio level, the 2A03 channels in the range 0.0-1.0 plus any
+     * cartridge expansion audio.
+     */
+    pub fn audio_sample(&self) -> f32 {
+        self.mem.audio_output()
+    }
+
     pub fn peek(&self, addr: memory::Addr) -> IronNesResult<u8> {
         self.mem.load(addr)
     }
@@ -72,3 +444,82 @@ impl IronNes {
         &self.cpu.get_registers()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_save_round_trip() {
+        // Namco 163 with a battery, 32kB of PRG and 8kB of CHR
+        let mut rom = cartridge::Cartridge::CARTRIDGE_HEADER.to_vec();
+        rom.extend([2, 1, 0x32, 0x10]);
+        rom.resize(cartridge::Cartridge::NES_FILE_HEADER_SIZE, 0);
+        rom.resize(rom.len() + 0x8000 + 0x2000, 0);
+
+        let dir = env::temp_dir().join(format!("ironnes-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.nes");
+        fs::write(&path, rom).unwrap();
+
+        // A byte of the chip's internal RAM, through its address port
+        let mut nes = IronNes::new();
+        nes.boot(path.to_str().unwrap()).unwrap();
+        nes.mem.store(0xf800, 0x23).unwrap();
+        nes.mem.store(0x4800, 0x42).unwrap();
+        nes.save().unwrap();
+        assert!(dir.join("game.sav").exists());
+
+        let mut nes = IronNes::new();
+        nes.boot(path.to_str().unwrap()).unwrap();
+        nes.mem.store(0xf800, 0x23).unwrap();
+        assert_eq!(0x42, nes.mem.load(0x4800).unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /**
+     * Runs from reset until an LDA $2002 starting on CPU cycle `start`, then
+     * another straight after it, and gives back what both read
+     */
+    fn read_status_from(start: usize) -> (u8, u8) {
+        let mut prg = vec![0xea; 0x8000];
+        prg[0x7ffc..0x7ffe].copy_from_slice(&[0x00, 0x80]);
+
+        // The reset takes 7 cycles, NOPs 2 and an LDA from zero page 3
+        let mut pc = 0;
+        let mut cycle = 7;
+        if (start - cycle) % 2 == 1 {
+            prg[..2].copy_from_slice(&[0xa5, 0x00]);
+            pc += 2;
+            cycle += 3;
+        }
+        pc += (start - cycle) / 2;
+        prg[pc..pc + 6].copy_from_slice(&[0xad, 0x02, 0x20, 0xad, 0x02, 0x20]);
+
+        let mut nes = IronNes::new();
+        nes.mem.load_rom(&prg).unwrap();
+        nes.reset().unwrap();
+        while nes.get_cpu_registers().pc != 0x8000 + pc as u16 {
+            nes.step().unwrap();
+        }
+        assert_eq!(start, nes.cpu.cycle);
+
+        nes.step().unwrap();
+        let first = nes.get_cpu_registers().a;
+        nes.step().unwrap();
+        (first, nes.get_cpu_registers().a)
+    }
+
+    #[test]
+    fn test_status_read_mid_instruction() {
+        // Vblank is set on dot 1 of scanline 241, dot 82182 of the frame. The
+        // LDA reads on its 4th cycle, so one starting on cycle 27391 reads
+        // with the PPU on that very dot, which stops the flag being set.
+        assert_eq!((0x00, 0x00), read_status_from(27391));
+
+        // A cycle later it's been set and the read clears it
+        assert_eq!((0x80, 0x00), read_status_from(27392));
+    }
+}
@@ -0,0 +1,791 @@
+use crate::nes::cartridge::CartridgeRegion;
+use crate::nes::mapper::Mapper;
+use crate::nes::memory::Addr;
+
+use std::cell::Cell;
+
+/**
+ * The 2A03's audio processing unit: two pulse channels, a triangle, a noise
+ * channel and the delta modulation channel (DMC), stepped along by the frame
+ * counter.
+ *
+ *   $4000-$4003  pulse 1: DDLC VVVV  EPPP NSSS  period low  LLLL LHHH
+ *   $4004-$4007  pulse 2, as pulse 1
+ *   $4008-$400B  triangle: CRRR RRRR  unused  period low  LLLL LHHH
+ *   $400C-$400F  noise: --LC VVVV  unused  M--- PPPP  LLLL L---
+ *   $4010-$4013  DMC: IL-- RRRR  direct load  sample address  sample length
+ *   $4015        channel enables, reads back the length counters and IRQs
+ *   $4017        frame counter: MI-- ----  five step mode, IRQ inhibit
+ *
+ * D duty, L length counter halt / length, C constant volume, V volume,
+ * E sweep enable, P period, N negate, S shift, M mode, R rate.
+ *
+ * The DMC fetches its samples from the cartridge without stalling the CPU.
+ *
+ * PAL's 2A07 counts its slower CPU clock with its own noise, DMC and frame
+ * counter periods so it sounds at the same pitch. Dendy keeps NTSC's.
+ */
+pub struct Apu {
+    pulse: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame: FrameCounter,
+    /// Pulse timers step every other CPU cycle
+    odd_cycle: bool,
+}
+
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[rustfmt::skip]
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Noise periods in CPU cycles
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// DMC periods in CPU cycles
+const DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+const PAL_DMC_PERIODS: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, v: u8) {
+        self.looping = (v & 0x20) != 0;
+        self.constant = (v & 0x10) != 0;
+        self.volume = v & 0x0f;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        match self.constant {
+            true => self.volume,
+            false => self.decay,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    count: u8,
+}
+
+impl LengthCounter {
+    fn load(&mut self, v: u8) {
+        if self.enabled {
+            self.count = LENGTH_TABLE[(v >> 3) as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.count = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.count > 0 {
+            self.count -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.count > 0
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    /// Pulse 1 negates its sweep with ones' complement, so one lower
+    ones_complement: bool,
+    envelope: Envelope,
+    length: LengthCounter,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: Addr, v: u8) {
+        match reg {
+            0 => {
+                self.duty = v >> 6;
+                self.length.halt = (v & 0x20) != 0;
+                self.envelope.write(v);
+            }
+            1 => {
+                self.sweep_enabled = (v & 0x80) != 0;
+                self.sweep_period = (v >> 4) & 0b111;
+                self.sweep_negate = (v & 0x08) != 0;
+                self.sweep_shift = v & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | v as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (((v & 0b111) as u16) << 8);
+                self.length.load(v);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn target_period(&self) -> i32 {
+        let change = (self.period >> self.sweep_shift) as i32;
+        match (self.sweep_negate, self.ones_complement) {
+            (false, _) => self.period as i32 + change,
+            (true, false) => self.period as i32 - change,
+            (true, true) => self.period as i32 - change - 1,
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x7ff
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period().max(0) as u16;
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        let high = DUTY_TABLE[self.duty as usize][self.step as usize] != 0;
+        match high && self.length.active() && !self.muted() {
+            true => self.envelope.output(),
+            false => 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    length: LengthCounter,
+    /// Also halts the length counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, reg: Addr, v: u8) {
+        match reg {
+            0 => {
+                self.control = (v & 0x80) != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = v & 0x7f;
+            }
+            1 => (),
+            2 => self.period = (self.period & 0x0700) | v as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (((v & 0b111) as u16) << 8);
+                self.length.load(v);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 31;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// Holds the last step when silenced, like the real thing
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+}
+
+struct Noise {
+    envelope: Envelope,
+    length: LengthCounter,
+    /// Short mode, which taps bit 6 instead of bit 1
+    short: bool,
+    periods: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+    shift: u16,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            short: false,
+            periods: &NOISE_PERIODS,
+            period: NOISE_PERIODS[0],
+            timer: 0,
+            shift: 1,
+        }
+    }
+}
+
+impl Noise {
+    fn write(&mut self, reg: Addr, v: u8) {
+        match reg {
+            0 => {
+                self.length.halt = (v & 0x20) != 0;
+                self.envelope.write(v);
+            }
+            1 => (),
+            2 => {
+                self.short = (v & 0x80) != 0;
+                self.period = self.periods[(v & 0x0f) as usize];
+            }
+            _ => {
+                self.length.load(v);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period - 1;
+        let tap = if self.short { 6 } else { 1 };
+        let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    fn output(&self) -> u8 {
+        match (self.shift & 1) == 0 && self.length.active() {
+            true => self.envelope.output(),
+            false => 0,
+        }
+    }
+}
+
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    periods: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+    level: u8,
+
+    sample_address: Addr,
+    sample_length: u16,
+    address: Addr,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    shift: u8,
+    bits_remaining: u8,
+    silent: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            periods: &DMC_PERIODS,
+            period: DMC_PERIODS[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            address: 0xc000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silent: true,
+        }
+    }
+}
+
+impl Dmc {
+    fn write(&mut self, reg: Addr, v: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = (v & 0x80) != 0;
+                self.irq &= self.irq_enabled;
+                self.looping = (v & 0x40) != 0;
+                self.period = self.periods[(v & 0x0f) as usize];
+            }
+            1 => self.level = v & 0x7f,
+            2 => self.sample_address = 0xc000 | ((v as Addr) << 6),
+            _ => self.sample_length = ((v as u16) << 4) | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fetch(&mut self, mapper: &dyn Mapper) {
+        if self.buffer.is_some() || self.bytes_remaining == 0 {
+            return;
+        }
+
+        self.buffer = Some(mapper.load_prg(self.address));
+        self.address = match self.address {
+            0xffff => 0x8000,
+            addr => addr + 1,
+        };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self, mapper: &dyn Mapper) {
+        self.fetch(mapper);
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silent {
+            match self.shift & 1 {
+                1 if self.level <= 125 => self.level += 2,
+                0 if self.level >= 2 => self.level -= 2,
+                _ => (),
+            }
+            self.shift >>= 1;
+        }
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(sample) => {
+                    self.shift = sample;
+                    self.silent = false;
+                }
+                None => self.silent = true,
+            }
+        }
+    }
+}
+
+/// What a step of the frame counter clocks
+#[derive(Debug, PartialEq)]
+enum FrameStep {
+    None,
+    /// Envelopes and the triangle's linear counter
+    Quarter,
+    /// Quarter frame, plus the length counters and sweeps
+    Half,
+}
+
+struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    /// Cleared by reading $4015
+    irq: Cell<bool>,
+    cycle: usize,
+    steps: [usize; 4],
+    five_step_end: usize,
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self {
+            five_step: false,
+            irq_inhibit: false,
+            irq: Cell::new(false),
+            cycle: 0,
+            steps: Self::STEPS,
+            five_step_end: Self::FIVE_STEP_END,
+        }
+    }
+}
+
+impl FrameCounter {
+    /// Frame counter steps, in CPU cycles since the sequence started
+    const STEPS: [usize; 4] = [7457, 14913, 22371, 29829];
+    const FIVE_STEP_END: usize = 37281;
+    const PAL_STEPS: [usize; 4] = [8313, 16627, 24939, 33253];
+    const PAL_FIVE_STEP_END: usize = 41565;
+
+    fn write(&mut self, v: u8) -> FrameStep {
+        self.five_step = (v & 0x80) != 0;
+        self.irq_inhibit = (v & 0x40) != 0;
+        if self.irq_inhibit {
+            self.irq.set(false);
+        }
+
+        // Five step mode clocks everything straight away
+        self.cycle = 0;
+        match self.five_step {
+            true => FrameStep::Half,
+            false => FrameStep::None,
+        }
+    }
+
+    fn clock(&mut self) -> FrameStep {
+        self.cycle += 1;
+        let end = match self.five_step {
+            true => self.five_step_end,
+            false => self.steps[3],
+        };
+
+        match self.cycle {
+            c if c == self.steps[0] || c == self.steps[2] => FrameStep::Quarter,
+            c if c == self.steps[1] => FrameStep::Half,
+            c if c == end => {
+                if !self.five_step && !self.irq_inhibit {
+                    self.irq.set(true);
+                }
+                self.cycle = 0;
+                FrameStep::Half
+            }
+            _ => FrameStep::None,
+        }
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        let mut pulse: [Pulse; 2] = Default::default();
+        pulse[0].ones_complement = true;
+        Self {
+            pulse,
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame: FrameCounter::default(),
+            odd_cycle: false,
+        }
+    }
+
+    /// Switches to the periods of the region's APU
+    pub fn set_region(&mut self, region: CartridgeRegion) {
+        let is_pal = region == CartridgeRegion::PAL;
+        let (noise, dmc, steps, five_step_end) = match is_pal {
+            true => (
+                &PAL_NOISE_PERIODS,
+                &PAL_DMC_PERIODS,
+                FrameCounter::PAL_STEPS,
+                FrameCounter::PAL_FIVE_STEP_END,
+            ),
+            false => (
+                &NOISE_PERIODS,
+                &DMC_PERIODS,
+                FrameCounter::STEPS,
+                FrameCounter::FIVE_STEP_END,
+            ),
+        };
+        self.noise.periods = noise;
+        self.dmc.periods = dmc;
+        self.frame.steps = steps;
+        self.frame.five_step_end = five_step_end;
+    }
+
+    /// Reads $4015, which acknowledges the frame counter IRQ
+    pub fn status(&self) -> u8 {
+        let status = (self.pulse[0].length.active() as u8)
+            | (self.pulse[1].length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | ((self.dmc.bytes_remaining > 0) as u8) << 4
+            | (self.frame.irq.get() as u8) << 6
+            | (self.dmc.irq as u8) << 7;
+        self.frame.irq.set(false);
+        status
+    }
+
+    pub fn store(&mut self, addr: Addr, v: u8) {
+        let reg = addr & 0b11;
+        match addr {
+            0x4000..=0x4003 => self.pulse[0].write(reg, v),
+            0x4004..=0x4007 => self.pulse[1].write(reg, v),
+            0x4008..=0x400b => self.triangle.write(reg, v),
+            0x400c..=0x400f => self.noise.write(reg, v),
+            0x4010..=0x4013 => self.dmc.write(reg, v),
+            0x4015 => {
+                self.pulse[0].length.set_enabled((v & 0x01) != 0);
+                self.pulse[1].length.set_enabled((v & 0x02) != 0);
+                self.triangle.length.set_enabled((v & 0x04) != 0);
+                self.noise.length.set_enabled((v & 0x08) != 0);
+                self.dmc.irq = false;
+                match (v & 0x10) != 0 {
+                    true if self.dmc.bytes_remaining == 0 => self.dmc.restart(),
+                    true => (),
+                    false => self.dmc.bytes_remaining = 0,
+                }
+            }
+            0x4017 => {
+                let step = self.frame.write(v);
+                self.clock_frame(step);
+            }
+            _ => (),
+        }
+    }
+
+    /// Advances the APU by a number of CPU cycles, the DMC reads its samples
+    /// off the cartridge
+    pub fn tick(&mut self, cycles: usize, mapper: &dyn Mapper) {
+        for _ in 0..cycles {
+            self.odd_cycle = !self.odd_cycle;
+            if self.odd_cycle {
+                self.pulse.iter_mut().for_each(|p| p.clock_timer());
+            }
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer(mapper);
+
+            let step = self.frame.clock();
+            self.clock_frame(step);
+        }
+    }
+
+    fn clock_frame(&mut self, step: FrameStep) {
+        if step == FrameStep::None {
+            return;
+        }
+
+        self.pulse.iter_mut().for_each(|p| p.envelope.clock());
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+
+        if step == FrameStep::Half {
+            for pulse in self.pulse.iter_mut() {
+                pulse.length.clock();
+                pulse.clock_sweep();
+            }
+            self.triangle.length.clock();
+            self.noise.length.clock();
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame.irq.get() || self.dmc.irq
+    }
+
+    /// Mixes the channels the way the 2A03's DACs do, into 0.0-1.0
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let pulse = match pulse > 0.0 {
+            true => 95.88 / (8128.0 / pulse + 100.0),
+            false => 0.0,
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.level as f32 / 22638.0;
+        let tnd = match tnd > 0.0 {
+            true => 159.79 / (1.0 / tnd + 100.0),
+            false => 0.0,
+        };
+
+        pulse + tnd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::Nrom;
+
+    fn nrom() -> Nrom {
+        Nrom::new(vec![0x55; 0x8000], Vec::new(), Default::default())
+    }
+
+    #[test]
+    fn test_apu_pulse() {
+        let mut apu = Apu::new();
+        let mapper = nrom();
+
+        apu.store(0x4015, 0x01);
+        apu.store(0x4000, 0b1011_1111);
+        apu.store(0x4002, 0x00);
+        apu.store(0x4003, 0x01);
+        assert_eq!(0x01, apu.status() & 0x0f);
+
+        // 50% duty, constant volume: high for half of the 16 steps it takes
+        // a period of $100 to go around
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            apu.tick(0x101 * 2, &mapper);
+            levels.push(apu.pulse[0].output());
+        }
+        assert_eq!(4, levels.iter().filter(|&&l| l == 15).count());
+        assert!(apu.output() >= 0.0);
+
+        // Disabling the channel clears its length counter
+        apu.store(0x4015, 0x00);
+        assert_eq!(0, apu.status() & 0x0f);
+        assert_eq!(0, apu.pulse[0].output());
+    }
+
+    #[test]
+    fn test_apu_frame_irq() {
+        let mut apu = Apu::new();
+        let mapper = nrom();
+
+        apu.tick(FrameCounter::STEPS[3] - 1, &mapper);
+        assert!(!apu.irq());
+        apu.tick(1, &mapper);
+        assert!(apu.irq());
+        assert_eq!(0x40, apu.status() & 0x40);
+        assert!(!apu.irq());
+
+        // Inhibited, and no IRQ in five step mode
+        apu.store(0x4017, 0x40);
+        apu.tick(FrameCounter::STEPS[3] * 2, &mapper);
+        assert!(!apu.irq());
+        apu.store(0x4017, 0x80);
+        apu.tick(FrameCounter::FIVE_STEP_END * 2, &mapper);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_apu_dmc() {
+        let mut apu = Apu::new();
+        let mapper = nrom();
+
+        // One byte sample at $C000, with an IRQ at the end
+        apu.store(0x4010, 0x8f);
+        apu.store(0x4011, 0x40);
+        apu.store(0x4012, 0x00);
+        apu.store(0x4013, 0x00);
+        apu.store(0x4015, 0x10);
+        assert_eq!(0x10, apu.status() & 0x10);
+
+        apu.tick(1, &mapper);
+        assert!(apu.irq());
+        assert_eq!(0x80, apu.status() & 0x90);
+
+        // $55 alternates up and down by 2 each bit once it's in the shifter
+        apu.tick(54 * 16, &mapper);
+        assert!((0x3e..=0x42).contains(&apu.dmc.level));
+
+        apu.store(0x4015, 0x00);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_apu_pal() {
+        let mut apu = Apu::new();
+        let mapper = nrom();
+        apu.set_region(CartridgeRegion::PAL);
+
+        apu.store(0x400e, 0x0f);
+        assert_eq!(3778, apu.noise.period);
+
+        apu.tick(FrameCounter::STEPS[3], &mapper);
+        assert!(!apu.irq());
+        apu.tick(FrameCounter::PAL_STEPS[3] - FrameCounter::STEPS[3], &mapper);
+        assert!(apu.irq());
+    }
+}
@@ -0,0 +1,195 @@
+//! Reads ROM images that may be inside a .zip or .gz archive.
+
+use crate::error::*;
+
+use flate2::read::GzDecoder;
+use log::*;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files that can be booted, looked for inside zip archives
+pub const ROM_EXTENSIONS: [&str; 6] = ["nes", "unf", "unif", "fds", "nsf", "nsfe"];
+
+/// Most a zip entry's declared size is trusted for, when reserving memory for it
+const ZIP_SIZE_HINT_LIMIT: usize = 16 * 1024 * 1024;
+
+/**
+ * A ROM image read from disk. `name` is the file name of the image itself,
+ * which is the entry's name when it came out of an archive, while `path` is
+ * the file on disk that held it.
+ */
+pub struct RomFile {
+    pub path: PathBuf,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl RomFile {
+    /**
+     * Reads a ROM, a .gz of one, or an entry of a .zip. A zip entry can be
+     * chosen with `archive.zip#entry.nes`, otherwise the first ROM in the
+     * archive is used.
+     */
+    pub fn open(rom: &str) -> IronNesResult<Self> {
+        let (path, entry) = match rom.rsplit_once('#') {
+            Some((path, entry)) if !Path::new(rom).exists() => (Path::new(path), Some(entry)),
+            _ => (Path::new(rom), None),
+        };
+        let has_extension = |ext: &str| {
+            path.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+        };
+
+        let (name, data) = match (has_extension("zip"), has_extension("gz")) {
+            (true, _) => read_zip(path, entry)?,
+            (false, true) => read_gz(path)?,
+            (false, false) => (file_name(path), fs::read(path)?),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            name,
+            data,
+        })
+    }
+
+    /// Whether the image's own name ends with the extension, ignoring case
+    pub fn has_extension(&self, ext: &str) -> bool {
+        Path::new(&self.name)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_zip(path: &Path, entry: Option<&str>) -> IronNesResult<(String, Vec<u8>)> {
+    let zip_error = |e: zip::result::ZipError| {
+        error!("Can't read zip archive {}: {}", path.display(), e);
+        IronNesError::CartridgeError
+    };
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(zip_error)?;
+
+    let is_rom = |name: &str| {
+        Path::new(name)
+            .extension()
+            .is_some_and(|e| ROM_EXTENSIONS.iter().any(|ext| e.eq_ignore_ascii_case(ext)))
+    };
+    let name = match entry {
+        Some(entry) => entry.to_string(),
+        None => {
+            let mut names = Vec::new();
+            for i in 0..archive.len() {
+                names.push(archive.by_index(i).map_err(zip_error)?.name().to_string());
+            }
+            match names.into_iter().find(|n| is_rom(n)) {
+                Some(name) => name,
+                None => {
+                    error!("No ROM found in {}", path.display());
+                    return Err(IronNesError::CartridgeError);
+                }
+            }
+        }
+    };
+
+    info!("Reading {} from {}", name, path.display());
+    let mut file = archive.by_name(&name).map_err(zip_error)?;
+
+    // The header's size is only a hint, a corrupt one mustn't exhaust memory
+    let size_hint = (file.size() as usize).min(ZIP_SIZE_HINT_LIMIT);
+    let mut data = Vec::with_capacity(size_hint);
+    file.read_to_end(&mut data).map_err(|e| {
+        error!("Can't read {} from {}: {}", name, path.display(), e);
+        IronNesError::CartridgeError
+    })?;
+    Ok((name, data))
+}
+
+/// A .gz holds a single file, named by the header or else by dropping the .gz
+fn read_gz(path: &Path) -> IronNesResult<(String, Vec<u8>)> {
+    let mut decoder = GzDecoder::new(File::open(path)?);
+    let mut data = Vec::new();
+    decoder.read_to_end(&mut data)?;
+
+    let name = decoder
+        .header()
+        .and_then(|h| h.filename())
+        .map(|n| String::from_utf8_lossy(n).into_owned())
+        .unwrap_or_else(|| file_name(&path.with_extension("")));
+    Ok((name, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::io::Write;
+    use zip::write::FileOptions;
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ironnes-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_open_zip() {
+        let path = temp_file("roms.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in [("readme.txt", b"hi"), ("a.nes", b"aa"), ("b.nes", b"bb")] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let rom = RomFile::open(path.to_str().unwrap()).unwrap();
+        assert_eq!("a.nes", rom.name);
+        assert_eq!(b"aa", rom.data.as_slice());
+        assert_eq!(path, rom.path);
+
+        let rom = RomFile::open(&format!("{}#b.nes", path.display())).unwrap();
+        assert_eq!("b.nes", rom.name);
+        assert_eq!(b"bb", rom.data.as_slice());
+
+        assert!(RomFile::open(&format!("{}#c.nes", path.display())).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_open_corrupt_zip() {
+        let path = temp_file("corrupt.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("a.nes", options).unwrap();
+        zip.write_all(b"NES\x1a").unwrap();
+        zip.finish().unwrap();
+
+        // Damage the entry so its checksum no longer matches
+        let mut bytes = fs::read(&path).unwrap();
+        let offset = bytes.windows(4).position(|w| w == b"NES\x1a").unwrap();
+        bytes[offset] = b'X';
+        fs::write(&path, bytes).unwrap();
+
+        let result = RomFile::open(path.to_str().unwrap());
+        assert!(matches!(result, Err(IronNesError::CartridgeError)));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_open_gz() {
+        let path = temp_file("game.nes.gz");
+        let mut gz = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        gz.write_all(b"NES\x1a").unwrap();
+        gz.finish().unwrap();
+
+        let rom = RomFile::open(path.to_str().unwrap()).unwrap();
+        assert!(rom.has_extension("nes"));
+        assert_eq!(b"NES\x1a", rom.data.as_slice());
+        fs::remove_file(path).unwrap();
+    }
+}
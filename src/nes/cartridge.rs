@@ -1,8 +1,12 @@
+mod database;
+mod ines;
+mod unif;
+
 use crate::error::*;
 
 use log::*;
 use std::fmt;
-use std::fs::File;
+use std::fs;
 use std::io::Read;
 use std::path::Path;
 
@@ -15,7 +19,16 @@ pub struct Cartridge {
     pub has_battery: bool,
     pub has_trainer: bool,
     pub mapper: u8,
+    /// Set when the header is NES 2.0 rather than iNES
+    pub is_nes2: bool,
+    /// NES 2.0 submapper, 0 when the header doesn't specify one
+    pub submapper: u8,
     pub region: CartridgeRegion,
+    /// Set when the ROM is a known dump
+    pub title: Option<String>,
+    /// Checksums of PRG and CHR ROM
+    pub crc32: u32,
+    pub sha1: [u8; 20],
 }
 
 /**
@@ -39,9 +52,20 @@ pub struct Cartridge {
  * 9      | bit 0     1 for PAL cartridges, otherwise assume NTSC.
  *        | bit 1-7   Reserved, must be zeroes!
  * 10-15  | Reserved, must be zeroes!
+ *        | Old tools wrote their name over bytes 7-15 ("DiskDude!"), so
+ *        | anything in bytes 12-15 of an iNES header means bytes 7-15 are junk.
  * 16-... | DATA - ROM banks, in ascending order. If a trainer is present, its
  *        | 512 bytes precede the ROM bank contents.
  * ...-EOF| PROG - VROM banks, in ascending order.
+ *
+ * NES 2.0 headers set bits 2-3 of byte 7 to 0b10 and reuse bytes 8-15:
+ * 8      | bit 0-3   Mapper bits 8-11.
+ *        | bit 4-7   Submapper.
+ * 9      | bit 0-3   PRG ROM size MSB.
+ *        | bit 4-7   VROM size MSB.
+ * 10     | bit 0-3   PRG RAM size, as a shift count (64 << n bytes).
+ *        | bit 4-7   Battery-backed PRG RAM size, as a shift count.
+ * 12     | bit 0-1   0 NTSC, 1 PAL, 2 multi-region, 3 Dendy.
  */
 impl Cartridge {
     pub const CARTRIDGE_HEADER: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
@@ -50,6 +74,7 @@ impl Cartridge {
     pub const CHIP_SIZE_PROG: usize = 0x4000;
     const CHIP_SIZE_PPU: usize = 0x2000;
     const CHIP_SIZE_RAM: usize = 0x2000;
+    pub const TRAINER_SIZE: usize = 0x200;
 
     /**
      * Parses the cartridge file and returns a tuple of (Cartridge, prog_bytes, ppu_bytes)
@@ -63,38 +88,110 @@ impl Cartridge {
             return Err(IronNesError::CartridgeError);
         }
 
-        let mut f = File::open(cartridge_file)?;
+        Self::from_bytes(&fs::read(cartridge_file)?)
+    }
 
-        let mut header = vec![0u8; Self::NES_FILE_HEADER_SIZE];
-        f.read(&mut header)?;
+    /**
+     * Reads a whole iNES or UNIF image from a reader, see `from_bytes`
+     */
+    pub fn from_reader<R: Read>(reader: &mut R) -> IronNesResult<(Self, Vec<u8>, Vec<u8>)> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(&data)
+    }
 
-        let cartridge = Self::from_header(&header)?;
-        warn!("Read Cartridge: {}", cartridge);
+    /**
+     * Parses a whole iNES or UNIF image and returns a tuple of (Cartridge, prog_bytes, ppu_bytes).
+     * The image must hold all the ROM its header asks for, anything after that
+     * is ignored.
+     */
+    pub fn from_bytes(data: &[u8]) -> IronNesResult<(Self, Vec<u8>, Vec<u8>)> {
+        if data.starts_with(&unif::UNIF_HEADER) {
+            let (mut cartridge, prog_rom, ppu_rom) = Self::from_unif(data)?;
+            cartridge.identify(&prog_rom, &ppu_rom);
+            warn!("Read UNIF Cartridge: {}", cartridge);
+            return Ok((cartridge, prog_rom, ppu_rom));
+        }
 
-        let mut prog_rom = vec![0u8; cartridge.get_prog_size()];
-        let mut ppu_vrom = vec![0u8; cartridge.get_ppu_size()];
+        let header = &data[..Self::NES_FILE_HEADER_SIZE.min(data.len())];
+        let mut cartridge = Self::from_header(header)?;
 
-        f.read(&mut prog_rom)?;
-        f.read(&mut ppu_vrom)?;
+        let mut rom = &data[Self::NES_FILE_HEADER_SIZE..];
+        if cartridge.has_trainer {
+            rom = &rom[Self::TRAINER_SIZE.min(rom.len())..];
+        }
 
-        Ok((cartridge, prog_rom, ppu_vrom))
+        let prog_size = cartridge.get_prog_size();
+        if rom.len() < prog_size {
+            error!("Catridge PRG ROM is {} bytes short", prog_size - rom.len());
+            return Err(IronNesError::TruncatedPrg {
+                expected: prog_size,
+                found: rom.len(),
+            });
+        }
+        let (prog_rom, rom) = rom.split_at(prog_size);
+
+        let ppu_size = cartridge.get_ppu_size();
+        if rom.len() < ppu_size {
+            error!("Catridge CHR ROM is {} bytes short", ppu_size - rom.len());
+            return Err(IronNesError::TruncatedChr {
+                expected: ppu_size,
+                found: rom.len(),
+            });
+        }
+        let (ppu_vrom, rest) = rom.split_at(ppu_size);
+        if !rest.is_empty() {
+            warn!("Catridge has {} bytes after the ROM, ignoring them", rest.len());
+        }
+
+        cartridge.identify(prog_rom, ppu_vrom);
+        warn!("Read Cartridge: {}", cartridge);
+
+        Ok((cartridge, prog_rom.to_vec(), ppu_vrom.to_vec()))
     }
 
     pub fn from_header(cartridge: &[u8]) -> IronNesResult<Self> {
         Self::cartridge_header_check(cartridge)?;
 
-        if (cartridge[7] & 0b1110u8) != 0 || (cartridge[9] & 0b11111110u8) != 0 {
-            error!("Catridge 0 sections invalid");
-            return Err(IronNesError::CartridgeError);
+        let is_nes2 = (cartridge[7] & 0b1100) == 0b1000;
+        let is_dirty = !is_nes2 && cartridge[12..16].iter().any(|&b| b != 0);
+
+        if is_dirty {
+            warn!("Catridge header has junk in bytes 7-15, ignoring them");
+        } else if !is_nes2 {
+            for (byte, reserved) in [(7, 0b1110u8), (9, 0b11111110u8)] {
+                if (cartridge[byte] & reserved) != 0 {
+                    error!("Catridge header byte {} has reserved bits set", byte);
+                    return Err(IronNesError::ReservedBits {
+                        byte,
+                        value: cartridge[byte],
+                    });
+                }
+            }
         }
 
         let mut c = Cartridge::default();
 
+        c.is_nes2 = is_nes2;
         c.num_prog_rom = cartridge[4] as usize;
-        trace!("Cartridge has {} prog chips", c.num_prog_rom);
         c.num_ppu_vrom = cartridge[5] as usize;
+        c.num_ram = match is_dirty {
+            true => 0,
+            false => cartridge[8] as usize,
+        };
+
+        if is_nes2 {
+            c.num_prog_rom |= ((cartridge[9] & 0x0f) as usize) << 8;
+            c.num_ppu_vrom |= ((cartridge[9] & 0xf0) as usize) << 4;
+            let ram_shift = (cartridge[10] & 0x0f).max(cartridge[10] >> 4);
+            c.num_ram = match ram_shift {
+                0 => 0,
+                shift => (64usize << shift).div_ceil(Self::CHIP_SIZE_RAM),
+            };
+        }
+
+        trace!("Cartridge has {} prog chips", c.num_prog_rom);
         trace!("Cartridge has {} ppu chips", c.num_ppu_vrom);
-        c.num_ram = cartridge[8] as usize;
         trace!("Cartridge has {} ram chips", c.num_ram);
 
         let has_4s = (cartridge[6] & 0b1000) != 0;
@@ -110,18 +207,29 @@ impl Cartridge {
         c.has_trainer = (cartridge[6] & 0b100) > 0;
 
         c.mapper = (cartridge[6] & 0xf0) >> 4;
-        c.mapper = c.mapper & cartridge[7] & 0xf0;
+        if !is_dirty {
+            c.mapper |= cartridge[7] & 0xf0;
+        }
 
-        if c.mapper != 0 {
-            error!(
-                "Emulator does not support mappers. Requested: {}",
-                which_mapper(c.mapper)
-            );
-            return Err(IronNesError::CartridgeError);
+        if is_nes2 {
+            if (cartridge[8] & 0x0f) != 0 {
+                let mapper = ((cartridge[8] as u16 & 0x0f) << 8)
+                    | (cartridge[7] & 0xf0) as u16
+                    | (cartridge[6] >> 4) as u16;
+                error!("Mappers above 255 are not supported");
+                return Err(IronNesError::UnsupportedMapper(mapper));
+            }
+            c.submapper = cartridge[8] >> 4;
         }
 
-        c.region = match cartridge[9] & 1 {
-            1 => CartridgeRegion::PAL,
+        // NES 2.0 has 0 NTSC, 1 PAL, 2 either and 3 Dendy
+        c.region = match (is_nes2, is_dirty) {
+            (true, _) => match cartridge[12] & 0b11 {
+                1 => CartridgeRegion::PAL,
+                3 => CartridgeRegion::Dendy,
+                _ => CartridgeRegion::NTSC,
+            },
+            (false, false) if (cartridge[9] & 1) == 1 => CartridgeRegion::PAL,
             _ => CartridgeRegion::NTSC,
         };
 
@@ -129,9 +237,16 @@ impl Cartridge {
     }
 
     fn cartridge_header_check(cartridge: &[u8]) -> IronNesResult<()> {
-        if cartridge[0..Self::CARTRIDGE_HEADER.len()] != Self::CARTRIDGE_HEADER {
+        if !cartridge.starts_with(&Self::CARTRIDGE_HEADER) {
             error!("Catridge has an invalid header");
-            return Err(IronNesError::CartridgeError);
+            return Err(IronNesError::BadMagic);
+        }
+        if cartridge.len() < Self::NES_FILE_HEADER_SIZE {
+            error!("Catridge is too short to have a header");
+            return Err(IronNesError::TruncatedHeader {
+                expected: Self::NES_FILE_HEADER_SIZE,
+                found: cartridge.len(),
+            });
         }
         Ok(())
     }
@@ -163,6 +278,8 @@ impl fmt::Display for Cartridge {
             MirrorDirection::Horizontal => write!(f, " MIRROR_HORIZONTAL")?,
             MirrorDirection::Vertical => write!(f, " MIRROR_VERTICAL")?,
             MirrorDirection::FourScreen => write!(f, " FOUR_SCREEN")?,
+            MirrorDirection::SingleScreenLower => write!(f, " SINGLE_SCREEN_LOWER")?,
+            MirrorDirection::SingleScreenUpper => write!(f, " SINGLE_SCREEN_UPPER")?,
         }
 
         if self.has_battery {
@@ -180,19 +297,32 @@ impl fmt::Display for Cartridge {
         let result = match self.region {
             CartridgeRegion::PAL => write!(f, " PAL"),
             CartridgeRegion::NTSC => write!(f, " NTSC"),
+            CartridgeRegion::Dendy => write!(f, " DENDY"),
         };
 
         write!(f, " MAPPER: {}", which_mapper(self.mapper))?;
 
+        if self.submapper != 0 {
+            write!(f, " SUBMAPPER: {}", self.submapper)?;
+        }
+
+        if let Some(title) = &self.title {
+            write!(f, " TITLE: {}", title)?;
+        }
+
         result
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MirrorDirection {
     Vertical,
     Horizontal,
     FourScreen,
+    /// Every nametable maps onto the first 1kB of CIRAM
+    SingleScreenLower,
+    /// Every nametable maps onto the second 1kB of CIRAM
+    SingleScreenUpper,
 }
 
 impl Default for MirrorDirection {
@@ -201,10 +331,12 @@ impl Default for MirrorDirection {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CartridgeRegion {
     PAL,
     NTSC,
+    /// Famicom clones with PAL length frames and NTSC CPU timing
+    Dendy,
 }
 
 impl Default for CartridgeRegion {
@@ -213,7 +345,7 @@ impl Default for CartridgeRegion {
     }
 }
 
-fn which_mapper(mapper: u8) -> &'static str {
+pub fn which_mapper(mapper: u8) -> &'static str {
     match mapper {
         0 => "No mapper",
         1 => "Nintendo MMC1",
@@ -235,14 +367,16 @@ fn which_mapper(mapper: u8) -> &'static str {
         18 => "Jaleco SS8806 chip",
         19 => "Namcot 106 chip",
         20 => "Nintendo DiskSystem",
-        21 => "Konami VRC4a",
+        21 => "Konami VRC4a/VRC4c",
         22 => "Konami VRC2a",
-        23 => "Konami VRC2a",
-        24 => "Konami VRC6",
-        25 => "Konami VRC4b",
+        23 => "Konami VRC2b/VRC4e/VRC4f",
+        24 => "Konami VRC6a",
+        25 => "Konami VRC4b/VRC4d/VRC2c",
+        26 => "Konami VRC6b",
         32 => "Irem G-101 chip",
         33 => "Taito TC0190/TC0350",
         34 => "Nina-1 board",
+        48 => "Taito TC0690",
         64 => "Tengen RAMBO-1 chip",
         65 => "Irem H-3001 chip",
         66 => "GNROM switch",
@@ -253,7 +387,94 @@ fn which_mapper(mapper: u8) -> &'static str {
         78 => "Irem 74HC161/32-based",
         79 => "AVE Nina-3 board",
         81 => "AVE Nina-6 board",
+        85 => "Konami VRC7",
         91 => "Pirate HK-SF3 chip",
+        118 => "Nintendo TxSROM (MMC3)",
+        119 => "Nintendo TQROM (MMC3)",
+        159 => "Bandai LZ93D50 with X24C01",
+        200 => "NROM-128 multicart (address latch)",
+        201 => "NROM-256 multicart (address latch)",
+        203 => "NROM-128 multicart (data latch)",
+        206 => "Namco 108",
         _ => "UNKNOWN",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ines(prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let mut header = Cartridge::CARTRIDGE_HEADER.to_vec();
+        header.extend([prg_banks, chr_banks]);
+        header.resize(Cartridge::NES_FILE_HEADER_SIZE, 0);
+        header
+    }
+
+    #[test]
+    fn test_from_bytes_checks_length() {
+        let rom = fs::read("tests/nestest/nestest.nes").unwrap();
+        let (c, prg, chr) = Cartridge::from_reader(&mut rom.as_slice()).unwrap();
+        assert_eq!((0x4000, 0x2000), (prg.len(), chr.len()));
+        assert_eq!(0x158b_0388, c.crc32);
+
+        // Trailing bytes are ignored
+        let long = [rom.clone(), vec![0xff; 128]].concat();
+        assert_eq!(prg, Cartridge::from_bytes(&long).unwrap().1);
+
+        assert!(matches!(
+            Cartridge::from_bytes(&rom[..rom.len() - 1]),
+            Err(IronNesError::TruncatedChr { expected: 0x2000, found: 0x1fff })
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(&rom[..0x100]),
+            Err(IronNesError::TruncatedPrg { expected: 0x4000, found: 0xf0 })
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(b"NES"),
+            Err(IronNesError::BadMagic)
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(&rom[..10]),
+            Err(IronNesError::TruncatedHeader { expected: 16, found: 10 })
+        ));
+    }
+
+    #[test]
+    fn test_reserved_bits() {
+        let mut header = ines(1, 0);
+        header[9] = 0x02;
+        assert!(matches!(
+            Cartridge::from_header(&header),
+            Err(IronNesError::ReservedBits { byte: 9, value: 0x02 })
+        ));
+
+        // Junk in bytes 12-15 means bytes 7-9 are junk too, and ignored
+        header[15] = b'!';
+        assert!(Cartridge::from_header(&header).is_ok());
+    }
+
+    #[test]
+    fn test_from_bytes_skips_trainer() {
+        let mut rom = ines(1, 0);
+        rom[6] = 0b100;
+        rom.extend(vec![0xaa; Cartridge::TRAINER_SIZE]);
+        rom.extend(vec![0x55; Cartridge::CHIP_SIZE_PROG]);
+
+        let (c, prg, chr) = Cartridge::from_bytes(&rom).unwrap();
+        assert!(c.has_trainer);
+        assert!(prg.iter().all(|&b| b == 0x55));
+        assert!(chr.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let mut header = ines(1, 0);
+        header[7] = 0b1000;
+        header[8] = 0x01;
+        assert!(matches!(
+            Cartridge::from_header(&header),
+            Err(IronNesError::UnsupportedMapper(0x100))
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+use super::*;
+use crate::hash;
+
+/**
+ * Known good dumps, from database.csv, with what their headers should say.
+ * Dumps are matched on the CRC32 and SHA-1 of PRG and CHR ROM together, so the
+ * header and any trainer don't change the match.
+ *
+ * The CSV is imported from the NES 2.0 XML database by
+ * scripts/import_nes20db.py, keeping only boards we have a mapper for.
+ */
+struct RomEntry {
+    crc32: u32,
+    sha1: [u8; 20],
+    mapper: u8,
+    submapper: u8,
+    mirror: MirrorDirection,
+    has_battery: bool,
+    region: CartridgeRegion,
+    title: &'static str,
+}
+
+include!(concat!(env!("OUT_DIR"), "/rom_database.rs"));
+
+fn lookup(crc32: u32, sha1: &[u8; 20]) -> Option<&'static RomEntry> {
+    ROM_DATABASE
+        .iter()
+        .find(|e| e.crc32 == crc32 && e.sha1 == *sha1)
+}
+
+impl Cartridge {
+    /**
+     * Hashes the ROM and, when it's a known dump, corrects whatever the header
+     * got wrong about the board
+     */
+    pub fn identify(&mut self, prog_rom: &[u8], ppu_rom: &[u8]) {
+        let rom = [prog_rom, ppu_rom].concat();
+        self.crc32 = hash::crc32(&rom);
+        self.sha1 = hash::sha1(&rom);
+
+        let entry = match lookup(self.crc32, &self.sha1) {
+            Some(entry) => entry,
+            None => {
+                info!("ROM {:08x} is not in the database", self.crc32);
+                return;
+            }
+        };
+        info!("ROM {:08x} is {}", self.crc32, entry.title);
+        self.title = Some(entry.title.to_string());
+
+        if (self.mapper, self.submapper) != (entry.mapper, entry.submapper) {
+            warn!(
+                "Header says mapper {}.{}, the database says {}.{}",
+                self.mapper, self.submapper, entry.mapper, entry.submapper
+            );
+            self.mapper = entry.mapper;
+            self.submapper = entry.submapper;
+        }
+        if self.mirror != entry.mirror {
+            warn!(
+                "Header says {:?} mirroring, the database says {:?}",
+                self.mirror, entry.mirror
+            );
+            self.mirror = entry.mirror;
+        }
+        if self.has_battery != entry.has_battery {
+            warn!("Header battery bit is wrong, the database says {}", entry.has_battery);
+            self.has_battery = entry.has_battery;
+        }
+        if self.region != entry.region {
+            warn!("Header says {:?}, the database says {:?}", self.region, entry.region);
+            self.region = entry.region;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_database_corrects_header() {
+        let rom = fs::read("tests/nestest/nestest.nes").unwrap();
+        let (prg, chr) = rom[16..].split_at(0x4000);
+
+        // A header from an old tool: "DiskDude!" over bytes 7-15, the wrong
+        // mapper, a battery and vertical mirroring
+        let mut header = rom[..16].to_vec();
+        header[6] = 0x13;
+        header[7..16].copy_from_slice(b"DiskDude!");
+
+        let mut c = Cartridge::from_header(&header).unwrap();
+        assert_eq!(1, c.mapper);
+        c.identify(prg, chr);
+        assert_eq!(Some("nestest"), c.title.as_deref());
+        assert_eq!(0x158b_0388, c.crc32);
+        assert_eq!(0, c.mapper);
+        assert_eq!(MirrorDirection::Horizontal, c.mirror);
+        assert!(!c.has_battery);
+
+        // Anything else is left alone
+        let mut c = Cartridge::from_header(&header).unwrap();
+        c.identify(&prg[1..], chr);
+        assert_eq!(None, c.title);
+        assert_eq!(1, c.mapper);
+    }
+
+    #[test]
+    fn test_database_corrects_loaded_rom() {
+        // nestest as an old tool would have written it: mapper 1, four screen
+        // and a battery it doesn't have
+        let mut rom = fs::read("tests/nestest/nestest.nes").unwrap();
+        rom[6] = 0x1a;
+        rom[7] = 0;
+
+        let (c, prg, chr) = Cartridge::from_bytes(&rom).unwrap();
+        assert_eq!(Some("nestest"), c.title.as_deref());
+        assert_eq!((0, 0), (c.mapper, c.submapper));
+        assert_eq!(MirrorDirection::Horizontal, c.mirror);
+        assert!(!c.has_battery);
+        assert_eq!((0x4000, 0x2000), (prg.len(), chr.len()));
+    }
+}
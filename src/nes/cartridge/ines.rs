@@ -0,0 +1,116 @@
+use super::*;
+
+impl Cartridge {
+    /**
+     * Builds an iNES or NES 2.0 image of the board with the given ROM. The
+     * header is written from scratch, so it never carries the junk old tools
+     * left in bytes 7-15.
+     */
+    pub fn to_bytes(
+        &self,
+        prog_rom: &[u8],
+        ppu_rom: &[u8],
+        trainer: Option<&[u8]>,
+        nes2: bool,
+    ) -> IronNesResult<Vec<u8>> {
+        let max_chips = match nes2 {
+            true => 0xfff,
+            false => 0xff,
+        };
+        let chips = |rom: &[u8], size: usize, name: &str| {
+            if !rom.len().is_multiple_of(size) || rom.len() / size > max_chips {
+                error!(
+                    "{} ROM of {} bytes can't be put in a header",
+                    name,
+                    rom.len()
+                );
+                return Err(IronNesError::CartridgeError);
+            }
+            Ok(rom.len() / size)
+        };
+        let num_prog_rom = chips(prog_rom, Self::CHIP_SIZE_PROG, "PRG")?;
+        let num_ppu_vrom = chips(ppu_rom, Self::CHIP_SIZE_PPU, "CHR")?;
+        if trainer.is_some_and(|t| t.len() != Self::TRAINER_SIZE) {
+            error!("Trainer must be {} bytes", Self::TRAINER_SIZE);
+            return Err(IronNesError::CartridgeError);
+        }
+
+        let mut header = [0u8; Self::NES_FILE_HEADER_SIZE];
+        header[..4].copy_from_slice(&Self::CARTRIDGE_HEADER);
+        header[4] = num_prog_rom as u8;
+        header[5] = num_ppu_vrom as u8;
+
+        header[6] =
+            (self.mapper << 4) | ((trainer.is_some() as u8) << 2) | ((self.has_battery as u8) << 1);
+        header[6] |= match self.mirror {
+            MirrorDirection::FourScreen => 0b1000,
+            MirrorDirection::Vertical => 0b0001,
+            _ => 0,
+        };
+        header[7] = self.mapper & 0xf0;
+
+        let is_pal = self.region == CartridgeRegion::PAL;
+        match nes2 {
+            true => {
+                header[7] |= 0b1000;
+                header[8] = self.submapper << 4;
+                header[9] = ((num_ppu_vrom >> 4) as u8 & 0xf0) | (num_prog_rom >> 8) as u8;
+
+                // iNES says no RAM size means 8kB, NES 2.0 says it means none
+                let num_ram = match self.is_nes2 {
+                    true => self.num_ram,
+                    false => self.num_ram.max(1),
+                };
+                let ram_shift = match num_ram {
+                    0 => 0,
+                    n => 7 + n.next_power_of_two().trailing_zeros() as u8,
+                };
+                header[10] = match self.has_battery {
+                    true => ram_shift << 4,
+                    false => ram_shift,
+                };
+                // 8kB of CHR RAM when there's no CHR ROM
+                header[11] = match num_ppu_vrom {
+                    0 => 7,
+                    _ => 0,
+                };
+                header[12] = match self.region {
+                    CartridgeRegion::NTSC => 0,
+                    CartridgeRegion::PAL => 1,
+                    CartridgeRegion::Dendy => 3,
+                };
+            }
+            false => {
+                header[8] = self.num_ram as u8;
+                header[9] = is_pal as u8;
+            }
+        }
+
+        let trainer = trainer.unwrap_or(&[]);
+        Ok([&header[..], trainer, prog_rom, ppu_rom].concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_bytes_round_trip() {
+        let rom = fs::read("tests/nestest/nestest.nes").unwrap();
+        let (c, prg, chr) = Cartridge::from_bytes(&rom).unwrap();
+        assert_eq!(rom, c.to_bytes(&prg, &chr, None, false).unwrap());
+
+        let nes2 = c.to_bytes(&prg, &chr, None, true).unwrap();
+        let (c2, prg2, chr2) = Cartridge::from_bytes(&nes2).unwrap();
+        assert!(c2.is_nes2);
+        assert_eq!((prg, chr), (prg2, chr2));
+        assert_eq!(
+            (c.mapper, c.mirror, c.region),
+            (c2.mapper, c2.mirror, c2.region)
+        );
+        assert_eq!(Cartridge::CHIP_SIZE_RAM, c2.get_ram_size());
+
+        assert!(c.to_bytes(&rom, &[], None, false).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use super::*;
+
+/**
+ * UNIF Layout (.unf/.unif file)
+ * Byte   | Contents
+ * -------|-------------------------------------------------------------------
+ * 0-3    | String "UNIF".
+ * 4-7    | Revision, little endian.
+ * 8-31   | Reserved, zeroes.
+ * 32-... | Chunks: a 4 byte ID, a 4 byte little endian length, then the data.
+ *
+ * Chunks:
+ * MAPR   | Board name, a NUL terminated string such as "NES-TLROM".
+ * PRGn   | PRG ROM, n from 0 to F, joined in order.
+ * CHRn   | CHR ROM, n from 0 to F, joined in order.
+ * MIRR   | 0 horizontal, 1 vertical, 2 and 3 single screen, 4 four screen,
+ *        | 5 controlled by the mapper.
+ * BATR   | Present when the board has battery backed RAM.
+ * TVCI   | 0 NTSC, 1 PAL, 2 either.
+ * CTRL   | Bit field of the controllers the game uses.
+ *
+ * UNIF names boards instead of numbering mappers, so the board name is looked
+ * up in `BOARDS` to find the mapper that emulates it.
+ */
+pub const UNIF_HEADER: [u8; 4] = [0x55, 0x4e, 0x49, 0x46];
+const UNIF_HEADER_SIZE: usize = 32;
+
+/// Board names, without their "NES-", "UNL-" style prefix, with the mapper
+/// and submapper that emulates them
+const BOARDS: &[(&str, u8, u8)] = &[
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("RROM", 0, 0),
+    ("RROM-128", 0, 0),
+    ("SROM", 0, 0),
+    ("RTROM", 0, 0),
+    ("STROM", 0, 0),
+    ("HROM", 0, 0),
+    ("TBROM", 4, 0),
+    ("TEROM", 4, 0),
+    ("TFROM", 4, 0),
+    ("TGROM", 4, 0),
+    ("TKROM", 4, 0),
+    ("TLROM", 4, 0),
+    ("TL1ROM", 4, 0),
+    ("TNROM", 4, 0),
+    ("TR1ROM", 4, 0),
+    ("TSROM", 4, 0),
+    ("TVROM", 4, 0),
+    ("B4", 4, 0),
+    ("TKSROM", 118, 0),
+    ("TLSROM", 118, 0),
+    ("TQROM", 119, 0),
+    ("DEROM", 206, 0),
+    ("DE1ROM", 206, 0),
+    ("DRROM", 206, 0),
+    ("CPROM", 13, 0),
+    ("BNROM", 34, 2),
+    ("NINA-01", 34, 1),
+    ("NINA-001", 34, 1),
+    ("GNROM", 66, 0),
+    ("MHROM", 66, 0),
+    ("TENGEN-800032", 64, 0),
+    ("K-1029", 15, 0),
+];
+
+/// Strips the manufacturer prefix off a board name, "NES-TLROM" is "TLROM"
+fn board_name(name: &str) -> &str {
+    const PREFIXES: [&str; 7] = ["NES-", "HVC-", "UNL-", "BMC-", "BTL-", "AVE-", "IREM-"];
+    PREFIXES
+        .iter()
+        .find_map(|p| name.strip_prefix(p))
+        .unwrap_or(name)
+}
+
+fn lookup_board(name: &str) -> Option<(u8, u8)> {
+    let name = board_name(name).to_ascii_uppercase();
+    BOARDS
+        .iter()
+        .find(|(board, _, _)| *board == name)
+        .map(|&(_, mapper, submapper)| (mapper, submapper))
+}
+
+impl Cartridge {
+    /**
+     * Parses a whole UNIF file and returns a tuple of (Cartridge, prog_bytes, ppu_bytes)
+     */
+    pub fn from_unif(data: &[u8]) -> IronNesResult<(Self, Vec<u8>, Vec<u8>)> {
+        if !data.starts_with(&UNIF_HEADER) {
+            error!("Catridge has an invalid UNIF header");
+            return Err(IronNesError::BadMagic);
+        }
+        if data.len() < UNIF_HEADER_SIZE {
+            error!("Catridge is too short to have a UNIF header");
+            return Err(IronNesError::TruncatedHeader {
+                expected: UNIF_HEADER_SIZE,
+                found: data.len(),
+            });
+        }
+
+        let mut c = Cartridge::default();
+        let mut board = None;
+        let mut prg: [Vec<u8>; 16] = Default::default();
+        let mut chr: [Vec<u8>; 16] = Default::default();
+
+        let mut pos = UNIF_HEADER_SIZE;
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let len =
+                u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+                    as usize;
+            pos += 8;
+
+            let chunk = match data.get(pos..pos + len) {
+                Some(chunk) => chunk,
+                None => {
+                    let problem = format!("{} runs past the end of the file", show_id(id));
+                    error!("UNIF chunk {}", problem);
+                    return Err(IronNesError::BadUnifChunk(problem));
+                }
+            };
+            pos += len;
+
+            let first = chunk.first().copied().unwrap_or(0);
+            match id {
+                b"MAPR" => {
+                    let end = chunk.iter().position(|&b| b == 0).unwrap_or(chunk.len());
+                    board = Some(String::from_utf8_lossy(&chunk[..end]).into_owned());
+                }
+                [b'P', b'R', b'G', n] => prg[hex_digit(*n)?] = chunk.to_vec(),
+                [b'C', b'H', b'R', n] => chr[hex_digit(*n)?] = chunk.to_vec(),
+                b"MIRR" => {
+                    c.mirror = match first {
+                        1 => MirrorDirection::Vertical,
+                        2 => MirrorDirection::SingleScreenLower,
+                        3 => MirrorDirection::SingleScreenUpper,
+                        4 => MirrorDirection::FourScreen,
+                        _ => MirrorDirection::Horizontal,
+                    };
+                }
+                b"BATR" => c.has_battery = first != 0,
+                b"TVCI" => {
+                    c.region = match first {
+                        1 => CartridgeRegion::PAL,
+                        _ => CartridgeRegion::NTSC,
+                    };
+                }
+                b"CTRL" => info!("UNIF controllers {:08b}", first),
+                _ => trace!("Skipping UNIF chunk {}", show_id(id)),
+            }
+        }
+
+        let board = board.ok_or_else(|| {
+            error!("UNIF file has no MAPR chunk");
+            IronNesError::BadUnifChunk("no MAPR chunk".to_string())
+        })?;
+        let (mapper, submapper) = lookup_board(&board).ok_or_else(|| {
+            error!("Emulator does not support UNIF board {}", board);
+            IronNesError::UnsupportedBoard(board.clone())
+        })?;
+        c.mapper = mapper;
+        c.submapper = submapper;
+
+        let prg = prg.concat();
+        let chr = chr.concat();
+        c.num_prog_rom = prg.len().div_ceil(Self::CHIP_SIZE_PROG);
+        c.num_ppu_vrom = chr.len().div_ceil(Self::CHIP_SIZE_PPU);
+        c.num_ram = 1;
+
+        // Mappers count banks back from the end, which needs at least two
+        if prg.len() < 0x4000 || prg.len() % 0x2000 != 0 {
+            let problem = format!(
+                "PRG ROM is {} bytes, not 16kB or more in 8kB banks",
+                prg.len()
+            );
+            error!("UNIF {}", problem);
+            return Err(IronNesError::BadUnifChunk(problem));
+        }
+
+        Ok((c, prg, chr))
+    }
+}
+
+fn hex_digit(n: u8) -> IronNesResult<usize> {
+    (n as char).to_digit(16).map(|d| d as usize).ok_or_else(|| {
+        error!("Invalid UNIF ROM chunk number {}", n as char);
+        IronNesError::BadUnifChunk(format!("ROM chunk number {}", n as char))
+    })
+}
+
+fn show_id(id: &[u8]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        [id, &(data.len() as u32).to_le_bytes(), data].concat()
+    }
+
+    fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = UNIF_HEADER.to_vec();
+        file.extend(7u32.to_le_bytes());
+        file.resize(UNIF_HEADER_SIZE, 0);
+        file.extend(chunks.concat());
+        file
+    }
+
+    #[test]
+    fn test_unif_chunks() {
+        let file = unif(&[
+            chunk(b"MAPR", b"NES-TLROM\0"),
+            chunk(b"PRG1", &[2; 0x4000]),
+            chunk(b"PRG0", &[1; 0x4000]),
+            chunk(b"CHR0", &[3; 0x2000]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"BATR", &[1]),
+            chunk(b"TVCI", &[1]),
+            chunk(b"CTRL", &[1]),
+            chunk(b"READ", b"Some notes\0"),
+        ]);
+
+        let (c, prg, chr) = Cartridge::from_unif(&file).unwrap();
+        assert_eq!(4, c.mapper);
+        assert_eq!(MirrorDirection::Vertical, c.mirror);
+        assert!(c.has_battery);
+        assert!(matches!(c.region, CartridgeRegion::PAL));
+
+        // PRG chunks are joined by number, not by file order
+        assert_eq!(0x8000, prg.len());
+        assert_eq!([1, 2], [prg[0], prg[0x4000]]);
+        assert_eq!(c.get_ppu_size(), chr.len());
+    }
+
+    #[test]
+    fn test_unif_boards() {
+        assert_eq!(Some((34, 1)), lookup_board("AVE-NINA-01"));
+        assert_eq!(Some((118, 0)), lookup_board("NES-TLSROM"));
+        assert_eq!(Some((15, 0)), lookup_board("BMC-K-1029"));
+        assert_eq!(None, lookup_board("UNL-SOMETHING"));
+        assert_eq!(None, lookup_board("BMC-Super24in1SC03"));
+
+        let file = unif(&[
+            chunk(b"MAPR", b"UNL-SOMETHING\0"),
+            chunk(b"PRG0", &[0; 0x4000]),
+        ]);
+        assert!(matches!(
+            Cartridge::from_unif(&file),
+            Err(IronNesError::UnsupportedBoard(board)) if board == "UNL-SOMETHING"
+        ));
+
+        // Chunks can't run off the end of the file
+        let mut file = unif(&[
+            chunk(b"MAPR", b"NES-NROM-256\0"),
+            chunk(b"PRG0", &[0; 0x8000]),
+        ]);
+        file.truncate(file.len() - 1);
+        assert!(matches!(
+            Cartridge::from_unif(&file),
+            Err(IronNesError::BadUnifChunk(_))
+        ));
+
+        // Too little PRG, or a part of a bank
+        for size in [0, 0x1000, 0x2000, 0x5000] {
+            let file = unif(&[
+                chunk(b"MAPR", b"NES-TLROM\0"),
+                chunk(b"PRG0", &vec![0; size]),
+            ]);
+            assert!(matches!(
+                Cartridge::from_unif(&file),
+                Err(IronNesError::BadUnifChunk(_))
+            ));
+        }
+
+        let file = unif(&[chunk(b"PRG0", &[0; 0x4000])]);
+        assert!(matches!(
+            Cartridge::from_unif(&file),
+            Err(IronNesError::BadUnifChunk(_))
+        ));
+        let file = unif(&[chunk(b"MAPR", b"NES-NROM-256\0"), chunk(b"PRGX", &[0])]);
+        assert!(matches!(
+            Cartridge::from_unif(&file),
+            Err(IronNesError::BadUnifChunk(_))
+        ));
+        assert!(matches!(
+            Cartridge::from_unif(&UNIF_HEADER),
+            Err(IronNesError::TruncatedHeader { .. })
+        ));
+    }
+}
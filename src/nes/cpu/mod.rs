@@ -27,6 +27,8 @@ impl Cpu {
     pub const ADDR_NMI: Addr = 0xFFFA;
     pub const ADDR_RESET: Addr = 0xFFFC;
 
+    const INTERRUPT_CYCLES: usize = 7;
+
     pub fn new() -> Self {
         Self {
             cycle: 0,
@@ -38,8 +40,15 @@ impl Cpu {
         &self.registers
     }
 
+    /// Lets a driver set up registers before calling into a program, as an
+    /// NSF player does
+    pub fn registers_mut(&mut self) -> &mut register::Registers {
+        &mut self.registers
+    }
+
+    /// The reset sequence takes as long as an interrupt
     pub fn reset(&mut self, mem: &Memory) -> IronNesResult<()> {
-        self.cycle = 0;
+        self.cycle = Self::INTERRUPT_CYCLES;
 
         self.registers = register::Registers::new();
         self.registers.pc = mem.load16(Self::ADDR_RESET)?;
@@ -60,6 +69,7 @@ impl Cpu {
 
         // Increment the program counter
         self.registers.pc = self.registers.pc.wrapping_add(instr.bytes.into());
+        self.catch_up_ppu(&instr, mem)?;
 
         // Generated jump-table to make the code less verbose
         include!(concat!(env!("OUT_DIR"), "/instr_jumptable.rs"))?;
@@ -71,14 +81,56 @@ impl Cpu {
         ((addr1 & 0xff00) != (addr2 & 0xff00)) as usize
     }
 
-    /// Used to advanced the CPU to a future instruction
+    /**
+     * The PPU is otherwise only ticked once an instruction's done, up to 21
+     * dots after it read or wrote a register. Loads and stores land on an
+     * instruction's last cycle, so catch the PPU up to just before that one. A
+     * read-modify-write really reads two cycles earlier, but is left at the last.
+     */
+    fn catch_up_ppu(&self, instr: &Instruction, mem: &mut Memory) -> IronNesResult<()> {
+        match instr.addr_mode {
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY => (),
+            _ => return Ok(()),
+        }
+
+        let addr = instr.addr_mode.load_operand(&self.registers, mem)?;
+        if Memory::is_ppu_register(addr) {
+            let cycles = instr.cycles + page_cross_penalty(self, instr, addr);
+            mem.catch_up(cycles - 1);
+        }
+        Ok(())
+    }
+
+    /**
+     * Used to advanced the CPU to a future instruction, in place of the jump
+     * the reset vector would have made, so it takes no cycles
+     */
     pub fn jsr(&mut self, addr: Addr) -> IronNesResult<Instruction> {
         let instr = Instruction::lookup(0x20);
-        self.cycle += instr.cycles;
         self.registers.pc = addr;
         Ok(instr)
     }
 
+    /// Services a maskable interrupt request, if interrupts are enabled
+    pub fn irq(&mut self, mem: &mut Memory) -> IronNesResult<()> {
+        if self.registers.get_flag(Flags::I) {
+            return Ok(());
+        }
+
+        self.cycle += Self::INTERRUPT_CYCLES;
+        self.interrupt(mem, InterruptType::IRQ)
+    }
+
+    /// Services a non-maskable interrupt, the PPU's vblank
+    pub fn nmi(&mut self, mem: &mut Memory) -> IronNesResult<()> {
+        self.cycle += Self::INTERRUPT_CYCLES;
+        self.interrupt(mem, InterruptType::NMI)
+    }
+
     // Interrupts can happen on NON-brk instructions...
     fn interrupt(&mut self, mem: &mut Memory, t: InterruptType) -> IronNesResult<()> {
         if self.registers.get_flag(Flags::I) && t == InterruptType::IRQ {
@@ -111,35 +163,40 @@ impl Cpu {
         let instr = Instruction::lookup(opcode);
 
         Ok(format!(
-            "{:04x} {:28} {} CYC {}",
+            "{:04x} {:28} {} PPU {:3},{:3} CYC {}",
             self.registers.pc,
             instr.print(self.registers.pc - (instr.bytes as u16), &mem),
             self.registers,
+            mem.ppu().scanline(),
+            mem.ppu().dot(),
             self.cycle
         ))
     }
 }
 
-fn pay_for_page_cross(cpu: &mut Cpu, instr: &Instruction, addr: Addr) -> IronNesResult<()> {
-    if instr.can_cross_page {
-        let src_addr = match instr.addr_mode {
-            AddressingMode::Relative => cpu.registers.pc,
-            AddressingMode::AbsoluteX => addr.wrapping_sub(cpu.registers.x as Addr),
-            AddressingMode::AbsoluteY | AddressingMode::IndirectY => {
-                addr.wrapping_sub(cpu.registers.y as Addr)
-            }
-            _ => addr,
-        };
-        let penalty = Cpu::calc_page_cross_penalty(src_addr, addr);
-        trace!(
-            "Paying {} cycles for page cross penalty [${:04x} -> ${:04x}]",
-            penalty,
-            src_addr,
-            addr
-        );
-        cpu.cycle += penalty;
+fn page_cross_penalty(cpu: &Cpu, instr: &Instruction, addr: Addr) -> usize {
+    if !instr.can_cross_page {
+        return 0;
     }
+    let src_addr = match instr.addr_mode {
+        AddressingMode::Relative => cpu.registers.pc,
+        AddressingMode::AbsoluteX => addr.wrapping_sub(cpu.registers.x as Addr),
+        AddressingMode::AbsoluteY | AddressingMode::IndirectY => {
+            addr.wrapping_sub(cpu.registers.y as Addr)
+        }
+        _ => addr,
+    };
+    Cpu::calc_page_cross_penalty(src_addr, addr)
+}
 
+fn pay_for_page_cross(cpu: &mut Cpu, instr: &Instruction, addr: Addr) -> IronNesResult<()> {
+    let penalty = page_cross_penalty(cpu, instr, addr);
+    trace!(
+        "Paying {} cycles for page cross penalty [${:04x}]",
+        penalty,
+        addr
+    );
+    cpu.cycle += penalty;
     Ok(())
 }
 
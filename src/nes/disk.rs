@@ -0,0 +1,218 @@
+use crate::error::*;
+
+use log::*;
+
+/**
+ * Famicom Disk System image (.fds file)
+ *
+ * Byte    | Contents
+ * --------|------------------------------------------------------------------
+ * 0-3     | String "FDS^Z", only present in images with the fwNES header.
+ * 4       | Number of disk sides.
+ * 5-15    | Reserved, zeroes.
+ * 16-...  | Disk sides of 65500 bytes each, in order.
+ *
+ * Each side is a run of blocks, each starting with its block code:
+ * 1       | Disk info, 56 bytes, starting "*NINTENDO-HVC*".
+ * 2       | File count, 2 bytes.
+ * 3       | File header, 16 bytes, the file size at bytes 13-14.
+ * 4       | File data, 1 byte plus the size in the file header before it.
+ *
+ * The image leaves out what the drive sees between blocks: the gaps, the
+ * start mark before each block and the CRC after it. `DiskSide` puts them
+ * back so the drive can stream the side byte by byte, and takes them out
+ * again when the image is saved.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct FdsImage {
+    pub sides: Vec<DiskSide>,
+    /// Whether the file had the fwNES header, which saving keeps
+    has_header: bool,
+}
+
+/// One side of a disk, as the drive head reads it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiskSide {
+    pub data: Vec<u8>,
+}
+
+impl FdsImage {
+    pub const HEADER: [u8; 4] = [0x46, 0x44, 0x53, 0x1a];
+    const HEADER_SIZE: usize = 16;
+    pub const SIDE_SIZE: usize = 65500;
+    const DISK_INFO: &'static [u8] = b"\x01*NINTENDO-HVC*";
+
+    /// Whether the file looks like a disk image, with or without a header
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(&Self::HEADER) || data.starts_with(Self::DISK_INFO)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> IronNesResult<Self> {
+        let has_header = data.starts_with(&Self::HEADER);
+        let body = match has_header {
+            true => &data[Self::HEADER_SIZE.min(data.len())..],
+            false => data,
+        };
+
+        // Dumps sometimes come a few bytes short of a full side
+        let count = body.len().div_ceil(Self::SIDE_SIZE);
+        if count == 0 || !body.starts_with(Self::DISK_INFO) {
+            error!("Disk image has no disk info block");
+            return Err(IronNesError::CartridgeError);
+        }
+        if has_header && data[4] as usize != count {
+            warn!("Disk image header says {} sides, found {}", data[4], count);
+        }
+
+        let sides = body
+            .chunks(Self::SIDE_SIZE)
+            .map(DiskSide::from_image)
+            .collect();
+        Ok(Self { sides, has_header })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        if self.has_header {
+            data.extend_from_slice(&Self::HEADER);
+            data.push(self.sides.len() as u8);
+            data.resize(Self::HEADER_SIZE, 0);
+        }
+        self.sides.iter().for_each(|s| data.extend(s.to_image()));
+        data
+    }
+}
+
+impl DiskSide {
+    /// The gap before the first block, and between blocks, in bytes
+    const LEAD_IN: usize = 28300 / 8;
+    const GAP: usize = 976 / 8;
+    const BLOCK_START: u8 = 0x80;
+    /// The drive doesn't check CRCs, so any two bytes do
+    const CRC: [u8; 2] = [0x4d, 0x62];
+    /// Some room past the last block for files written by the game
+    const SIZE: usize = 0x12000;
+
+    fn block_size(code: u8, file_size: usize) -> Option<usize> {
+        match code {
+            1 => Some(56),
+            2 => Some(2),
+            3 => Some(16),
+            4 => Some(1 + file_size),
+            _ => None,
+        }
+    }
+
+    /// Spreads the blocks from an image out into what the drive reads
+    fn from_image(image: &[u8]) -> Self {
+        let mut data = vec![0; Self::LEAD_IN];
+        let mut file_size = 0;
+        let mut pos = 0;
+
+        while let Some(size) = image.get(pos).and_then(|&c| Self::block_size(c, file_size)) {
+            let block = &image[pos..(pos + size).min(image.len())];
+            if block[0] == 3 && block.len() == 16 {
+                file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+            }
+
+            data.push(Self::BLOCK_START);
+            data.extend_from_slice(block);
+            data.extend_from_slice(&Self::CRC);
+            data.extend(std::iter::repeat_n(0, Self::GAP));
+            pos += size;
+        }
+
+        data.resize(data.len().max(Self::SIZE), 0);
+        Self { data }
+    }
+
+    /// Packs the blocks back together, dropping the gaps and CRCs
+    fn to_image(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(FdsImage::SIDE_SIZE);
+        let mut file_size = 0;
+        let mut pos = 0;
+
+        loop {
+            while self.data.get(pos) == Some(&0) {
+                pos += 1;
+            }
+            if self.data.get(pos) != Some(&Self::BLOCK_START) {
+                break;
+            }
+            pos += 1;
+
+            let size = match self
+                .data
+                .get(pos)
+                .and_then(|&c| Self::block_size(c, file_size))
+            {
+                Some(size) => size,
+                None => break,
+            };
+            let block = &self.data[pos..(pos + size).min(self.data.len())];
+            if block[0] == 3 && block.len() == 16 {
+                file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+            }
+            image.extend_from_slice(block);
+            pos += size + Self::CRC.len();
+        }
+
+        image.resize(FdsImage::SIDE_SIZE, 0);
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One side with the disk info, a file count and one 3 byte file
+    fn side() -> Vec<u8> {
+        let mut side = FdsImage::DISK_INFO.to_vec();
+        side.resize(56, 0);
+        side.extend([2, 1]);
+        side.extend([3, 0, 0]);
+        side.extend(b"FILE0000");
+        side.extend([0x00, 0x60, 3, 0, 0]);
+        side.extend([4, 0xaa, 0xbb, 0xcc]);
+        side.resize(FdsImage::SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    fn test_fds_header() {
+        let mut headered = FdsImage::HEADER.to_vec();
+        headered.extend([2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        headered.extend(side());
+        headered.extend(side());
+
+        let image = FdsImage::from_bytes(&headered).unwrap();
+        assert_eq!(2, image.sides.len());
+        assert_eq!(headered, image.to_bytes());
+
+        let raw = [side(), side()].concat();
+        assert!(FdsImage::detect(&raw));
+        let image = FdsImage::from_bytes(&raw).unwrap();
+        assert_eq!(2, image.sides.len());
+        assert_eq!(raw, image.to_bytes());
+
+        assert!(FdsImage::from_bytes(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn test_fds_gaps() {
+        let image = FdsImage::from_bytes(&side()).unwrap();
+        let data = &image.sides[0].data;
+
+        // Lead in, start mark, then the disk info block and its CRC
+        let start = DiskSide::LEAD_IN;
+        assert!(data[..start].iter().all(|&b| b == 0));
+        assert_eq!(0x80, data[start]);
+        assert_eq!(1, data[start + 1]);
+        assert_eq!(&[0x4d, 0x62], &data[start + 57..start + 59]);
+
+        // The file data block came through whole
+        let file = data.windows(4).position(|w| w == [4, 0xaa, 0xbb, 0xcc]);
+        assert!(file.is_some());
+    }
+}
@@ -0,0 +1,282 @@
+use super::eeprom::{Eeprom, EepromChip};
+use super::*;
+
+/**
+ * Mappers 16 and 159: Bandai FCG boards.
+ *
+ * PRG:  16kB switchable at $8000, last bank fixed at $C000.
+ * CHR:  eight switchable 1kB banks.
+ * IRQ:  a 16 bit counter decremented every CPU cycle.
+ * Save: a serial EEPROM instead of PRG RAM, on the LZ93D50 boards.
+ *
+ * Registers repeat every 16 bytes:
+ *   $x0-$x7 CHR banks      $x8 PRG bank      $x9 mirroring
+ *   $xA IRQ control        $xB-$xC IRQ counter low and high
+ *   $xD EEPROM lines: bit 5 SCL, bit 6 SDA
+ *
+ * The older FCG-1 and FCG-2 chips (submapper 4) have their registers at
+ * $6000-$7FFF and write the IRQ counter directly. The LZ93D50 (submapper 5,
+ * and mapper 159) has them at $8000-$FFFF and writes a latch that is copied
+ * to the counter through $xA. Mapper 16 without a submapper gets both.
+ *
+ * Mapper 159 boards carry an X24C01, the LZ93D50 mapper 16 boards an X24C02.
+ * Its data line reads back through bit 4 of $6000-$7FFF. Without a submapper
+ * only headers with the battery bit set get an EEPROM, so games that never
+ * save don't leave empty saves behind.
+ */
+pub struct Bandai {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    chip: FcgChip,
+    eeprom: Option<Eeprom>,
+
+    prg_bank: u8,
+    chr_banks: [u8; 8],
+    mirror: u8,
+
+    irq_latch: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FcgChip {
+    Fcg,
+    Lz93d50,
+    /// Unknown, decode registers in both places
+    Either,
+}
+
+impl Bandai {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mapper: u8, submapper: u8, has_battery: bool) -> Self {
+        let (chip, eeprom) = match (mapper, submapper) {
+            (159, _) => (FcgChip::Lz93d50, Some(EepromChip::X24C01)),
+            (_, 4) => (FcgChip::Fcg, None),
+            (_, 5) => (FcgChip::Lz93d50, Some(EepromChip::X24C02)),
+            (_, _) if has_battery => (FcgChip::Either, Some(EepromChip::X24C02)),
+            (_, _) => (FcgChip::Either, None),
+        };
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            chip,
+            eeprom: eeprom.map(Eeprom::new),
+            prg_bank: 0,
+            chr_banks: [0; 8],
+            mirror: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_bank as usize,
+            _ => self.prg.len() / 0x4000 - 1,
+        };
+        bank_offset(&self.prg, 0x4000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_banks[(addr as usize >> 10) & 7];
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+
+    fn decodes(&self, addr: Addr) -> bool {
+        matches!(
+            (self.chip, addr),
+            (FcgChip::Fcg, 0x6000..=0x7fff)
+                | (FcgChip::Lz93d50, 0x8000..=0xffff)
+                | (FcgChip::Either, 0x6000..=0xffff)
+        )
+    }
+
+    fn write_register(&mut self, addr: Addr, v: u8) {
+        // The FCG chips have no latch, so their counter takes writes directly
+        let direct = matches!(
+            (self.chip, addr),
+            (FcgChip::Fcg, _) | (FcgChip::Either, 0x6000..=0x7fff)
+        );
+
+        match addr & 0x0f {
+            reg @ 0x0..=0x7 => self.chr_banks[reg as usize] = v,
+            0x8 => self.prg_bank = v & 0x0f,
+            0x9 => self.mirror = v & 0b11,
+            0xa => {
+                self.irq_enabled = (v & 1) != 0;
+                self.irq_pending = false;
+                if !direct {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xb => {
+                self.irq_latch = (self.irq_latch & 0xff00) | v as u16;
+                if direct {
+                    self.irq_counter = (self.irq_counter & 0xff00) | v as u16;
+                }
+            }
+            0xc => {
+                self.irq_latch = (self.irq_latch & 0x00ff) | ((v as u16) << 8);
+                if direct {
+                    self.irq_counter = (self.irq_counter & 0x00ff) | ((v as u16) << 8);
+                }
+            }
+            0xd => {
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.set_lines((v & 0x20) != 0, (v & 0x40) != 0);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match (addr, &self.eeprom) {
+            (0x6000..=0x7fff, Some(eeprom)) => {
+                (open_bus(addr) & 0xef) | ((eeprom.output() as u8) << 4)
+            }
+            (0x8000..=0xffff, _) => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        if self.decodes(addr) {
+            self.write_register(addr, v);
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        match self.mirror {
+            0 => MirrorDirection::Vertical,
+            1 => MirrorDirection::Horizontal,
+            2 => MirrorDirection::SingleScreenLower,
+            _ => MirrorDirection::SingleScreenUpper,
+        }
+    }
+
+    /// The counter is checked before it's decremented, so a value of N fires
+    /// after N+1 cycles
+    fn tick(&mut self, cycles: usize) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        for _ in 0..cycles {
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.eeprom.as_ref().map(|e| e.data().to_vec())
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.load(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bandai(mapper: u8, submapper: u8) -> Bandai {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x4000]).collect();
+        let chr = (0..=255).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Bandai::new(prg, chr, mapper, submapper, false)
+    }
+
+    #[test]
+    fn test_bandai_register_ranges() {
+        let mut fcg = bandai(16, 4);
+        fcg.store_prg(0x8008, 3);
+        assert_eq!(0, fcg.load_prg(0x8000));
+        fcg.store_prg(0x6008, 3);
+        assert_eq!(3, fcg.load_prg(0x8000));
+        assert_eq!(15, fcg.load_prg(0xc000));
+
+        let mut lz = bandai(16, 5);
+        lz.store_prg(0x6003, 0x44);
+        assert_eq!(0, lz.load_chr(0x0c00));
+        lz.store_prg(0xfff3, 0x44);
+        assert_eq!(0x44, lz.load_chr(0x0c00));
+    }
+
+    #[test]
+    fn test_bandai_irq() {
+        let mut m = bandai(16, 5);
+        m.store_prg(0x800b, 3);
+        m.store_prg(0x800c, 0);
+        assert_eq!(0, m.irq_counter);
+        m.store_prg(0x800a, 1);
+
+        m.tick(3);
+        assert!(!m.irq());
+        m.tick(1);
+        assert!(m.irq());
+
+        m.store_prg(0x800a, 0);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn test_bandai_eeprom() {
+        let mut m = bandai(159, 0);
+        let lines = |m: &mut Bandai, scl: u8, sda: u8| m.store_prg(0x800d, (scl << 5) | (sda << 6));
+
+        // Start, then the X24C01 address byte for a write to $03, LSB first
+        lines(&mut m, 1, 1);
+        lines(&mut m, 1, 0);
+        for bit in (0..8).map(|b| (0x03 >> b) & 1) {
+            lines(&mut m, 0, bit);
+            lines(&mut m, 1, bit);
+            lines(&mut m, 0, bit);
+        }
+
+        // Acknowledged on $6000-$7FFF bit 4
+        assert_eq!(0, m.load_prg(0x6000) & 0x10);
+
+        let mut saved = m.battery_ram().unwrap();
+        assert_eq!(128, saved.len());
+        saved[5] = 0x55;
+        m.restore_battery_ram(&saved);
+        assert_eq!(0x55, m.eeprom.as_ref().unwrap().data()[5]);
+
+        assert!(bandai(16, 4).battery_ram().is_none());
+
+        // Without a submapper, only the battery bit says there's an EEPROM
+        assert!(bandai(16, 0).battery_ram().is_none());
+        let prg = vec![0; 0x8000];
+        let m = Bandai::new(prg, vec![], 16, 0, true);
+        assert_eq!(256, m.battery_ram().unwrap().len());
+    }
+}
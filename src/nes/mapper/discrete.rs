@@ -0,0 +1,363 @@
+use super::*;
+
+/**
+ * Boards built from discrete logic: a latch or two holding bank numbers, and
+ * nothing else.
+ *
+ * Mapper 66, GxROM:       $8000-$FFFF ..PP..CC, 32kB PRG and 8kB CHR.
+ * Mapper 11, Color Dreams: $8000-$FFFF CCCC..PP, 32kB PRG and 8kB CHR.
+ * Mapper 71, Camerica:    $C000-$FFFF 16kB PRG at $8000, last bank fixed at
+ *                         $C000. Fire Hawk also picks a single screen with
+ *                         bit 4 of $8000-$9FFF. NES 2.0 marks it as submapper
+ *                         1, and iNES headers can't say, so they get the
+ *                         register too: the other games never write there.
+ * Mapper 34, BNROM:       $8000-$FFFF 32kB PRG, 8kB CHR RAM.
+ * Mapper 34, NINA-001:    $7FFD 32kB PRG, $7FFE and $7FFF 4kB CHR,
+ *                         with 8kB PRG RAM underneath.
+ * Mapper 13, CPROM:       $8000-$FFFF ......CC, 4kB CHR RAM bank at $1000
+ *                         out of 16kB, the first 4kB fixed at $0000.
+ *
+ * The latches on most of these boards share the data bus with the PRG ROM,
+ * which drives it at the same time as the CPU, and only bits both agree on
+ * reach the latch. Games write to a ROM byte holding the same value to avoid
+ * that.
+ */
+pub struct Discrete {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Option<Vec<u8>>,
+    board: DiscreteBoard,
+    mirror: MirrorDirection,
+
+    prg_bank: u8,
+    /// 8kB banks use the first one, 4kB banks one per pattern table
+    chr_banks: [u8; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiscreteBoard {
+    Gxrom,
+    ColorDreams,
+    /// Fire Hawk has mirroring control, the other Camerica games don't
+    Camerica {
+        fire_hawk: bool,
+    },
+    Bnrom,
+    Nina001,
+    Cprom,
+}
+
+impl DiscreteBoard {
+    /// NES 2.0 submappers say which board it is, otherwise guess from the ROM
+    pub fn detect(mapper: u8, submapper: u8, is_nes2: bool, chr_size: usize) -> Option<Self> {
+        match (mapper, submapper) {
+            (66, _) => Some(DiscreteBoard::Gxrom),
+            (11, _) => Some(DiscreteBoard::ColorDreams),
+            (71, 1) => Some(DiscreteBoard::Camerica { fire_hawk: true }),
+            (71, _) => Some(DiscreteBoard::Camerica {
+                fire_hawk: !is_nes2,
+            }),
+            (34, 1) => Some(DiscreteBoard::Nina001),
+            (34, 2) => Some(DiscreteBoard::Bnrom),
+            // BNROM only ever came with CHR RAM
+            (34, _) if chr_size > 0x2000 => Some(DiscreteBoard::Nina001),
+            (34, _) => Some(DiscreteBoard::Bnrom),
+            (13, _) => Some(DiscreteBoard::Cprom),
+            _ => None,
+        }
+    }
+
+    fn bus_conflicts(self) -> bool {
+        !matches!(
+            self,
+            DiscreteBoard::Camerica { .. } | DiscreteBoard::Nina001
+        )
+    }
+}
+
+impl Discrete {
+    const CPROM_CHR_SIZE: usize = 0x4000;
+
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, board: DiscreteBoard, mirror: MirrorDirection) -> Self {
+        let (chr, chr_is_ram) = match (board, chr.is_empty()) {
+            (DiscreteBoard::Cprom, true) => (vec![0; Self::CPROM_CHR_SIZE], true),
+            _ => chr_or_ram(chr),
+        };
+
+        let ram = match board {
+            DiscreteBoard::Nina001 => Some(vec![0; PRG_RAM_SIZE]),
+            _ => None,
+        };
+
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram,
+            board,
+            mirror,
+            prg_bank: 0,
+            chr_banks: [0; 2],
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        match self.board {
+            DiscreteBoard::Camerica { .. } => {
+                let bank = match addr {
+                    0x8000..=0xbfff => self.prg_bank as usize,
+                    _ => self.prg.len() / 0x4000 - 1,
+                };
+                bank_offset(&self.prg, 0x4000, bank, addr as usize)
+            }
+            _ => bank_offset(&self.prg, 0x8000, self.prg_bank as usize, addr as usize),
+        }
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let table = (addr as usize >> 12) & 1;
+        match self.board {
+            DiscreteBoard::Nina001 => bank_offset(
+                &self.chr,
+                0x1000,
+                self.chr_banks[table] as usize,
+                addr as usize,
+            ),
+            DiscreteBoard::Cprom => {
+                let bank = [0, self.chr_banks[0]][table];
+                bank_offset(&self.chr, 0x1000, bank as usize, addr as usize)
+            }
+            _ => bank_offset(&self.chr, 0x2000, self.chr_banks[0] as usize, addr as usize),
+        }
+    }
+
+    fn write_latch(&mut self, addr: Addr, v: u8) {
+        let v = match self.board.bus_conflicts() {
+            true => v & self.prg[self.prg_offset(addr)],
+            false => v,
+        };
+
+        match (self.board, addr) {
+            (DiscreteBoard::Gxrom, _) => {
+                self.prg_bank = (v >> 4) & 0b11;
+                self.chr_banks[0] = v & 0b11;
+            }
+            (DiscreteBoard::ColorDreams, _) => {
+                self.prg_bank = v & 0b11;
+                self.chr_banks[0] = v >> 4;
+            }
+            (DiscreteBoard::Camerica { fire_hawk: true }, 0x8000..=0x9fff) => {
+                self.mirror = match v & 0x10 {
+                    0 => MirrorDirection::SingleScreenLower,
+                    _ => MirrorDirection::SingleScreenUpper,
+                };
+            }
+            (DiscreteBoard::Camerica { .. }, 0xc000..=0xffff) => self.prg_bank = v,
+            (DiscreteBoard::Bnrom, _) => self.prg_bank = v,
+            (DiscreteBoard::Cprom, _) => self.chr_banks[0] = v & 0b11,
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match (addr, &self.ram) {
+            (0x6000..=0x7fff, Some(ram)) => ram[(addr - 0x6000) as usize],
+            (0x8000..=0xffff, _) => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        if let (0x6000..=0x7fff, Some(ram)) = (addr, &mut self.ram) {
+            ram[(addr - 0x6000) as usize] = v;
+        }
+
+        match (self.board, addr) {
+            (DiscreteBoard::Nina001, 0x7ffd) => self.prg_bank = v & 1,
+            (DiscreteBoard::Nina001, 0x7ffe) => self.chr_banks[0] = v & 0x0f,
+            (DiscreteBoard::Nina001, 0x7fff) => self.chr_banks[1] = v & 0x0f,
+            (DiscreteBoard::Nina001, _) => (),
+            (_, 0x8000..=0xffff) => self.write_latch(addr, v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM made of `count` banks of `size` bytes, each filled with its bank number
+    fn rom(count: usize, size: usize) -> Vec<u8> {
+        (0..count).flat_map(|b| vec![b as u8; size]).collect()
+    }
+
+    /// PRG ROM where every byte is $FF, so bus conflicts never get in the way
+    fn open_prg(size: usize) -> Vec<u8> {
+        vec![0xff; size]
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let mut m = Discrete::new(
+            rom(4, 0x8000),
+            rom(4, 0x2000),
+            DiscreteBoard::Gxrom,
+            Default::default(),
+        );
+        m.store_prg(0x8000, 0x21);
+        // Bus conflict: bank 0 is all zeroes, so nothing gets through
+        assert_eq!(0, m.load_prg(0x8000));
+
+        // A byte of $FF in bank 3 lets it all through
+        m.prg[3 * 0x8000 + 0x10] = 0xff;
+        m.prg_bank = 3;
+        m.store_prg(0x8010, 0x21);
+        assert_eq!(2, m.load_prg(0x8000));
+        assert_eq!(1, m.load_chr(0x1000));
+    }
+
+    #[test]
+    fn test_color_dreams() {
+        let mut m = Discrete::new(
+            open_prg(0x20000),
+            rom(16, 0x2000),
+            DiscreteBoard::ColorDreams,
+            Default::default(),
+        );
+        m.store_prg(0xffff, 0x72);
+        assert_eq!(2, m.prg_bank);
+        assert_eq!(7, m.load_chr(0x0000));
+
+        // Bits cleared by the ROM never reach the latch
+        let offset = m.prg_offset(0x8000);
+        m.prg[offset] = 0x0f;
+        m.store_prg(0x8000, 0x73);
+        assert_eq!(3, m.prg_bank);
+        assert_eq!(0, m.load_chr(0x0000));
+    }
+
+    #[test]
+    fn test_camerica() {
+        let mut m = Discrete::new(
+            rom(8, 0x4000),
+            vec![],
+            DiscreteBoard::Camerica { fire_hawk: false },
+            MirrorDirection::Vertical,
+        );
+        assert_eq!(7, m.load_prg(0xc000));
+
+        // No bus conflicts
+        m.store_prg(0xc000, 5);
+        assert_eq!(5, m.load_prg(0x8000));
+        assert_eq!(7, m.load_prg(0xffff));
+
+        m.store_prg(0x9000, 0x10);
+        assert_eq!(MirrorDirection::Vertical, m.mirror());
+    }
+
+    #[test]
+    fn test_camerica_fire_hawk() {
+        let board = DiscreteBoard::detect(71, 1, true, 0).unwrap();
+        let mut m = Discrete::new(rom(8, 0x4000), vec![], board, MirrorDirection::Vertical);
+
+        m.store_prg(0x9000, 0x10);
+        assert_eq!(MirrorDirection::SingleScreenUpper, m.mirror());
+        m.store_prg(0x9000, 0x00);
+        assert_eq!(MirrorDirection::SingleScreenLower, m.mirror());
+
+        // iNES headers can't tell it apart from the other Camerica games
+        let ines = DiscreteBoard::detect(71, 0, false, 0);
+        assert_eq!(Some(DiscreteBoard::Camerica { fire_hawk: true }), ines);
+        let nes2 = DiscreteBoard::detect(71, 0, true, 0);
+        assert_eq!(Some(DiscreteBoard::Camerica { fire_hawk: false }), nes2);
+    }
+
+    #[test]
+    fn test_bnrom() {
+        assert_eq!(
+            Some(DiscreteBoard::Bnrom),
+            DiscreteBoard::detect(34, 0, false, 0)
+        );
+
+        let mut m = Discrete::new(
+            open_prg(0x20000),
+            vec![],
+            DiscreteBoard::Bnrom,
+            Default::default(),
+        );
+        m.prg[3 * 0x8000] = 0x33;
+        m.store_prg(0x8000, 3);
+        assert_eq!(0x33, m.load_prg(0x8000));
+
+        // CHR RAM
+        m.store_chr(0x1234, 0x56);
+        assert_eq!(0x56, m.load_chr(0x1234));
+    }
+
+    #[test]
+    fn test_nina001() {
+        assert_eq!(
+            Some(DiscreteBoard::Nina001),
+            DiscreteBoard::detect(34, 0, false, 0x10000)
+        );
+
+        let mut m = Discrete::new(
+            rom(2, 0x8000),
+            rom(16, 0x1000),
+            DiscreteBoard::Nina001,
+            Default::default(),
+        );
+        m.store_prg(0x7ffd, 1);
+        m.store_prg(0x7ffe, 9);
+        m.store_prg(0x7fff, 4);
+        assert_eq!(1, m.load_prg(0x8000));
+        assert_eq!(9, m.load_chr(0x0000));
+        assert_eq!(4, m.load_chr(0x1000));
+
+        // The registers sit on top of PRG RAM
+        m.store_prg(0x6000, 0xab);
+        assert_eq!(0xab, m.load_prg(0x6000));
+        assert_eq!(4, m.load_prg(0x7fff));
+    }
+
+    #[test]
+    fn test_cprom() {
+        let mut m = Discrete::new(
+            open_prg(0x8000),
+            vec![],
+            DiscreteBoard::Cprom,
+            Default::default(),
+        );
+        m.store_chr(0x0000, 0x11);
+        m.store_prg(0x8000, 1);
+        m.store_chr(0x1000, 0x22);
+        m.store_prg(0x8000, 2);
+        m.store_chr(0x1000, 0x33);
+
+        assert_eq!(0x11, m.load_chr(0x0000));
+        assert_eq!(0x33, m.load_chr(0x1000));
+        m.store_prg(0x8000, 0);
+        assert_eq!(0x11, m.load_chr(0x1000));
+        m.store_prg(0x8000, 1);
+        assert_eq!(0x22, m.load_chr(0x1000));
+    }
+}
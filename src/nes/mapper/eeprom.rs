@@ -0,0 +1,300 @@
+/**
+ * Xicor X24C01 and X24C02 serial EEPROMs, driven bit by bit over I2C.
+ *
+ * The host drives the clock (SCL) and data (SDA) lines directly. Data bits
+ * are sampled when SCL rises, and the chip changes its own output while SCL
+ * is low. Bringing SDA low while SCL is high is a start condition, bringing
+ * it high is a stop condition.
+ *
+ * The X24C02 speaks standard I2C: a device address byte ($A0 or $A1), a word
+ * address, then data, most significant bit first. The X24C01 skips the device
+ * address, takes a 7 bit word address and the read bit in the first byte, and
+ * sends everything least significant bit first.
+ *
+ * Every byte is acknowledged by pulling SDA low on the ninth clock.
+ */
+pub struct Eeprom {
+    chip: EepromChip,
+    data: Vec<u8>,
+
+    scl: bool,
+    sda: bool,
+    phase: Phase,
+    shift: u8,
+    /// Clocks into the current byte, 8 is the acknowledge clock
+    bit: u8,
+    /// Acknowledge the byte just received
+    acking: bool,
+    address: u8,
+    /// Level the chip puts on SDA
+    output: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EepromChip {
+    X24C01,
+    X24C02,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Idle,
+    Device,
+    Address,
+    Write,
+    Read,
+}
+
+impl Eeprom {
+    pub fn new(chip: EepromChip) -> Self {
+        let size = match chip {
+            EepromChip::X24C01 => 128,
+            EepromChip::X24C02 => 256,
+        };
+
+        Self {
+            chip,
+            data: vec![0; size],
+            scl: false,
+            sda: false,
+            phase: Phase::Idle,
+            shift: 0,
+            bit: 0,
+            acking: false,
+            address: 0,
+            output: true,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Level the chip drives on SDA, high when it lets go of the line
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    pub fn set_lines(&mut self, scl: bool, sda: bool) {
+        match (self.scl, scl) {
+            (true, true) if sda != self.sda => match sda {
+                false => self.start(),
+                true => self.stop(),
+            },
+            (false, true) => self.clock_rise(sda),
+            (true, false) => self.clock_fall(),
+            _ => (),
+        }
+
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn start(&mut self) {
+        self.phase = match self.chip {
+            EepromChip::X24C01 => Phase::Address,
+            EepromChip::X24C02 => Phase::Device,
+        };
+        self.shift = 0;
+        self.bit = 0;
+        self.acking = false;
+        self.output = true;
+    }
+
+    fn stop(&mut self) {
+        self.phase = Phase::Idle;
+        self.output = true;
+    }
+
+    fn lsb_first(&self) -> bool {
+        self.chip == EepromChip::X24C01
+    }
+
+    fn clock_rise(&mut self, sda: bool) {
+        if self.phase == Phase::Idle {
+            return;
+        }
+
+        if self.bit < 8 {
+            if self.phase != Phase::Read {
+                self.shift = match self.lsb_first() {
+                    true => self.shift | ((sda as u8) << self.bit),
+                    false => (self.shift << 1) | sda as u8,
+                };
+            }
+
+            self.bit += 1;
+            if self.bit == 8 && self.phase != Phase::Read {
+                self.receive(self.shift);
+            }
+            return;
+        }
+
+        // The acknowledge clock. While reading it's the host acknowledging,
+        // and only then does it want another byte
+        if self.phase == Phase::Read && !self.acking {
+            match sda {
+                false => self.address = self.next_address(),
+                true => self.phase = Phase::Idle,
+            }
+        }
+        self.bit = 0;
+        self.shift = 0;
+        self.acking = false;
+    }
+
+    fn clock_fall(&mut self) {
+        self.output = match (self.phase, self.bit) {
+            (_, 8) if self.acking => false,
+            (Phase::Read, 0..=7) => {
+                let bit = match self.lsb_first() {
+                    true => self.bit,
+                    false => 7 - self.bit,
+                };
+                (self.data[self.address as usize] >> bit) & 1 != 0
+            }
+            _ => true,
+        };
+    }
+
+    fn receive(&mut self, byte: u8) {
+        self.acking = true;
+        self.phase = match (self.phase, self.chip) {
+            (Phase::Device, _) if (byte & 0xf0) != 0xa0 => {
+                self.acking = false;
+                Phase::Idle
+            }
+            (Phase::Device, _) if (byte & 1) != 0 => Phase::Read,
+            (Phase::Device, _) => Phase::Address,
+            (Phase::Address, EepromChip::X24C01) => {
+                self.address = byte & 0x7f;
+                match byte >> 7 {
+                    0 => Phase::Write,
+                    _ => Phase::Read,
+                }
+            }
+            (Phase::Address, EepromChip::X24C02) => {
+                self.address = byte;
+                Phase::Write
+            }
+            (_, _) => {
+                self.data[self.address as usize] = byte;
+                self.address = self.next_address();
+                Phase::Write
+            }
+        };
+    }
+
+    fn next_address(&self) -> u8 {
+        ((self.address as usize + 1) % self.data.len()) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drives the lines like a game would, one whole transaction at a time
+    struct Host<'a>(&'a mut Eeprom);
+
+    impl Host<'_> {
+        fn start(&mut self) {
+            self.0.set_lines(false, true);
+            self.0.set_lines(true, true);
+            self.0.set_lines(true, false);
+            self.0.set_lines(false, false);
+        }
+
+        fn stop(&mut self) {
+            self.0.set_lines(false, false);
+            self.0.set_lines(true, false);
+            self.0.set_lines(true, true);
+        }
+
+        /// Clocks one bit out, and returns what the chip put on SDA
+        fn clock(&mut self, sda: bool) -> bool {
+            self.0.set_lines(false, sda);
+            self.0.set_lines(true, sda);
+            let level = self.0.output() && sda;
+            self.0.set_lines(false, sda);
+            level
+        }
+
+        fn order(&self) -> Vec<u8> {
+            match self.0.chip {
+                EepromChip::X24C01 => (0..8).collect(),
+                EepromChip::X24C02 => (0..8).rev().collect(),
+            }
+        }
+
+        /// Sends a byte and returns whether it was acknowledged
+        fn write(&mut self, byte: u8) -> bool {
+            for b in self.order() {
+                self.clock((byte >> b) & 1 != 0);
+            }
+            !self.clock(true)
+        }
+
+        fn read(&mut self, ack: bool) -> u8 {
+            let byte = self
+                .order()
+                .into_iter()
+                .fold(0, |acc, b| acc | ((self.clock(true) as u8) << b));
+            self.clock(!ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn test_x24c02_write_read() {
+        let mut eeprom = Eeprom::new(EepromChip::X24C02);
+        let mut host = Host(&mut eeprom);
+
+        host.start();
+        assert!(host.write(0xa0));
+        assert!(host.write(0x10));
+        assert!(host.write(0x12));
+        assert!(host.write(0x34));
+        host.stop();
+
+        // Set the address with a dummy write, then read from it
+        host.start();
+        assert!(host.write(0xa0));
+        assert!(host.write(0x10));
+        host.start();
+        assert!(host.write(0xa1));
+        assert_eq!(0x12, host.read(true));
+        assert_eq!(0x34, host.read(false));
+        host.stop();
+
+        // Other devices on the bus are not acknowledged
+        host.start();
+        assert!(!host.write(0xb0));
+        host.stop();
+
+        assert_eq!([0x12, 0x34], eeprom.data()[0x10..0x12]);
+    }
+
+    #[test]
+    fn test_x24c01_write_read() {
+        let mut eeprom = Eeprom::new(EepromChip::X24C01);
+        let mut host = Host(&mut eeprom);
+
+        host.start();
+        assert!(host.write(0x05));
+        assert!(host.write(0xc3));
+        host.stop();
+
+        host.start();
+        assert!(host.write(0x80 | 0x05));
+        assert_eq!(0xc3, host.read(false));
+        host.stop();
+
+        assert_eq!(0xc3, eeprom.data()[0x05]);
+    }
+}
@@ -0,0 +1,453 @@
+use super::fds_audio::FdsAudio;
+use super::*;
+use crate::nes::disk::FdsImage;
+
+use std::cell::Cell;
+
+/**
+ * Famicom Disk System: the RAM adapter plugged into the cartridge slot, and
+ * the disk drive behind it.
+ *
+ * PRG:  32kB RAM at $6000-$DFFF, the 8kB BIOS at $E000.
+ * CHR:  8kB RAM.
+ * IRQ:  a 16 bit timer, and the drive asking for the next byte.
+ *
+ *   $4020 $4021  timer reload value, low and high
+ *   $4022        ......ER  timer enable, repeat
+ *   $4023        ......SD  enable sound registers, disk registers
+ *   $4024        byte to write to the disk
+ *   $4025        IC.RMWTM  byte IRQ, CRC, ready, mirroring, read mode,
+ *                          transfer reset, motor
+ *   $4030        read: E.....BT  end of head, byte transferred, timer IRQ
+ *   $4031        read: byte read from the disk
+ *   $4032        read: .....WRI  write protected, not ready, no disk
+ *   $4033        read: B.......  battery good
+ *   $4040-$4092  audio, see `FdsAudio`
+ *
+ * The drive streams the side one byte every 150 CPU cycles, gaps and all, so
+ * the BIOS sees the disk the same way it would on hardware.
+ */
+pub struct Fds {
+    bios: Vec<u8>,
+    ram: Vec<u8>,
+    chr: Vec<u8>,
+    image: FdsImage,
+    /// A side written to since the image was loaded
+    modified: bool,
+
+    disk_enabled: bool,
+    sound_enabled: bool,
+    mirror: MirrorDirection,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: Cell<bool>,
+
+    drive: FdsDrive,
+    audio: FdsAudio,
+}
+
+#[derive(Default)]
+struct FdsDrive {
+    side: Option<usize>,
+    /// Side going in once the previous one has been out for a while
+    next_side: Option<usize>,
+    swap_delay: usize,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    ready: bool,
+    irq_enabled: bool,
+
+    position: usize,
+    delay: usize,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+
+    read_data: Cell<u8>,
+    write_data: u8,
+    transfer_complete: Cell<bool>,
+    irq: Cell<bool>,
+}
+
+impl Fds {
+    pub const BIOS_SIZE: usize = 0x2000;
+    const RAM_SIZE: usize = 0x8000;
+    /// CPU cycles to read or write a byte, at 96.4kbit/s
+    const BYTE_CYCLES: usize = 150;
+    /// CPU cycles for the head to get back to the start of the disk
+    const REWIND_CYCLES: usize = 50000;
+    /// How long a disk stays out while the sides are swapped, about a second
+    const SWAP_CYCLES: usize = 1_800_000;
+
+    pub fn new(bios: Vec<u8>, image: FdsImage) -> Self {
+        Self {
+            bios,
+            ram: vec![0; Self::RAM_SIZE],
+            chr: vec![0; CHR_RAM_SIZE],
+            image,
+            modified: false,
+            disk_enabled: false,
+            sound_enabled: false,
+            mirror: MirrorDirection::Horizontal,
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: Cell::new(false),
+            drive: FdsDrive {
+                side: Some(0),
+                end_of_head: true,
+                ..Default::default()
+            },
+            audio: FdsAudio::new(),
+        }
+    }
+
+    fn load_register(&self, addr: Addr) -> u8 {
+        let drive = &self.drive;
+        match addr {
+            0x4030 => {
+                let v = self.timer_irq.get() as u8
+                    | (drive.transfer_complete.get() as u8) << 1
+                    | (drive.end_of_head as u8) << 6;
+                self.timer_irq.set(false);
+                drive.transfer_complete.set(false);
+                drive.irq.set(false);
+                v
+            }
+            0x4031 => {
+                drive.transfer_complete.set(false);
+                drive.irq.set(false);
+                drive.read_data.get()
+            }
+            0x4032 => {
+                let no_disk = drive.side.is_none();
+                (open_bus(addr) & 0xf8)
+                    | no_disk as u8
+                    | ((no_disk || !drive.scanning) as u8) << 1
+                    | (no_disk as u8) << 2
+            }
+            0x4033 => 0x80,
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_register(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xff00) | v as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00ff) | ((v as u16) << 8),
+            0x4022 => {
+                self.timer_repeat = (v & 1) != 0;
+                self.timer_enabled = (v & 2) != 0 && self.disk_enabled;
+                match self.timer_enabled {
+                    true => self.timer_counter = self.timer_reload,
+                    false => self.timer_irq.set(false),
+                }
+            }
+            0x4023 => {
+                self.disk_enabled = (v & 1) != 0;
+                self.sound_enabled = (v & 2) != 0;
+                if !self.disk_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq.set(false);
+                    self.drive.irq.set(false);
+                }
+            }
+            0x4024 if self.disk_enabled => {
+                self.drive.write_data = v;
+                self.drive.transfer_complete.set(false);
+                self.drive.irq.set(false);
+            }
+            0x4025 if self.disk_enabled => {
+                let drive = &mut self.drive;
+                drive.motor_on = (v & 0x01) != 0;
+                drive.reset_transfer = (v & 0x02) != 0;
+                drive.read_mode = (v & 0x04) != 0;
+                drive.crc_control = (v & 0x10) != 0;
+                drive.ready = (v & 0x40) != 0;
+                drive.irq_enabled = (v & 0x80) != 0;
+                drive.irq.set(false);
+                self.mirror = match v & 0x08 {
+                    0 => MirrorDirection::Vertical,
+                    _ => MirrorDirection::Horizontal,
+                };
+            }
+            _ => (),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+
+        match self.timer_counter {
+            0 => {
+                self.timer_irq.set(true);
+                self.timer_counter = self.timer_reload;
+                self.timer_enabled = self.timer_repeat;
+            }
+            _ => self.timer_counter -= 1,
+        }
+    }
+
+    /// Moves the disk under the head, one CPU cycle at a time
+    fn clock_drive(&mut self) {
+        let drive = &mut self.drive;
+        if drive.swap_delay > 0 {
+            drive.swap_delay -= 1;
+            if drive.swap_delay == 0 {
+                drive.side = drive.next_side.take();
+            }
+            return;
+        }
+
+        let side = match (drive.side, drive.motor_on) {
+            (Some(side), true) => side,
+            _ => {
+                drive.end_of_head = true;
+                drive.scanning = false;
+                return;
+            }
+        };
+
+        if drive.reset_transfer && !drive.scanning {
+            return;
+        }
+
+        if drive.end_of_head {
+            drive.delay = Self::REWIND_CYCLES;
+            drive.end_of_head = false;
+            drive.position = 0;
+            drive.gap_ended = false;
+            return;
+        }
+
+        if drive.delay > 0 {
+            drive.delay -= 1;
+            return;
+        }
+
+        drive.scanning = true;
+        let data = &mut self.image.sides[side].data;
+        if drive.read_mode {
+            let v = data[drive.position];
+            let mut irq = drive.irq_enabled;
+            if !drive.ready {
+                drive.gap_ended = false;
+            } else if v != 0 && !drive.gap_ended {
+                // The start mark ends the gap, without an IRQ of its own
+                drive.gap_ended = true;
+                irq = false;
+            }
+
+            if drive.gap_ended {
+                drive.transfer_complete.set(true);
+                drive.read_data.set(v);
+                if irq {
+                    drive.irq.set(true);
+                }
+            }
+        } else {
+            let mut v = 0;
+            if !drive.crc_control {
+                drive.transfer_complete.set(true);
+                v = drive.write_data;
+                if drive.irq_enabled {
+                    drive.irq.set(true);
+                }
+            }
+            // CRCs are never checked, so they go down as zeroes
+            if !drive.ready || drive.crc_control {
+                v = 0;
+            }
+            data[drive.position] = v;
+            drive.gap_ended = false;
+            self.modified = true;
+        }
+
+        drive.position += 1;
+        match drive.position >= data.len() {
+            true => drive.motor_on = false,
+            false => drive.delay = Self::BYTE_CYCLES,
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x4030..=0x4033 if self.disk_enabled => self.load_register(addr),
+            0x4040..=0x4097 if self.sound_enabled => {
+                let v = self.audio.load(addr).unwrap_or(0);
+                (open_bus(addr) & 0xc0) | v
+            }
+            0x6000..=0xdfff => self.ram[(addr - 0x6000) as usize],
+            0xe000..=0xffff => self.bios[(addr - 0xe000) as usize % self.bios.len()],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x4020..=0x402f => self.store_register(addr, v),
+            0x4040..=0x4097 if self.sound_enabled => self.audio.store(addr, v),
+            0x6000..=0xdfff => self.ram[(addr - 0x6000) as usize] = v,
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[addr as usize % CHR_RAM_SIZE]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        self.chr[addr as usize % CHR_RAM_SIZE] = v;
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.clock_timer();
+            self.clock_drive();
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq.get() || self.drive.irq.get()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    /// The whole disk image, once the game has written to it
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.modified {
+            true => Some(self.image.to_bytes()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        match FdsImage::from_bytes(data) {
+            Ok(image) if image.sides.len() == self.image.sides.len() => {
+                self.image = image;
+                self.modified = true;
+            }
+            _ => warn!("Saved disk doesn't match the loaded one, ignoring it"),
+        }
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.image.sides.len()
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        self.drive.side
+    }
+
+    /// Takes the disk out, and puts `side` in after a delay long enough for
+    /// the BIOS to notice the swap
+    fn insert_disk(&mut self, side: Option<usize>) {
+        let side = side.filter(|&s| s < self.image.sides.len());
+        self.drive.side = None;
+        self.drive.next_side = side;
+        self.drive.swap_delay = match side {
+            Some(_) => Self::SWAP_CYCLES,
+            None => 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fds() -> Fds {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0);
+        side.extend([2, 0]);
+        side.resize(FdsImage::SIDE_SIZE, 0);
+        let image = FdsImage::from_bytes(&[side.clone(), side].concat()).unwrap();
+
+        let mut fds = Fds::new(vec![0xea; Fds::BIOS_SIZE], image);
+        fds.store_prg(0x4023, 0x03);
+        fds
+    }
+
+    #[test]
+    fn test_fds_timer_irq() {
+        let mut m = fds();
+        m.store_prg(0x4020, 10);
+        m.store_prg(0x4021, 0);
+        m.store_prg(0x4022, 0x03);
+
+        m.tick(10);
+        assert!(!m.irq());
+        m.tick(1);
+        assert!(m.irq());
+        assert_eq!(0x01, m.load_prg(0x4030) & 0x01);
+        assert!(!m.irq());
+
+        // Repeats
+        m.tick(11);
+        assert!(m.irq());
+    }
+
+    #[test]
+    fn test_fds_read_disk() {
+        let mut m = fds();
+        assert_eq!(0, m.load_prg(0x4032) & 0x01);
+
+        // Motor on, read mode, then wait for the head to reach the start
+        m.store_prg(0x4025, 0x2f);
+        m.tick(1);
+        m.store_prg(0x4025, 0xe5);
+        m.tick(Fds::REWIND_CYCLES + 1);
+
+        // The lead in gap goes by without a byte, then the start mark is
+        // skipped and the disk info block comes in
+        let mut bytes = vec![];
+        while bytes.len() < 15 {
+            m.tick(1);
+            if m.irq() {
+                bytes.push(m.load_prg(0x4031));
+            }
+        }
+        assert_eq!(b"\x01*NINTENDO-HVC*", &bytes[..]);
+        assert_eq!(0, m.load_prg(0x4032) & 0x02);
+    }
+
+    #[test]
+    fn test_fds_write_and_swap() {
+        let mut m = fds();
+        m.store_prg(0x6000, 0x12);
+        m.store_prg(0xdfff, 0x34);
+        assert_eq!([0x12, 0x34], [m.load_prg(0x6000), m.load_prg(0xdfff)]);
+        assert_eq!(0xea, m.load_prg(0xfffc));
+        assert!(m.battery_ram().is_none());
+
+        // Write mode, not ready: the head writes gap
+        m.store_prg(0x4025, 0x21);
+        m.tick(Fds::REWIND_CYCLES + 2);
+        assert!(m.battery_ram().is_some());
+
+        m.insert_disk(Some(1));
+        assert_eq!(None, m.disk_side());
+        assert_eq!(0x01, m.load_prg(0x4032) & 0x01);
+        m.tick(Fds::SWAP_CYCLES);
+        assert_eq!(Some(1), m.disk_side());
+        assert_eq!(2, m.disk_sides());
+    }
+}
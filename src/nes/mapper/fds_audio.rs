@@ -0,0 +1,324 @@
+use crate::nes::memory::Addr;
+
+/**
+ * FDS expansion audio: one 64 step wavetable channel whose pitch is bent by a
+ * second wavetable, the modulator. Both have a volume envelope, clocked
+ * at the CPU rate.
+ *
+ *   $4040-$407F  wavetable, 6 bit samples, writable while $4089 bit 7 is set
+ *   $4080        volume envelope: E D SSSSSS  off, increase, speed or gain
+ *   $4082 $4083  wave pitch low, high: H E .. PPPP  halt wave, halt envelopes
+ *   $4084        modulator envelope, as $4080
+ *   $4085        modulator counter, 7 bit signed
+ *   $4086 $4087  modulator pitch low, high: H ... PPPP  halt modulator
+ *   $4088        modulator table, appended two entries at a time while halted
+ *   $4089        W ..... VV  wavetable write enable, master volume
+ *   $408A        envelope speed multiplier
+ *   $4090 $4092  read back the volume and modulator gains
+ */
+pub(super) struct FdsAudio {
+    wave: [u8; 64],
+    wave_position: u8,
+    wave_pitch: u16,
+    wave_accumulator: u16,
+    wave_halted: bool,
+    wave_writable: bool,
+    envelopes_halted: bool,
+    master_volume: u8,
+    envelope_speed: u8,
+
+    volume: FdsEnvelope,
+    modulator: FdsModulator,
+}
+
+#[derive(Default)]
+struct FdsEnvelope {
+    speed: u8,
+    increase: bool,
+    off: bool,
+    gain: u8,
+    timer: u32,
+}
+
+struct FdsModulator {
+    envelope: FdsEnvelope,
+    table: [u8; 64],
+    position: u8,
+    /// 7 bit signed counter the table steps are added to
+    counter: i8,
+    pitch: u16,
+    accumulator: u16,
+    halted: bool,
+    /// Pitch offset for the wave channel
+    output: i32,
+}
+
+impl FdsEnvelope {
+    fn write(&mut self, v: u8, master_speed: u8) {
+        self.speed = v & 0x3f;
+        self.increase = (v & 0x40) != 0;
+        self.off = (v & 0x80) != 0;
+        if self.off {
+            self.gain = self.speed;
+        }
+        self.reset(master_speed);
+    }
+
+    fn reset(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    /// Returns true when the gain got a step
+    fn clock(&mut self, master_speed: u8) -> bool {
+        if self.off || master_speed == 0 {
+            return false;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.reset(master_speed);
+        match self.increase {
+            true if self.gain < 32 => self.gain += 1,
+            false if self.gain > 0 => self.gain -= 1,
+            _ => (),
+        }
+        true
+    }
+}
+
+impl Default for FdsModulator {
+    fn default() -> Self {
+        Self {
+            envelope: FdsEnvelope::default(),
+            table: [0; 64],
+            position: 0,
+            counter: 0,
+            pitch: 0,
+            accumulator: 0,
+            halted: true,
+            output: 0,
+        }
+    }
+}
+
+impl FdsModulator {
+    /// Added to the counter for each table entry, 4 resets it instead
+    const STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+    fn set_counter(&mut self, v: i32) {
+        // Wraps around as a 7 bit signed value
+        self.counter = (((v + 64) & 0x7f) - 64) as i8;
+    }
+
+    fn write_table(&mut self, v: u8) {
+        if self.halted {
+            self.table[self.position as usize] = v & 0b111;
+            self.table[(self.position as usize + 1) & 0x3f] = v & 0b111;
+            self.position = (self.position + 2) & 0x3f;
+        }
+    }
+
+    /// Returns true when the modulator moved on to its next table entry
+    fn clock(&mut self) -> bool {
+        if self.halted || self.pitch == 0 {
+            return false;
+        }
+
+        let (accumulator, overflow) = self.accumulator.overflowing_add(self.pitch);
+        self.accumulator = accumulator;
+        if !overflow {
+            return false;
+        }
+
+        let step = self.table[self.position as usize];
+        match step {
+            4 => self.set_counter(0),
+            _ => self.set_counter(self.counter as i32 + Self::STEPS[step as usize] as i32),
+        }
+        self.position = (self.position + 1) & 0x3f;
+        true
+    }
+
+    /// The pitch bend for a wave pitch, worked out the way the chip rounds it
+    fn update_output(&mut self, wave_pitch: u16) {
+        let mut temp = self.counter as i32 * self.envelope.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && (temp & 0x80) == 0 {
+            temp += if self.counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= wave_pitch as i32;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        self.output = temp;
+    }
+}
+
+impl FdsAudio {
+    /// Gain multipliers for the four master volume settings, out of 36
+    const MASTER_VOLUME: [u32; 4] = [36, 24, 17, 14];
+    /// The FDS at full volume is about 2.4 times as loud as a 2A03 pulse
+    const LEVEL: f32 = 2.4 * 0.1494 / 63.0;
+
+    pub fn new() -> Self {
+        Self {
+            wave: [0; 64],
+            wave_position: 0,
+            wave_pitch: 0,
+            wave_accumulator: 0,
+            wave_halted: true,
+            wave_writable: false,
+            envelopes_halted: false,
+            master_volume: 0,
+            envelope_speed: 0xe8,
+            volume: FdsEnvelope::default(),
+            modulator: FdsModulator::default(),
+        }
+    }
+
+    pub fn load(&self, addr: Addr) -> Option<u8> {
+        match addr {
+            0x4040..=0x407f => Some(self.wave[(addr & 0x3f) as usize]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.modulator.envelope.gain),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, addr: Addr, v: u8) {
+        let speed = self.envelope_speed;
+        match addr {
+            0x4040..=0x407f if self.wave_writable => self.wave[(addr & 0x3f) as usize] = v & 0x3f,
+            0x4080 => self.volume.write(v, speed),
+            0x4082 => self.wave_pitch = (self.wave_pitch & 0x0f00) | v as u16,
+            0x4083 => {
+                self.wave_pitch = (self.wave_pitch & 0x00ff) | (((v & 0x0f) as u16) << 8);
+                self.wave_halted = (v & 0x80) != 0;
+                self.envelopes_halted = (v & 0x40) != 0;
+                if self.wave_halted {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset(speed);
+                    self.modulator.envelope.reset(speed);
+                }
+            }
+            0x4084 => self.modulator.envelope.write(v, speed),
+            0x4085 => self.modulator.set_counter((v & 0x7f) as i32),
+            0x4086 => self.modulator.pitch = (self.modulator.pitch & 0x0f00) | v as u16,
+            0x4087 => {
+                let pitch = ((v & 0x0f) as u16) << 8;
+                self.modulator.pitch = (self.modulator.pitch & 0x00ff) | pitch;
+                self.modulator.halted = (v & 0x80) != 0;
+                if self.modulator.halted {
+                    self.modulator.accumulator = 0;
+                }
+            }
+            0x4088 => self.modulator.write_table(v),
+            0x4089 => {
+                self.wave_writable = (v & 0x80) != 0;
+                self.master_volume = v & 0b11;
+            }
+            0x408a => self.envelope_speed = v,
+            _ => (),
+        }
+        self.modulator.update_output(self.wave_pitch);
+    }
+
+    pub fn clock(&mut self) {
+        let speed = self.envelope_speed;
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.clock(speed);
+            if self.modulator.envelope.clock(speed) {
+                self.modulator.update_output(self.wave_pitch);
+            }
+        }
+
+        if self.modulator.clock() {
+            self.modulator.update_output(self.wave_pitch);
+        }
+
+        let pitch = self.wave_pitch as i32 + self.modulator.output;
+        if self.wave_halted || self.wave_writable || pitch <= 0 {
+            return;
+        }
+
+        let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+        self.wave_accumulator = accumulator;
+        if overflow {
+            self.wave_position = (self.wave_position + 1) & 0x3f;
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let gain =
+            self.volume.gain.min(32) as u32 * Self::MASTER_VOLUME[self.master_volume as usize];
+        let level = self.wave[self.wave_position as usize] as u32 * gain / 1152;
+        level as f32 * Self::LEVEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fds_audio_wave() {
+        let mut audio = FdsAudio::new();
+        audio.store(0x4089, 0x80);
+        for (i, addr) in (0x4040..0x4080).enumerate() {
+            audio.store(addr, if i < 32 { 0x3f } else { 0 });
+        }
+        audio.store(0x4089, 0x00);
+
+        // Envelope off, full gain
+        audio.store(0x4080, 0x80 | 0x20);
+        assert_eq!(Some(0x20), audio.load(0x4090));
+        audio.store(0x4082, 0x00);
+        audio.store(0x4083, 0x04);
+        audio.store(0x4087, 0x80);
+        assert!(audio.output() > 0.3);
+
+        // $0400 a cycle steps once every 64 cycles, so the low half comes
+        // after 32 steps
+        (0..32 * 64).for_each(|_| audio.clock());
+        assert_eq!(0.0, audio.output());
+    }
+
+    #[test]
+    fn test_fds_modulator_table() {
+        let mut audio = FdsAudio::new();
+        audio.store(0x4087, 0x80);
+        audio.store(0x4088, 1);
+        audio.store(0x4088, 4);
+        assert_eq!([1, 1, 4, 4], audio.modulator.table[..4]);
+
+        // Filling the rest of the table brings the position back around
+        (0..30).for_each(|_| audio.store(0x4088, 1));
+        assert_eq!(0, audio.modulator.position);
+
+        // A pitch of $FFF overflows the accumulator every 16 and a bit cycles,
+        // and the counter wraps from 63 to -64
+        audio.store(0x4085, 0x3f);
+        audio.store(0x4086, 0xff);
+        audio.store(0x4087, 0x0f);
+        (0..16).for_each(|_| audio.clock());
+        assert_eq!(63, audio.modulator.counter);
+        audio.clock();
+        assert_eq!(-64, audio.modulator.counter);
+    }
+}
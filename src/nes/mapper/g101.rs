@@ -0,0 +1,154 @@
+use super::*;
+
+/**
+ * Mapper 32: Irem G-101.
+ *
+ * PRG:  two switchable 8kB banks and the last two banks fixed, the first
+ *       switchable one swappable with the second to last bank.
+ *       $6000-$7FFF 8kB RAM, battery backed on some boards.
+ * CHR:  eight switchable 1kB banks.
+ *
+ *   $8000-$8FFF PRG bank 0     $9000-$9FFF ......PM  PRG mode, mirroring
+ *   $A000-$AFFF PRG bank 1     $B000-$B007 CHR banks
+ *
+ * Major League (submapper 1) has the mirroring fixed to one screen, and
+ * ignores the PRG mode.
+ */
+pub struct G101 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    has_battery: bool,
+    fixed_mirror: bool,
+
+    prg_regs: [u8; 2],
+    chr_regs: [u8; 8],
+    /// $9000
+    control: u8,
+}
+
+impl G101 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, submapper: u8, has_battery: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            fixed_mirror: submapper == 1,
+            prg_regs: [0; 2],
+            chr_regs: [0; 8],
+            control: 0,
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let second_last = self.prg.len() / 0x2000 - 2;
+        let swapped = (self.control & 0b10) != 0 && !self.fixed_mirror;
+        let bank = match ((addr >> 13) & 0b11, swapped) {
+            (0, false) => self.prg_regs[0] as usize,
+            (0, true) => second_last,
+            (1, _) => self.prg_regs[1] as usize,
+            (2, false) => second_last,
+            (2, true) => self.prg_regs[0] as usize,
+            (_, _) => second_last + 1,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 10) & 7];
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for G101 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x6000..=0x7fff => self.ram[(addr - 0x6000) as usize] = v,
+            0x8000..=0x8fff => self.prg_regs[0] = v & 0x1f,
+            0x9000..=0x9fff => self.control = v,
+            0xa000..=0xafff => self.prg_regs[1] = v & 0x1f,
+            0xb000..=0xbfff => self.chr_regs[(addr & 7) as usize] = v,
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        match (self.fixed_mirror, self.control & 1) {
+            (true, _) => MirrorDirection::SingleScreenLower,
+            (false, 0) => MirrorDirection::Vertical,
+            (false, _) => MirrorDirection::Horizontal,
+        }
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some(self.ram.clone()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn g101(submapper: u8) -> G101 {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        G101::new(prg, vec![], submapper, false)
+    }
+
+    #[test]
+    fn test_g101_prg_mode() {
+        let mut m = g101(0);
+        m.store_prg(0x8000, 3);
+        m.store_prg(0xa000, 4);
+        assert_eq!(
+            [3, 4, 14, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+
+        m.store_prg(0x9000, 0b11);
+        assert_eq!(
+            [14, 4, 3, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+        assert_eq!(MirrorDirection::Horizontal, m.mirror());
+    }
+
+    #[test]
+    fn test_g101_major_league() {
+        let mut m = g101(1);
+        m.store_prg(0x8000, 3);
+        m.store_prg(0x9000, 0b11);
+        assert_eq!(3, m.load_prg(0x8000));
+        assert_eq!(MirrorDirection::SingleScreenLower, m.mirror());
+    }
+}
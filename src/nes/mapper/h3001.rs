@@ -0,0 +1,195 @@
+use super::*;
+
+/**
+ * Mapper 65: Irem H3001.
+ *
+ * PRG:  three switchable 8kB banks, the last bank fixed at $E000. The $8000
+ *       and $C000 banks trade places in PRG mode 1.
+ * CHR:  eight switchable 1kB banks.
+ * IRQ:  a 16 bit counter decremented every CPU cycle, which fires and stops
+ *       when it reaches zero.
+ *
+ *   $8000 $A000 $C000  PRG banks
+ *   $9000  P.......    PRG mode
+ *   $9001  MM......    mirroring: vertical, horizontal, or one screen
+ *   $9003  E.......    IRQ enable
+ *   $9004              reload the IRQ counter
+ *   $9005 $9006        IRQ reload value, high and low byte
+ *   $B000-$B007        CHR banks
+ *
+ * Writes to $9003 and $9004 also acknowledge the IRQ.
+ */
+pub struct H3001 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+
+    prg_regs: [u8; 3],
+    prg_swap: bool,
+    chr_regs: [u8; 8],
+    mirror: MirrorDirection,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl H3001 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            // Powers on with the first two banks and the second to last one
+            prg_regs: [0, 1, 0xfe],
+            prg_swap: false,
+            chr_regs: [0; 8],
+            mirror: MirrorDirection::Vertical,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let bank = match (addr, self.prg_swap) {
+            (0x8000..=0x9fff, false) | (0xc000..=0xdfff, true) => self.prg_regs[0] as usize,
+            (0xa000..=0xbfff, _) => self.prg_regs[1] as usize,
+            (0xc000..=0xdfff, false) | (0x8000..=0x9fff, true) => self.prg_regs[2] as usize,
+            _ => self.prg.len() / 0x2000 - 1,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 10) & 7];
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for H3001 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x8000..=0x8fff => self.prg_regs[0] = v,
+            0x9000 => self.prg_swap = (v & 0x80) != 0,
+            0x9001 => {
+                self.mirror = match v >> 6 {
+                    0b00 => MirrorDirection::Vertical,
+                    0b10 => MirrorDirection::Horizontal,
+                    _ => MirrorDirection::SingleScreenLower,
+                };
+            }
+            0x9003 => {
+                self.irq_enabled = (v & 0x80) != 0;
+                self.irq_pending = false;
+            }
+            0x9004 => {
+                self.irq_counter = self.irq_reload;
+                self.irq_pending = false;
+            }
+            0x9005 => self.irq_reload = (self.irq_reload & 0x00ff) | ((v as u16) << 8),
+            0x9006 => self.irq_reload = (self.irq_reload & 0xff00) | v as u16,
+            0xa000..=0xafff => self.prg_regs[1] = v,
+            0xb000..=0xbfff => self.chr_regs[(addr & 7) as usize] = v,
+            0xc000..=0xcfff => self.prg_regs[2] = v,
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            if !self.irq_enabled || self.irq_counter == 0 {
+                return;
+            }
+
+            self.irq_counter -= 1;
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_h3001_irq() {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let mut m = H3001::new(prg, vec![]);
+
+        m.store_prg(0x9005, 0x01);
+        m.store_prg(0x9006, 0x00);
+        m.store_prg(0x9004, 0);
+        m.store_prg(0x9003, 0x80);
+
+        m.tick(0xff);
+        assert!(!m.irq());
+        m.tick(1);
+        assert!(m.irq());
+
+        // Stopped at zero, until reloaded
+        m.store_prg(0x9003, 0x80);
+        m.tick(0x1000);
+        assert!(!m.irq());
+        m.store_prg(0x9004, 0);
+        m.tick(0x100);
+        assert!(m.irq());
+    }
+
+    #[test]
+    fn test_h3001_banking() {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let mut m = H3001::new(prg, vec![]);
+        m.store_prg(0x8000, 5);
+        m.store_prg(0xa000, 6);
+        m.store_prg(0xc000, 7);
+        assert_eq!(
+            [5, 6, 7, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+
+        m.store_prg(0x9000, 0x80);
+        assert_eq!(
+            [7, 6, 5, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+
+        m.store_prg(0x9001, 0x80);
+        assert_eq!(MirrorDirection::Horizontal, m.mirror());
+        m.store_prg(0x9001, 0x40);
+        assert_eq!(MirrorDirection::SingleScreenLower, m.mirror());
+        m.store_prg(0x9001, 0x00);
+        assert_eq!(MirrorDirection::Vertical, m.mirror());
+    }
+}
@@ -0,0 +1,145 @@
+use super::*;
+
+/**
+ * Mapper 91: the HK-SF3 pirate board, home of the Street Fighter 3 and
+ * Mortal Kombat ports.
+ *
+ * PRG:  two switchable 8kB banks and the last two banks fixed.
+ * CHR:  four switchable 2kB banks.
+ * IRQ:  fires once after eight scanlines.
+ *
+ *   $6000-$6003 CHR banks       (repeated through $6FFF)
+ *   $7000 $7001 PRG banks       (repeated through $7FFF)
+ *   $7002       stop and acknowledge the IRQ
+ *   $7003       restart the IRQ counter
+ */
+pub struct Hksf3 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirror: MirrorDirection,
+
+    prg_regs: [u8; 2],
+    chr_regs: [u8; 4],
+
+    irq_counter: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Hksf3 {
+    const IRQ_SCANLINES: u8 = 8;
+
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirror: MirrorDirection) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            mirror,
+            prg_regs: [0; 2],
+            chr_regs: [0; 4],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let last = self.prg.len() / 0x2000 - 1;
+        let bank = match (addr >> 13) & 0b11 {
+            0 => self.prg_regs[0] as usize,
+            1 => self.prg_regs[1] as usize,
+            2 => last - 1,
+            _ => last,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 11) & 3];
+        bank_offset(&self.chr, 0x800, bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for Hksf3 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match (addr & 0xf003, addr & 3) {
+            (0x6000..=0x6003, reg) => self.chr_regs[reg as usize] = v,
+            (0x7000..=0x7001, reg) => self.prg_regs[reg as usize] = v & 0x0f,
+            (0x7002, _) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0x7003, _) => {
+                self.irq_enabled = true;
+                self.irq_counter = 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    fn scanline(&mut self) {
+        if self.irq_enabled && self.irq_counter < Self::IRQ_SCANLINES {
+            self.irq_counter += 1;
+            if self.irq_counter == Self::IRQ_SCANLINES {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hksf3() {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..64).flat_map(|b| vec![b as u8; 0x800]).collect();
+        let mut m = Hksf3::new(prg, chr, MirrorDirection::Vertical);
+
+        m.store_prg(0x7000, 3);
+        m.store_prg(0x7ffd, 4);
+        m.store_prg(0x6002, 0x21);
+        assert_eq!(
+            [3, 4, 14, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+        assert_eq!(0x21, m.load_chr(0x1000));
+
+        m.store_prg(0x7003, 0);
+        (0..7).for_each(|_| m.scanline());
+        assert!(!m.irq());
+        m.scanline();
+        assert!(m.irq());
+        m.store_prg(0x7002, 0);
+        assert!(!m.irq());
+    }
+}
@@ -0,0 +1,397 @@
+use super::*;
+
+/**
+ * Mapper 4: Nintendo MMC3, and the boards built around it or its ancestor.
+ *
+ * PRG:  two switchable 8kB banks (R6, R7) and the last two banks fixed, with
+ *       R6 and the second to last bank swappable. $6000-$7FFF 8kB RAM.
+ * CHR:  two 2kB banks (R0, R1) and four 1kB banks (R2-R5), with the two
+ *       pattern tables swappable.
+ * IRQ:  a scanline counter, clocked when the PPU fetches sprite patterns.
+ *
+ * Registers come in even/odd pairs across each 8kB of $8000-$FFFF:
+ *   $8000 bank select    $8001 bank data
+ *   $A000 mirroring      $A001 PRG RAM protect
+ *   $C000 IRQ latch      $C001 IRQ reload
+ *   $E000 IRQ disable    $E001 IRQ enable
+ *
+ * Mapper 206, Namco 108: only the bank registers, without mode bits.
+ * Mapper 118, TxSROM:    bit 7 of the CHR banks also picks the nametables.
+ * Mapper 119, TQROM:     bit 6 of the CHR banks selects 8kB of CHR RAM.
+ */
+pub struct Mmc3 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// TQROM has CHR RAM alongside its CHR ROM
+    chr_ram: Vec<u8>,
+    ram: Vec<u8>,
+    has_battery: bool,
+    board: Mmc3Board,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    mirror: MirrorDirection,
+    /// $A001: RAM enabled, and writable
+    ram_enabled: bool,
+    ram_writable: bool,
+
+    irq: ScanlineCounter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mmc3Board {
+    Mmc3,
+    Namco108,
+    TxSrom,
+    TqRom,
+}
+
+impl Mmc3 {
+    pub fn new(
+        prg: Vec<u8>,
+        chr: Vec<u8>,
+        board: Mmc3Board,
+        mirror: MirrorDirection,
+        has_battery: bool,
+    ) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        let chr_ram = match board {
+            Mmc3Board::TqRom => vec![0; CHR_RAM_SIZE],
+            _ => Vec::new(),
+        };
+
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            chr_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            board,
+            bank_select: 0,
+            banks: [0; 8],
+            mirror,
+            ram_enabled: true,
+            ram_writable: true,
+            irq: ScanlineCounter::default(),
+        }
+    }
+
+    fn prg_swapped(&self) -> bool {
+        (self.bank_select & 0x40) != 0
+    }
+
+    fn chr_inverted(&self) -> bool {
+        (self.bank_select & 0x80) != 0
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let second_last = self.prg.len() / 0x2000 - 2;
+        let bank = match ((addr >> 13) & 0b11, self.prg_swapped()) {
+            (0, false) => self.banks[6] as usize,
+            (0, true) => second_last,
+            (1, _) => self.banks[7] as usize,
+            (2, false) => second_last,
+            (2, true) => self.banks[6] as usize,
+            (_, _) => second_last + 1,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    /// 1kB CHR bank mapped at a pattern table address
+    fn chr_bank(&self, addr: Addr) -> u8 {
+        let slot = (addr as usize >> 10) & 7;
+        let slot = match self.chr_inverted() {
+            true => slot ^ 4,
+            false => slot,
+        };
+
+        match slot {
+            0..=3 => (self.banks[slot / 2] & 0xfe) | (slot & 1) as u8,
+            _ => self.banks[slot - 2],
+        }
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        bank_offset(
+            &self.chr,
+            0x400,
+            self.chr_bank(addr) as usize,
+            addr as usize,
+        )
+    }
+
+    /// TQROM banks with bit 6 set come from CHR RAM instead of ROM
+    fn chr_is_ram_bank(&self, addr: Addr) -> bool {
+        self.board == Mmc3Board::TqRom && (self.chr_bank(addr) & 0x40) != 0
+    }
+
+    fn chr_ram_offset(&self, addr: Addr) -> usize {
+        bank_offset(
+            &self.chr_ram,
+            0x400,
+            self.chr_bank(addr) as usize,
+            addr as usize,
+        )
+    }
+
+    /// TxSROM wires CHR A17 to the VRAM page, so nametable N follows the bank
+    /// that would be at CHR $0400*N
+    fn txsrom_vram_offset(&self, addr: Addr) -> usize {
+        let page = (self.chr_bank(addr & 0x0fff) >> 7) as usize;
+        page * 0x400 + (addr as usize & 0x3ff)
+    }
+
+    fn write_register(&mut self, addr: Addr, v: u8) {
+        if self.board == Mmc3Board::Namco108 {
+            match addr & 0xe001 {
+                0x8000 => self.bank_select = v & 0b111,
+                0x8001 => self.write_bank(v),
+                _ => (),
+            }
+            return;
+        }
+
+        match addr & 0xe001 {
+            0x8000 => self.bank_select = v,
+            0x8001 => self.write_bank(v),
+            0xa000 if self.mirror != MirrorDirection::FourScreen => {
+                self.mirror = match v & 1 {
+                    0 => MirrorDirection::Vertical,
+                    _ => MirrorDirection::Horizontal,
+                };
+            }
+            0xa001 => {
+                self.ram_enabled = (v & 0x80) != 0;
+                self.ram_writable = (v & 0x40) == 0;
+            }
+            0xc000 => self.irq.latch = v,
+            0xc001 => self.irq.reload(),
+            0xe000 => self.irq.disable(),
+            0xe001 => self.irq.enabled = true,
+            _ => (),
+        }
+    }
+
+    fn write_bank(&mut self, v: u8) {
+        let reg = (self.bank_select & 0b111) as usize;
+        self.banks[reg] = match (reg, self.board) {
+            (6 | 7, Mmc3Board::Namco108) => v & 0x0f,
+            (6 | 7, _) => v & 0x3f,
+            (_, Mmc3Board::Namco108) => v & 0x3f,
+            (_, _) => v,
+        };
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled && self.ram_writable => {
+                self.ram[(addr - 0x6000) as usize] = v
+            }
+            0x8000..=0xffff => self.write_register(addr, v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        match self.chr_is_ram_bank(addr) {
+            true => self.chr_ram[self.chr_ram_offset(addr)],
+            false => self.chr[self.chr_offset(addr)],
+        }
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram_bank(addr) {
+            let offset = self.chr_ram_offset(addr);
+            self.chr_ram[offset] = v;
+        } else if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    fn load_ppu(&self, addr: Addr, vram: &[u8]) -> u8 {
+        match (addr, self.board) {
+            (0x0000..=0x1fff, _) => self.load_chr(addr),
+            (_, Mmc3Board::TxSrom) => vram[self.txsrom_vram_offset(addr)],
+            (_, _) => vram[vram_offset(self.mirror, addr)],
+        }
+    }
+
+    fn store_ppu(&mut self, addr: Addr, v: u8, vram: &mut [u8]) {
+        match (addr, self.board) {
+            (0x0000..=0x1fff, _) => self.store_chr(addr, v),
+            (_, Mmc3Board::TxSrom) => vram[self.txsrom_vram_offset(addr)] = v,
+            (_, _) => vram[vram_offset(self.mirror, addr)] = v,
+        }
+    }
+
+    fn scanline(&mut self) {
+        if self.board != Mmc3Board::Namco108 {
+            self.irq.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some(self.ram.clone()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/**
+ * The MMC3 IRQ counter, clocked once per scanline while rendering. Other
+ * chips copied it too.
+ */
+#[derive(Default)]
+pub(super) struct ScanlineCounter {
+    pub latch: u8,
+    counter: u8,
+    reload: bool,
+    pub enabled: bool,
+    pub pending: bool,
+}
+
+impl ScanlineCounter {
+    pub fn reload(&mut self) {
+        self.counter = 0;
+        self.reload = true;
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        self.pending = false;
+    }
+
+    pub fn clock(&mut self) {
+        if self.counter == 0 || self.reload {
+            self.counter = self.latch;
+            self.reload = false;
+        } else {
+            self.counter -= 1;
+        }
+
+        if self.counter == 0 && self.enabled {
+            self.pending = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmc3(board: Mmc3Board) -> Mmc3 {
+        let prg = (0..32).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..=255).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Mmc3::new(prg, chr, board, MirrorDirection::Vertical, false)
+    }
+
+    #[test]
+    fn test_mmc3_banking() {
+        let mut m = mmc3(Mmc3Board::Mmc3);
+        [(6, 4), (7, 5), (0, 0x11), (2, 0x20)]
+            .iter()
+            .for_each(|(reg, bank)| {
+                m.store_prg(0x8000, *reg);
+                m.store_prg(0x8001, *bank);
+            });
+
+        assert_eq!(4, m.load_prg(0x8000));
+        assert_eq!(5, m.load_prg(0xa000));
+        assert_eq!(30, m.load_prg(0xc000));
+        assert_eq!(31, m.load_prg(0xe000));
+        assert_eq!(0x10, m.load_chr(0x0000));
+        assert_eq!(0x11, m.load_chr(0x0400));
+        assert_eq!(0x20, m.load_chr(0x1000));
+
+        // Swap the PRG and CHR halves
+        m.store_prg(0x8000, 0xc0);
+        assert_eq!(30, m.load_prg(0x8000));
+        assert_eq!(4, m.load_prg(0xc000));
+        assert_eq!(0x20, m.load_chr(0x0000));
+        assert_eq!(0x11, m.load_chr(0x1400));
+    }
+
+    #[test]
+    fn test_mmc3_irq() {
+        let mut m = mmc3(Mmc3Board::Mmc3);
+        m.store_prg(0xc000, 2);
+        m.store_prg(0xc001, 0);
+        m.store_prg(0xe001, 0);
+
+        m.scanline();
+        m.scanline();
+        assert!(!m.irq());
+        m.scanline();
+        assert!(m.irq());
+
+        m.store_prg(0xe000, 0);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn test_namco108_ignores_mode_bits() {
+        let mut m = mmc3(Mmc3Board::Namco108);
+        m.store_prg(0x8000, 0xc6);
+        m.store_prg(0x8001, 0xff);
+        assert_eq!(15, m.load_prg(0x8000));
+        assert_eq!(0, m.bank_select & 0xc0);
+    }
+
+    #[test]
+    fn test_txsrom_nametables() {
+        let mut m = mmc3(Mmc3Board::TxSrom);
+        let mut vram = vec![0; VRAM_SIZE];
+
+        // R0 covers $2000 and $2400, R1 $2800 and $2C00
+        m.store_prg(0x8000, 0);
+        m.store_prg(0x8001, 0x80);
+        m.store_prg(0x8000, 1);
+        m.store_prg(0x8001, 0x00);
+
+        m.store_ppu(0x2400, 0xaa, &mut vram);
+        m.store_ppu(0x2800, 0xbb, &mut vram);
+        assert_eq!(0xaa, vram[0x400]);
+        assert_eq!(0xbb, vram[0x000]);
+    }
+
+    #[test]
+    fn test_tqrom_chr_ram() {
+        let mut m = mmc3(Mmc3Board::TqRom);
+        m.store_prg(0x8000, 2);
+        m.store_prg(0x8001, 0x41);
+        m.store_chr(0x1000, 0x5a);
+        assert_eq!(0x5a, m.load_chr(0x1000));
+
+        // ROM banks stay read only
+        m.store_prg(0x8001, 0x01);
+        m.store_chr(0x1000, 0x5a);
+        assert_eq!(0x01, m.load_chr(0x1000));
+    }
+}
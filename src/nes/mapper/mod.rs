@@ -0,0 +1,238 @@
+mod bandai;
+mod discrete;
+mod eeprom;
+mod fds;
+mod fds_audio;
+mod g101;
+mod h3001;
+mod hksf3;
+mod mmc3;
+mod multicart;
+mod namco163;
+mod nrom;
+mod nsf;
+mod opll;
+mod rambo1;
+mod ss88006;
+mod sunsoft4;
+mod tc0190;
+mod vrc24;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
+
+pub use bandai::{Bandai, FcgChip};
+pub use discrete::{Discrete, DiscreteBoard};
+pub use fds::Fds;
+pub use g101::G101;
+pub use h3001::H3001;
+pub use hksf3::Hksf3;
+pub use mmc3::{Mmc3, Mmc3Board};
+pub use multicart::{Multicart, MulticartBoard};
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use nsf::NsfMapper;
+pub use rambo1::Rambo1;
+pub use ss88006::Ss88006;
+pub use sunsoft4::Sunsoft4;
+pub use tc0190::Tc0190;
+pub use vrc24::{Vrc24, VrcChip};
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+use crate::error::*;
+use crate::nes::cartridge::{which_mapper, Cartridge, MirrorDirection};
+use crate::nes::memory::Addr;
+
+use log::*;
+
+const CHR_RAM_SIZE: usize = 0x2000;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// The console's 2kB of nametable RAM (CIRAM), plus the 2kB four screen boards add
+pub const VRAM_SIZE: usize = 0x1000;
+
+/**
+ * The board inside a cartridge, as seen from the CPU ($4020-$FFFF) and from
+ * the PPU ($0000-$2FFF).
+ *
+ * Nametables normally live in VRAM owned by the console, which the board only
+ * lays out through `mirror()`. Boards that do more than that override
+ * `load_ppu` and `store_ppu`, which get that VRAM passed in.
+ */
+pub trait Mapper {
+    fn load_prg(&self, addr: Addr) -> u8;
+    fn store_prg(&mut self, addr: Addr, v: u8);
+
+    fn load_chr(&self, addr: Addr) -> u8;
+    fn store_chr(&mut self, addr: Addr, v: u8);
+
+    fn mirror(&self) -> MirrorDirection;
+
+    /// PPU $0000-$2FFF: pattern tables from CHR, nametables from `vram`
+    fn load_ppu(&self, addr: Addr, vram: &[u8]) -> u8 {
+        match addr {
+            0x0000..=0x1fff => self.load_chr(addr),
+            _ => vram[vram_offset(self.mirror(), addr)],
+        }
+    }
+
+    fn store_ppu(&mut self, addr: Addr, v: u8, vram: &mut [u8]) {
+        match addr {
+            0x0000..=0x1fff => self.store_chr(addr, v),
+            _ => vram[vram_offset(self.mirror(), addr)] = v,
+        }
+    }
+
+    /// Advances on-cartridge hardware (IRQ counters, audio) by a number of CPU cycles
+    fn tick(&mut self, _cycles: usize) {}
+
+    /// Called by the PPU once per rendered scanline, where MMC3 style counters
+    /// see PPU A12 rise as it starts fetching sprite patterns
+    fn scanline(&mut self) {}
+
+    /// Level of the cartridge /IRQ line, true when asserted
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Expansion audio, scaled so 1.0 is the full-scale output of the 2A03 mixer
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Contents of battery backed memory, for boards that have any
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores memory saved from `battery_ram`
+    fn restore_battery_ram(&mut self, _data: &[u8]) {}
+
+    /// Number of disk sides, for boards with a disk drive
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    /// The disk side in the drive, if any
+    fn disk_side(&self) -> Option<usize> {
+        None
+    }
+
+    /// Swaps the disk in the drive for another side, or ejects it with `None`
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+}
+
+/**
+ * Builds the board described by the cartridge header around its ROM contents
+ */
+pub fn from_cartridge(
+    cartridge: &Cartridge,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+) -> IronNesResult<Box<dyn Mapper>> {
+    let mapper: Box<dyn Mapper> = match cartridge.mapper {
+        0 => Box::new(Nrom::new(prg, chr, cartridge.mirror)),
+        4 | 118 | 119 | 206 => {
+            let board = match cartridge.mapper {
+                118 => Mmc3Board::TxSrom,
+                119 => Mmc3Board::TqRom,
+                206 => Mmc3Board::Namco108,
+                _ => Mmc3Board::Mmc3,
+            };
+            Box::new(Mmc3::new(prg, chr, board, cartridge.mirror, cartridge.has_battery))
+        }
+        16 | 159 => Box::new(Bandai::new(
+            prg,
+            chr,
+            cartridge.mapper,
+            cartridge.submapper,
+            cartridge.has_battery,
+        )),
+        18 => Box::new(Ss88006::new(prg, chr, cartridge.has_battery)),
+        19 => Box::new(Namco163::new(prg, chr, cartridge.has_battery)),
+        21 | 22 | 23 | 25 => Box::new(Vrc24::new(
+            prg,
+            chr,
+            cartridge.mapper,
+            cartridge.submapper,
+            cartridge.has_battery,
+        )),
+        24 => Box::new(Vrc6::new(prg, chr, false, cartridge.has_battery)),
+        26 => Box::new(Vrc6::new(prg, chr, true, cartridge.has_battery)),
+        32 => Box::new(G101::new(
+            prg,
+            chr,
+            cartridge.submapper,
+            cartridge.has_battery,
+        )),
+        33 => Box::new(Tc0190::new(prg, chr, false)),
+        48 => Box::new(Tc0190::new(prg, chr, true)),
+        64 => Box::new(Rambo1::new(prg, chr, cartridge.mirror)),
+        65 => Box::new(H3001::new(prg, chr)),
+        68 => Box::new(Sunsoft4::new(prg, chr, cartridge.has_battery)),
+        85 => Box::new(Vrc7::new(
+            prg,
+            chr,
+            cartridge.submapper,
+            cartridge.has_battery,
+        )),
+        91 => Box::new(Hksf3::new(prg, chr, cartridge.mirror)),
+        15 | 200 | 201 | 203 => {
+            let board = MulticartBoard::from_mapper(cartridge.mapper)
+                .ok_or(IronNesError::CartridgeError)?;
+            Box::new(Multicart::new(prg, chr, board, cartridge.mirror))
+        }
+        11 | 13 | 34 | 66 | 71 => {
+            let board = DiscreteBoard::detect(
+                cartridge.mapper,
+                cartridge.submapper,
+                cartridge.is_nes2,
+                chr.len(),
+            )
+            .ok_or(IronNesError::CartridgeError)?;
+            Box::new(Discrete::new(prg, chr, board, cartridge.mirror))
+        }
+        _ => {
+            error!(
+                "Emulator does not support mapper. Requested: {}",
+                which_mapper(cartridge.mapper)
+            );
+            return Err(IronNesError::UnsupportedMapper(cartridge.mapper as u16));
+        }
+    };
+    Ok(mapper)
+}
+
+/// Boards without CHR ROM carry 8kB of CHR RAM instead
+fn chr_or_ram(chr: Vec<u8>) -> (Vec<u8>, bool) {
+    match chr.is_empty() {
+        true => (vec![0; CHR_RAM_SIZE], true),
+        false => (chr, false),
+    }
+}
+
+/// Offset into `mem` of `offset` within bank `bank`, where banks are `size` bytes.
+/// Bank numbers wrap around the amount of memory actually present.
+fn bank_offset(mem: &[u8], size: usize, bank: usize, offset: usize) -> usize {
+    let banks = (mem.len() / size).max(1);
+    ((bank % banks) * size + (offset % size)) % mem.len()
+}
+
+/// Offset into VRAM of a nametable address, for the standard layouts
+pub fn vram_offset(mirror: MirrorDirection, addr: Addr) -> usize {
+    let table = (addr as usize >> 10) & 0b11;
+    let page = match mirror {
+        MirrorDirection::Vertical => table & 1,
+        MirrorDirection::Horizontal => table >> 1,
+        MirrorDirection::SingleScreenLower => 0,
+        MirrorDirection::SingleScreenUpper => 1,
+        MirrorDirection::FourScreen => table,
+    };
+    page * 0x400 + (addr as usize & 0x3ff)
+}
+
+/// Value left floating on the data bus when nothing drives it
+fn open_bus(addr: Addr) -> u8 {
+    (addr >> 8) as u8
+}
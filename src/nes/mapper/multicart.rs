@@ -0,0 +1,254 @@
+use super::*;
+
+/**
+ * Multicarts: one latch picks which of the games on the cartridge is showing,
+ * each game being a plain NROM or UNROM layout within an outer bank.
+ *
+ * Mapper 15, 100-in-1:   $8000-$FFFF, address ......MM, data PMBBBBBB.
+ *                        The mode picks how the 16kB bank B is laid out:
+ *                          0: 32kB, B at $8000 and B|1 at $C000
+ *                          1: UNROM, B at $8000 and B|7 at $C000
+ *                          2: 8kB, half P of B at every 8kB
+ *                          3: 16kB, B at $8000 and $C000
+ *                        M is mirroring. CHR RAM is write protected in modes
+ *                        0 and 3, and there's 8kB of PRG RAM.
+ * Mapper 200:            $8000-$FFFF, address ....MBBB: 16kB PRG mirrored,
+ *                        8kB CHR and mirroring.
+ * Mapper 201:            $8000-$FFFF, address BBBBBBBB: 32kB PRG and 8kB CHR.
+ * Mapper 203:            $8000-$FFFF, data PPPPPPCC: 16kB PRG mirrored and
+ *                        8kB CHR.
+ *
+ * Every latch is cleared at power on, which is the menu.
+ */
+pub struct Multicart {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Option<Vec<u8>>,
+    board: MulticartBoard,
+    mirror: MirrorDirection,
+
+    /// 16kB bank numbers at $8000 and $C000, or for 8kB banks the bank at all
+    /// four
+    prg_banks: [u8; 2],
+    prg_8k: bool,
+    chr_bank: u8,
+    chr_writable: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MulticartBoard {
+    /// Mapper 15
+    K1029,
+    /// Mapper 200
+    Nrom128Address,
+    /// Mapper 201
+    Nrom256Address,
+    /// Mapper 203
+    Nrom128Data,
+}
+
+impl MulticartBoard {
+    pub fn from_mapper(mapper: u8) -> Option<Self> {
+        match mapper {
+            15 => Some(MulticartBoard::K1029),
+            200 => Some(MulticartBoard::Nrom128Address),
+            201 => Some(MulticartBoard::Nrom256Address),
+            203 => Some(MulticartBoard::Nrom128Data),
+            _ => None,
+        }
+    }
+}
+
+impl Multicart {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, board: MulticartBoard, mirror: MirrorDirection) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        let ram = match board {
+            MulticartBoard::K1029 => Some(vec![0; PRG_RAM_SIZE]),
+            _ => None,
+        };
+
+        let mut cart = Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram,
+            board,
+            mirror,
+            prg_banks: [0; 2],
+            prg_8k: false,
+            chr_bank: 0,
+            chr_writable: chr_is_ram,
+        };
+        cart.write_latch(0x8000, 0);
+        cart
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        match self.prg_8k {
+            true => bank_offset(&self.prg, 0x2000, self.prg_banks[0] as usize, addr as usize),
+            false => {
+                let bank = self.prg_banks[((addr >> 14) & 1) as usize];
+                bank_offset(&self.prg, 0x4000, bank as usize, addr as usize)
+            }
+        }
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        bank_offset(&self.chr, 0x2000, self.chr_bank as usize, addr as usize)
+    }
+
+    fn write_latch(&mut self, addr: Addr, v: u8) {
+        match self.board {
+            MulticartBoard::K1029 => {
+                let bank = v & 0x3f;
+                self.prg_8k = false;
+                self.prg_banks = match addr & 0b11 {
+                    0 => [bank, bank | 1],
+                    1 => [bank, bank | 7],
+                    2 => {
+                        self.prg_8k = true;
+                        [(bank << 1) | (v >> 7), 0]
+                    }
+                    _ => [bank, bank],
+                };
+                self.chr_writable = self.chr_is_ram && matches!(addr & 0b11, 1 | 2);
+                self.mirror = match v & 0x40 {
+                    0 => MirrorDirection::Vertical,
+                    _ => MirrorDirection::Horizontal,
+                };
+            }
+            MulticartBoard::Nrom128Address => {
+                let bank = (addr & 0b111) as u8;
+                self.prg_banks = [bank, bank];
+                self.chr_bank = bank;
+                self.mirror = match addr & 0b1000 {
+                    0 => MirrorDirection::Vertical,
+                    _ => MirrorDirection::Horizontal,
+                };
+            }
+            MulticartBoard::Nrom256Address => {
+                let bank = addr as u8;
+                self.prg_banks = [bank << 1, (bank << 1) | 1];
+                self.chr_bank = bank;
+            }
+            MulticartBoard::Nrom128Data => {
+                self.prg_banks = [v >> 2, v >> 2];
+                self.chr_bank = v & 0b11;
+            }
+        }
+    }
+}
+
+impl Mapper for Multicart {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match (addr, &self.ram) {
+            (0x6000..=0x7fff, Some(ram)) => ram[(addr - 0x6000) as usize],
+            (0x8000..=0xffff, _) => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match (addr, &mut self.ram) {
+            (0x6000..=0x7fff, Some(ram)) => ram[(addr - 0x6000) as usize] = v,
+            (0x8000..=0xffff, _) => self.write_latch(addr, v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_writable {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(count: usize, size: usize) -> Vec<u8> {
+        (0..count).flat_map(|b| vec![b as u8; size]).collect()
+    }
+
+    fn prg_at(m: &Multicart) -> [u8; 4] {
+        [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+    }
+
+    #[test]
+    fn test_mapper15_modes() {
+        let mut m = Multicart::new(
+            rom(128, 0x2000),
+            vec![],
+            MulticartBoard::K1029,
+            Default::default(),
+        );
+        // 16kB bank 4 is 8kB banks 8 and 9
+        m.store_prg(0x8000, 0x04);
+        assert_eq!([8, 9, 10, 11], prg_at(&m));
+        m.store_prg(0x8001, 0x04);
+        assert_eq!([8, 9, 14, 15], prg_at(&m));
+        m.store_prg(0x8002, 0x84);
+        assert_eq!([9, 9, 9, 9], prg_at(&m));
+        m.store_prg(0x8003, 0x44);
+        assert_eq!([8, 9, 8, 9], prg_at(&m));
+        assert_eq!(MirrorDirection::Horizontal, m.mirror());
+
+        // CHR RAM only takes writes in the UNROM and 8kB modes
+        m.store_chr(0x0000, 0x12);
+        assert_eq!(0, m.load_chr(0x0000));
+        m.store_prg(0x8001, 0);
+        m.store_chr(0x0000, 0x12);
+        assert_eq!(0x12, m.load_chr(0x0000));
+    }
+
+    #[test]
+    fn test_nrom_multicarts() {
+        assert_eq!(
+            Some(MulticartBoard::Nrom256Address),
+            MulticartBoard::from_mapper(201)
+        );
+
+        let mut m = Multicart::new(
+            rom(8, 0x4000),
+            rom(8, 0x2000),
+            MulticartBoard::Nrom128Address,
+            Default::default(),
+        );
+        m.store_prg(0x800d, 0);
+        assert_eq!([5, 5, 5, 5], prg_at(&m));
+        assert_eq!(5, m.load_chr(0));
+        assert_eq!(MirrorDirection::Horizontal, m.mirror());
+
+        let mut m = Multicart::new(
+            rom(8, 0x4000),
+            rom(4, 0x2000),
+            MulticartBoard::Nrom256Address,
+            MirrorDirection::Vertical,
+        );
+        m.store_prg(0x8003, 0);
+        assert_eq!([6, 6, 7, 7], prg_at(&m));
+        assert_eq!(3, m.load_chr(0));
+        assert_eq!(MirrorDirection::Vertical, m.mirror());
+
+        let mut m = Multicart::new(
+            rom(8, 0x4000),
+            rom(4, 0x2000),
+            MulticartBoard::Nrom128Data,
+            Default::default(),
+        );
+        m.store_prg(0x8000, 0x0e);
+        assert_eq!([3, 3, 3, 3], prg_at(&m));
+        assert_eq!(2, m.load_chr(0));
+    }
+}
@@ -0,0 +1,424 @@
+use super::*;
+use std::cell::Cell;
+
+/**
+ * Mapper 19: Namco 163.
+ *
+ * PRG:  three switchable 8kB banks and the last bank fixed at $E000,
+ *       $6000-$7FFF 8kB RAM.
+ * CHR:  eight 1kB banks. Bank numbers $E0 and up select a page of console
+ *       VRAM instead of CHR ROM, unless disabled for that pattern table.
+ * Nametables: four 1kB banks, each either a VRAM page or a CHR ROM bank.
+ * IRQ:  a 15 bit counter clocked by the CPU, firing when it reaches $7FFF.
+ * Audio: up to eight wavetable channels (see `Namco163Audio`).
+ *
+ * The chip has 128 bytes of internal RAM holding both the channel registers
+ * and the waveforms. Boards with a battery keep it together with PRG RAM.
+ */
+pub struct Namco163 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    has_battery: bool,
+
+    prg_regs: [u8; 3],
+    /// $8000-$DFFF: eight pattern table banks then four nametable banks
+    chr_regs: [u8; 12],
+    /// $E800 bits 6 and 7: VRAM can't be mapped into either pattern table
+    no_vram_low: bool,
+    no_vram_high: bool,
+    /// $F800: write enable for each 2kB of PRG RAM
+    ram_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    const VRAM_BANK: u8 = 0xe0;
+
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, has_battery: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            prg_regs: [0; 3],
+            chr_regs: [0; 12],
+            no_vram_low: false,
+            no_vram_high: false,
+            ram_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::default(),
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let bank = match addr {
+            0x8000..=0x9fff => self.prg_regs[0] as usize,
+            0xa000..=0xbfff => self.prg_regs[1] as usize,
+            0xc000..=0xdfff => self.prg_regs[2] as usize,
+            _ => self.prg.len() / 0x2000 - 1,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    /// $6000-$7FFF is writable when $F800 reads 0100xxxx and its 2kB isn't protected
+    fn ram_writable(&self, addr: Addr) -> bool {
+        let window = (addr - 0x6000) >> 11;
+        (self.ram_protect & 0xf0) == 0x40 && (self.ram_protect & (1 << window)) == 0
+    }
+
+    /// Pattern table access straight to CHR, ignoring VRAM banks
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 10) & 7];
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+
+    /// Where a PPU access lands: `Ok` for an offset into VRAM, `Err` for one into CHR
+    fn ppu_target(&self, addr: Addr) -> Result<usize, usize> {
+        let slot = (addr as usize >> 10) & 0xf;
+        let bank = self.chr_regs[slot];
+        let vram_allowed = match slot {
+            0..=3 => !self.no_vram_low,
+            4..=7 => !self.no_vram_high,
+            _ => true,
+        };
+
+        match bank >= Self::VRAM_BANK && vram_allowed {
+            true => Ok((bank as usize & 1) * 0x400 + (addr as usize & 0x3ff)),
+            false => Err(bank_offset(&self.chr, 0x400, bank as usize, addr as usize)),
+        }
+    }
+
+    fn write_register(&mut self, addr: Addr, v: u8) {
+        match addr & 0xf800 {
+            0x8000..=0xd800 => self.chr_regs[((addr - 0x8000) >> 11) as usize] = v,
+            0xe000 => {
+                self.prg_regs[0] = v & 0x3f;
+                self.audio.enabled = (v & 0x40) == 0;
+            }
+            0xe800 => {
+                self.prg_regs[1] = v & 0x3f;
+                self.no_vram_low = (v & 0x40) != 0;
+                self.no_vram_high = (v & 0x80) != 0;
+            }
+            0xf000 => self.prg_regs[2] = v & 0x3f,
+            _ => {
+                self.ram_protect = v;
+                self.audio.set_address(v);
+            }
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x4800..=0x4fff => self.audio.read(),
+            0x5000..=0x57ff => self.irq_counter as u8,
+            0x5800..=0x5fff => ((self.irq_counter >> 8) as u8) | ((self.irq_enabled as u8) << 7),
+            0x6000..=0x7fff => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x4800..=0x4fff => self.audio.write(v),
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | v as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | (((v & 0x7f) as u16) << 8);
+                self.irq_enabled = (v & 0x80) != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7fff if self.ram_writable(addr) => self.ram[(addr - 0x6000) as usize] = v,
+            0x8000..=0xffff => self.write_register(addr, v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    /// Only meaningful for the default nametable layout, which this board never uses
+    fn mirror(&self) -> MirrorDirection {
+        MirrorDirection::Vertical
+    }
+
+    fn load_ppu(&self, addr: Addr, vram: &[u8]) -> u8 {
+        match self.ppu_target(addr) {
+            Ok(offset) => vram[offset],
+            Err(offset) => self.chr[offset],
+        }
+    }
+
+    fn store_ppu(&mut self, addr: Addr, v: u8, vram: &mut [u8]) {
+        match self.ppu_target(addr) {
+            Ok(offset) => vram[offset] = v,
+            Err(offset) if self.chr_is_ram => self.chr[offset] = v,
+            Err(_) => (),
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            if self.irq_enabled && self.irq_counter < 0x7fff {
+                self.irq_counter += 1;
+                self.irq_pending = self.irq_counter == 0x7fff;
+            }
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some([&self.ram[..], &self.audio.ram[..]].concat()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let (ram, internal) = data.split_at(data.len().min(PRG_RAM_SIZE));
+        self.ram[..ram.len()].copy_from_slice(ram);
+
+        let len = internal.len().min(Namco163Audio::RAM_SIZE);
+        self.audio.ram[..len].copy_from_slice(&internal[..len]);
+    }
+}
+
+/**
+ * Namco 163 wavetable audio.
+ *
+ * The top of the internal RAM holds eight bytes of registers per channel,
+ * channel 7 at $78-$7F down to channel 0 at $40-$47:
+ *
+ *   +0 frequency bits 0-7      +1 phase bits 0-7
+ *   +2 frequency bits 8-15     +3 phase bits 8-15
+ *   +4 LLLLLLFF length, frequency bits 16-17
+ *   +5 phase bits 16-23        +6 waveform address, in 4 bit samples
+ *   +7 volume, and in $7F the number of enabled channels minus one
+ *
+ * Waveforms are 4 bit samples packed two per byte, low nibble first, anywhere
+ * in RAM. The chip updates one channel every 15 CPU cycles and only has one
+ * DAC, so its output switches from channel to channel instead of being mixed.
+ */
+pub(super) struct Namco163Audio {
+    ram: [u8; Self::RAM_SIZE],
+    /// $F800: RAM address for $4800, bit 7 auto increments it
+    address: Cell<u8>,
+    enabled: bool,
+    divider: u8,
+    /// Channel currently driving the DAC
+    channel: usize,
+    output: i16,
+}
+
+impl Default for Namco163Audio {
+    fn default() -> Self {
+        Self {
+            ram: [0; Self::RAM_SIZE],
+            address: Cell::new(0),
+            enabled: true,
+            divider: 0,
+            channel: 0,
+            output: 0,
+        }
+    }
+}
+
+impl Namco163Audio {
+    const RAM_SIZE: usize = 0x80;
+    const CYCLES_PER_CHANNEL: u8 = 15;
+    /// A single channel at full volume is a little louder than a 2A03 pulse
+    const LEVEL: f32 = 0.2 / 120.0;
+
+    pub(super) fn set_address(&self, v: u8) {
+        self.address.set(v);
+    }
+
+    fn ram_address(&self) -> usize {
+        let address = self.address.get();
+        if (address & 0x80) != 0 {
+            self.address.set(0x80 | (address.wrapping_add(1) & 0x7f));
+        }
+        (address & 0x7f) as usize
+    }
+
+    pub(super) fn read(&self) -> u8 {
+        self.ram[self.ram_address()]
+    }
+
+    pub(super) fn write(&mut self, v: u8) {
+        let address = self.ram_address();
+        self.ram[address] = v;
+    }
+
+    fn channels(&self) -> usize {
+        (((self.ram[0x7f] >> 4) & 0b111) + 1) as usize
+    }
+
+    pub(super) fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        self.divider += 1;
+        if self.divider < Self::CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.divider = 0;
+
+        self.channel = match self.channel {
+            c if c <= 8 - self.channels() => 7,
+            c => c - 1,
+        };
+        self.output = self.update_channel(self.channel);
+    }
+
+    /// Advances a channel's phase and returns its new level, -120 to 105
+    fn update_channel(&mut self, channel: usize) -> i16 {
+        let base = 0x40 + channel * 8;
+        let regs = &mut self.ram[base..base + 8];
+
+        let freq = regs[0] as u32 | (regs[2] as u32) << 8 | ((regs[4] & 0b11) as u32) << 16;
+        let length = (256 - (regs[4] & 0xfc) as u32) << 16;
+        let phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        let phase = (phase + freq) % length;
+        regs[1] = phase as u8;
+        regs[3] = (phase >> 8) as u8;
+        regs[5] = (phase >> 16) as u8;
+
+        let volume = (regs[7] & 0x0f) as i16;
+        let sample_addr = ((phase >> 16) as usize + regs[6] as usize) & 0xff;
+        let sample = (self.ram[sample_addr >> 1] >> ((sample_addr & 1) * 4)) & 0x0f;
+
+        (sample as i16 - 8) * volume
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        match self.enabled {
+            true => self.output as f32 * Self::LEVEL,
+            false => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n163() -> Namco163 {
+        let prg = (0..32).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..256).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Namco163::new(prg, chr, true)
+    }
+
+    #[test]
+    fn test_n163_nametables() {
+        let mut m = n163();
+        let mut vram = vec![0; VRAM_SIZE];
+
+        // $2400 from VRAM page 1, $2800 from CHR bank $42
+        m.store_prg(0xc800, 0xe1);
+        m.store_prg(0xd000, 0x42);
+        m.store_ppu(0x2405, 0x99, &mut vram);
+        assert_eq!(0x99, vram[0x405]);
+        assert_eq!(0x99, m.load_ppu(0x2405, &vram));
+        assert_eq!(0x42, m.load_ppu(0x2800, &vram));
+
+        // VRAM in the low pattern table until $E800 bit 6 says otherwise
+        m.store_prg(0x8000, 0xe1);
+        assert_eq!(0x99, m.load_ppu(0x0005, &vram));
+        m.store_prg(0xe800, 0x40);
+        assert_eq!(0xe1, m.load_ppu(0x0005, &vram));
+    }
+
+    #[test]
+    fn test_n163_irq() {
+        let mut m = n163();
+        m.store_prg(0x5000, 0xfd);
+        m.store_prg(0x5800, 0xff);
+        m.tick(1);
+        assert!(!m.irq());
+        m.tick(1);
+        assert!(m.irq());
+
+        // The counter stops at $7FFF
+        m.tick(10);
+        assert_eq!(0xff, m.load_prg(0x5800));
+        m.store_prg(0x5800, 0x00);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn test_n163_internal_ram() {
+        let mut m = n163();
+        m.store_prg(0xf800, 0x80 | 0x10);
+        (0..4).for_each(|v| m.store_prg(0x4800, v));
+
+        m.store_prg(0xf800, 0x80 | 0x11);
+        assert_eq!(1, m.load_prg(0x4800));
+        assert_eq!(2, m.load_prg(0x4800));
+
+        let saved = m.battery_ram().unwrap();
+        assert_eq!(PRG_RAM_SIZE + 0x80, saved.len());
+        assert_eq!(3, saved[PRG_RAM_SIZE + 0x13]);
+
+        let mut other = n163();
+        other.restore_battery_ram(&saved);
+        other.store_prg(0xf800, 0x12);
+        assert_eq!(2, other.load_prg(0x4800));
+    }
+
+    #[test]
+    fn test_n163_multiplexed_audio() {
+        let mut m = n163();
+        let mut poke = |addr: u8, v: u8| {
+            m.store_prg(0xf800, addr);
+            m.store_prg(0x4800, v);
+        };
+
+        // A flat waveform of 15s at $00, two channels at different volumes
+        poke(0x00, 0xff);
+        poke(0x7c, 0xfc);
+        poke(0x7f, 0x10 | 0x0f);
+        poke(0x74, 0xfc);
+        poke(0x77, 0x01);
+
+        m.tick(15);
+        assert_eq!(7 * 15, m.audio.output);
+        m.tick(15);
+        assert_eq!(7, m.audio.output);
+        m.tick(15);
+        assert_eq!(7 * 15, m.audio.output);
+    }
+}
@@ -0,0 +1,63 @@
+use super::*;
+
+/**
+ * Mapper 0: 16kB or 32kB of PRG ROM, 8kB of CHR and no bank switching.
+ *
+ * PRG ROM is left writable, as test harnesses poke code directly into it.
+ */
+pub struct Nrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    mirror: MirrorDirection,
+}
+
+impl Nrom {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirror: MirrorDirection) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            mirror,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg[(addr - 0x8000) as usize % self.prg.len()],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x6000..=0x7fff => self.ram[(addr - 0x6000) as usize] = v,
+            0x8000..=0xffff => {
+                let len = self.prg.len();
+                self.prg[(addr - 0x8000) as usize % len] = v;
+            }
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+}
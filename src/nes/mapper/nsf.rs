@@ -0,0 +1,240 @@
+use super::fds_audio::FdsAudio;
+use super::namco163::Namco163Audio;
+use super::opll::Opll;
+use super::vrc6::Vrc6Audio;
+use super::*;
+use crate::nes::nsf::Nsf;
+
+/**
+ * The hardware an NSF player cartridge has: 4kB PRG banks, 8kB of RAM and
+ * whichever expansion audio chips the tune asks for.
+ *
+ *   $5FF8-$5FFF  banks for $8000-$FFFF, 4kB each
+ *   $5FF6-$5FF7  banks for $6000-$7FFF, FDS tunes only
+ *   $6000-$7FFF  RAM
+ *
+ * Tunes without bankswitching are placed at their load address. FDS tunes run
+ * from RAM all the way from $6000 to $FFFF, so bank writes copy the bank into
+ * RAM instead of mapping it.
+ */
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    banks: [u8; 8],
+    bankswitched: bool,
+    /// $6000-$7FFF, or $6000-$FFFF for FDS tunes
+    ram: Vec<u8>,
+    chr: Vec<u8>,
+
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Opll>,
+    fds: Option<FdsAudio>,
+    n163: Option<Namco163Audio>,
+}
+
+impl NsfMapper {
+    const BANK_SIZE: usize = 0x1000;
+    const FDS_RAM_SIZE: usize = 0xa000;
+
+    pub fn new(nsf: &Nsf) -> Self {
+        let is_fds = (nsf.chips & Nsf::CHIP_FDS) != 0;
+
+        // Without bankswitching the data sits at its load address, as if
+        // $8000-$FFFF (or $6000-$FFFF on the FDS) were banks 0 to 7
+        let base = match (nsf.banks, is_fds) {
+            (Some(_), _) => nsf.load_addr & 0xf000,
+            (None, true) if nsf.load_addr < 0x8000 => 0x6000,
+            (None, _) => 0x8000,
+        };
+        let padding = nsf.load_addr.saturating_sub(base) as usize;
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(&nsf.data);
+        let size = prg.len().div_ceil(Self::BANK_SIZE).max(8) * Self::BANK_SIZE;
+        prg.resize(size, 0);
+
+        let ram_size = match is_fds {
+            true => Self::FDS_RAM_SIZE,
+            false => PRG_RAM_SIZE,
+        };
+        let chip = |flag: u8| (nsf.chips & flag) != 0;
+
+        let mut m = Self {
+            prg,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            bankswitched: nsf.banks.is_some(),
+            ram: vec![0; ram_size],
+            chr: vec![0; CHR_RAM_SIZE],
+            vrc6: Some(Vrc6Audio::default()).filter(|_| chip(Nsf::CHIP_VRC6)),
+            vrc7: Some(Opll::new()).filter(|_| chip(Nsf::CHIP_VRC7)),
+            fds: Some(FdsAudio::new()).filter(|_| is_fds),
+            n163: Some(Namco163Audio::default()).filter(|_| chip(Nsf::CHIP_N163)),
+        };
+
+        match (nsf.banks, is_fds) {
+            (Some(banks), true) => {
+                m.switch_bank(0, banks[6]);
+                m.switch_bank(1, banks[7]);
+                (0..8).for_each(|i| m.switch_bank(i + 2, banks[i]));
+            }
+            (Some(banks), false) => m.banks = banks,
+            // Everything from the load address up is already in place
+            (None, true) if base == 0x6000 => {
+                let len = m.ram.len();
+                m.ram.copy_from_slice(&m.prg[..len]);
+            }
+            (None, true) => (0..8).for_each(|i| m.switch_bank(i + 2, i as u8)),
+            (None, false) => (),
+        }
+        m
+    }
+
+    /// Points one of the 4kB windows from $6000 at a bank, counting $6000 as 0
+    fn switch_bank(&mut self, window: usize, bank: u8) {
+        if self.fds.is_some() {
+            let src = bank_offset(&self.prg, Self::BANK_SIZE, bank as usize, 0);
+            let dst = window * Self::BANK_SIZE;
+            self.ram[dst..dst + Self::BANK_SIZE]
+                .copy_from_slice(&self.prg[src..src + Self::BANK_SIZE]);
+        } else if window >= 2 {
+            self.banks[window - 2] = bank;
+        }
+    }
+
+    fn ram_offset(&self, addr: Addr) -> Option<usize> {
+        match addr {
+            0x6000..=0xffff => Some((addr - 0x6000) as usize).filter(|&o| o < self.ram.len()),
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        if let Some(offset) = self.ram_offset(addr) {
+            return self.ram[offset];
+        }
+
+        match addr {
+            0x4040..=0x409f => self
+                .fds
+                .as_ref()
+                .and_then(|fds| fds.load(addr))
+                .unwrap_or_else(|| open_bus(addr)),
+            0x4800..=0x4fff if self.n163.is_some() => self.n163.as_ref().unwrap().read(),
+            0x8000..=0xffff => {
+                let bank = self.banks[((addr - 0x8000) >> 12) as usize];
+                self.prg[bank_offset(&self.prg, Self::BANK_SIZE, bank as usize, addr as usize)]
+            }
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x4040..=0x409f => {
+                if let Some(fds) = self.fds.as_mut() {
+                    fds.store(addr, v);
+                }
+            }
+            0x4800..=0x4fff => {
+                if let Some(n163) = self.n163.as_mut() {
+                    n163.write(v);
+                }
+            }
+            0x5ff6..=0x5fff if self.bankswitched => {
+                self.switch_bank((addr - 0x5ff6) as usize, v);
+            }
+            0x9000..=0x9003 | 0xa000..=0xa002 | 0xb000..=0xb002 if self.vrc6.is_some() => {
+                self.vrc6.as_mut().unwrap().store(addr, v);
+            }
+            0x9010 if self.vrc7.is_some() => self.vrc7.as_mut().unwrap().write_address(v),
+            0x9030 if self.vrc7.is_some() => self.vrc7.as_mut().unwrap().write_data(v),
+            0xf800..=0xffff if self.n163.is_some() => self.n163.as_ref().unwrap().set_address(v),
+            _ => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.ram[offset] = v;
+                }
+            }
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[addr as usize % CHR_RAM_SIZE]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        self.chr[addr as usize % CHR_RAM_SIZE] = v;
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        MirrorDirection::Horizontal
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            if let Some(vrc6) = self.vrc6.as_mut() {
+                vrc6.clock();
+            }
+            if let Some(vrc7) = self.vrc7.as_mut() {
+                vrc7.clock();
+            }
+            if let Some(fds) = self.fds.as_mut() {
+                fds.clock();
+            }
+            if let Some(n163) = self.n163.as_mut() {
+                n163.clock();
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |a| a.output())
+            + self.vrc7.as_ref().map_or(0.0, |a| a.output())
+            + self.fds.as_ref().map_or(0.0, |a| a.output())
+            + self.n163.as_ref().map_or(0.0, |a| a.output())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nsf(load_addr: Addr, banks: Option<[u8; 8]>, chips: u8) -> Nsf {
+        Nsf {
+            load_addr,
+            banks,
+            chips,
+            data: (0..16).flat_map(|b| vec![b as u8; 0x1000]).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_nsf_banks() {
+        // Data at its load address
+        let m = NsfMapper::new(&nsf(0x8800, None, 0));
+        assert_eq!([0, 0, 1], [0x8000, 0x8800, 0x9800].map(|a| m.load_prg(a)));
+
+        // The load address is only an offset into the first bank
+        let mut m = NsfMapper::new(&nsf(0x8100, Some([0, 1, 2, 3, 4, 5, 6, 7]), 0));
+        assert_eq!([0, 0], [0x80ff, 0x8100].map(|a| m.load_prg(a)));
+        m.store_prg(0x5fff, 5);
+        assert_eq!(5, m.load_prg(0xf100));
+
+        m.store_prg(0x6123, 0x42);
+        assert_eq!(0x42, m.load_prg(0x6123));
+    }
+
+    #[test]
+    fn test_nsf_fds() {
+        let mut m = NsfMapper::new(&nsf(0x6000, None, Nsf::CHIP_FDS));
+        assert_eq!([0, 2, 9], [0x6000, 0x8000, 0xf000].map(|a| m.load_prg(a)));
+
+        // Code runs from RAM, so it can be written to
+        m.store_prg(0x8000, 0x42);
+        assert_eq!(0x42, m.load_prg(0x8000));
+
+        m.store_prg(0x4089, 0x80);
+        m.store_prg(0x4040, 0x3f);
+        assert_eq!(0x3f, m.load_prg(0x4040));
+    }
+}
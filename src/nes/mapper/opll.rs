@@ -0,0 +1,467 @@
+/**
+ * The VRC7's sound core: a cut-down Yamaha YM2413 (OPLL) with six two
+ * operator FM channels, fifteen built-in instruments and one user defined one.
+ *
+ * Every channel is a modulator operator phase-modulating a carrier operator.
+ * Each operator has its own phase generator and ADSR envelope, and the
+ * modulator can feed back into itself.
+ *
+ * The core runs at 3.58MHz / 72, one sample every 36 CPU cycles.
+ */
+pub struct Opll {
+    address: u8,
+    custom: [u8; 8],
+    channels: [FmChannel; Self::CHANNELS],
+    sine: Vec<f32>,
+    /// Counts CPU cycles up to the next sample
+    divider: u8,
+    eg_counter: u32,
+    lfo_time: f32,
+    output: f32,
+}
+
+/// Built-in instruments of the VRC7, as dumped from the die by Nuke.YKT
+#[rustfmt::skip]
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27], // Buzzy Bell
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12], // Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12], // Wurly
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27], // Flute
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28], // Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4], // Synth
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17], // Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02], // Vibes
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12], // Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02], // Fretless
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6], // Synth Bass
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06], // Sweep
+];
+
+/// Frequency multiplier per MULT value, doubled so it stays integral
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale attenuation at 6dB/octave for block 7, indexed by the top 4 F-number bits
+#[rustfmt::skip]
+const KSL_DB: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25,
+    36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+
+/// Envelope increments per rate row, one column per envelope counter step.
+/// Rows 0-3 serve rates 1-12, rows 4-11 rates 13 and 14, row 12 rate 15.
+#[rustfmt::skip]
+const EG_INCREMENTS: [[u8; 8]; 13] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 2, 1, 1, 1, 2],
+    [1, 2, 1, 2, 1, 2, 1, 2],
+    [1, 2, 2, 2, 1, 2, 2, 2],
+    [2, 2, 2, 2, 2, 2, 2, 2],
+    [2, 2, 2, 4, 2, 2, 2, 4],
+    [2, 4, 2, 4, 2, 4, 2, 4],
+    [2, 4, 4, 4, 2, 4, 4, 4],
+    [4, 4, 4, 4, 4, 4, 4, 4],
+];
+
+impl Opll {
+    const CHANNELS: usize = 6;
+    const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+    const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+    const SINE_BITS: u32 = 10;
+    const PHASE_BITS: u32 = 19;
+
+    /// Envelope levels are in 0.375dB steps, 127 being silence
+    const ENV_MAX: u8 = 127;
+    const ENV_STEP_DB: f32 = 0.375;
+
+    const AM_HZ: f32 = 3.7;
+    const AM_DEPTH_DB: f32 = 4.8;
+    const VIB_HZ: f32 = 6.4;
+    /// Vibrato depth as a fraction of the pitch, about 14 cents
+    const VIB_DEPTH: f32 = 0.008;
+
+    /// Full scale modulator output shifts the carrier phase by this many cycles
+    const MOD_DEPTH: f32 = 2.0;
+    /// A carrier at full volume, relative to the 2A03 mixer's full scale
+    const CHANNEL_LEVEL: f32 = 0.1;
+
+    pub fn new() -> Self {
+        let size = 1 << Self::SINE_BITS;
+        let sine = (0..size)
+            .map(|i| (i as f32 * std::f32::consts::PI * 2.0 / size as f32).sin())
+            .collect();
+
+        Self {
+            address: 0,
+            custom: [0; 8],
+            channels: Default::default(),
+            sine,
+            divider: 0,
+            eg_counter: 0,
+            lfo_time: 0.0,
+            output: 0.0,
+        }
+    }
+
+    /// Silences every channel and clears the registers
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn write_address(&mut self, v: u8) {
+        self.address = v;
+    }
+
+    pub fn write_data(&mut self, v: u8) {
+        let reg = self.address;
+        let n = (reg & 0x0f) as usize;
+
+        match reg {
+            0x00..=0x07 => self.custom[n] = v,
+            0x10..=0x15 => self.channels[n].fnum = (self.channels[n].fnum & 0x100) | v as u16,
+            0x20..=0x25 => {
+                let ch = &mut self.channels[n];
+                ch.fnum = (ch.fnum & 0xff) | (((v & 1) as u16) << 8);
+                ch.block = (v >> 1) & 0b111;
+                ch.sustain = (v & 0x20) != 0;
+
+                let key = (v & 0x10) != 0;
+                match (ch.key, key) {
+                    (false, true) => ch.key_on(),
+                    (true, false) => ch.key_off(),
+                    _ => (),
+                }
+                ch.key = key;
+            }
+            0x30..=0x35 => {
+                self.channels[n].instrument = v >> 4;
+                self.channels[n].volume = v & 0x0f;
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked once per CPU cycle
+    pub fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider == Self::CPU_CYCLES_PER_SAMPLE {
+            self.divider = 0;
+            self.output = self.sample();
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        match instrument {
+            0 => Patch::new(&self.custom),
+            n => Patch::new(&PATCHES[n as usize - 1]),
+        }
+    }
+
+    fn sample(&mut self) -> f32 {
+        self.eg_counter = self.eg_counter.wrapping_add(1);
+        self.lfo_time += 1.0 / Self::SAMPLE_RATE;
+
+        let tau = std::f32::consts::PI * 2.0;
+        let am_db = Self::AM_DEPTH_DB * 0.5 * (1.0 - (tau * Self::AM_HZ * self.lfo_time).cos());
+        let vib = 1.0 + Self::VIB_DEPTH * (tau * Self::VIB_HZ * self.lfo_time).sin();
+
+        let mut mix = 0.0;
+        for n in 0..Self::CHANNELS {
+            let patch = self.patch(self.channels[n].instrument);
+            let eg_counter = self.eg_counter;
+            let sine = &self.sine;
+            mix += self.channels[n].sample(&patch, eg_counter, am_db, vib, sine);
+        }
+        mix * Self::CHANNEL_LEVEL
+    }
+}
+
+/// One of the 16 instruments, decoded from its 8 register bytes
+struct Patch {
+    ops: [OperatorPatch; 2],
+    /// Modulator total level in 0.75dB steps
+    total_level: u8,
+    feedback: u8,
+}
+
+#[derive(Clone, Copy)]
+struct OperatorPatch {
+    am: bool,
+    vibrato: bool,
+    /// Hold the sustain level until key off, otherwise keep decaying
+    sustained: bool,
+    ksr: bool,
+    multiplier: u8,
+    ksl: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl Patch {
+    fn new(regs: &[u8; 8]) -> Self {
+        let op = |n: usize, ksl: u8, rectified: bool| OperatorPatch {
+            am: (regs[n] & 0x80) != 0,
+            vibrato: (regs[n] & 0x40) != 0,
+            sustained: (regs[n] & 0x20) != 0,
+            ksr: (regs[n] & 0x10) != 0,
+            multiplier: regs[n] & 0x0f,
+            ksl,
+            rectified,
+            attack: regs[4 + n] >> 4,
+            decay: regs[4 + n] & 0x0f,
+            sustain_level: regs[6 + n] >> 4,
+            release: regs[6 + n] & 0x0f,
+        };
+
+        Self {
+            ops: [
+                op(0, regs[2] >> 6, (regs[3] & 0x08) != 0),
+                op(1, regs[3] >> 6, (regs[3] & 0x10) != 0),
+            ],
+            total_level: regs[2] & 0x3f,
+            feedback: regs[3] & 0b111,
+        }
+    }
+}
+
+#[derive(Default)]
+struct FmChannel {
+    fnum: u16,
+    block: u8,
+    key: bool,
+    /// Sustain flag from $2x, slows down the release
+    sustain: bool,
+    instrument: u8,
+    /// Carrier attenuation in 3dB steps
+    volume: u8,
+    ops: [Operator; 2],
+    feedback: [f32; 2],
+}
+
+impl FmChannel {
+    fn key_on(&mut self) {
+        self.ops.iter_mut().for_each(|op| {
+            op.phase = 0;
+            op.state = EnvelopeState::Attack;
+        });
+    }
+
+    fn key_off(&mut self) {
+        self.ops
+            .iter_mut()
+            .for_each(|op| op.state = EnvelopeState::Release);
+    }
+
+    fn sample(
+        &mut self,
+        patch: &Patch,
+        eg_counter: u32,
+        am_db: f32,
+        vib: f32,
+        sine: &[f32],
+    ) -> f32 {
+        let ksl_db = (KSL_DB[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32).max(0.0);
+        let rks = (self.block << 1) | (self.fnum >> 8) as u8;
+        let pitch = (self.fnum as u32) << self.block;
+
+        for (op, p) in self.ops.iter_mut().zip(patch.ops.iter()) {
+            op.advance(p, pitch, vib, rks, self.sustain, eg_counter);
+        }
+
+        let [m, c] = &patch.ops;
+        let mod_base_db = patch.total_level as f32 * 0.75;
+        let car_base_db = self.volume as f32 * 3.0;
+
+        let fb = match patch.feedback {
+            0 => 0.0,
+            n => (self.feedback[0] + self.feedback[1]) * 0.5 * (1 << n) as f32 / 64.0,
+        };
+        let modulator = self.ops[0].output(m, mod_base_db, ksl_db, am_db, fb, sine);
+        self.feedback = [self.feedback[1], modulator];
+
+        self.ops[1].output(
+            c,
+            car_base_db,
+            ksl_db,
+            am_db,
+            modulator * Opll::MOD_DEPTH,
+            sine,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    #[default]
+    Release,
+}
+
+struct Operator {
+    phase: u32,
+    env: u8,
+    state: EnvelopeState,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            phase: 0,
+            env: Opll::ENV_MAX,
+            state: EnvelopeState::Release,
+        }
+    }
+}
+
+impl Operator {
+    /// Steps the phase and envelope generators, `pitch` being the F-number shifted by the block
+    fn advance(
+        &mut self,
+        p: &OperatorPatch,
+        pitch: u32,
+        vib: f32,
+        rks: u8,
+        sustain: bool,
+        eg_counter: u32,
+    ) {
+        let step = (pitch * MULTIPLIERS[p.multiplier as usize]) >> 1;
+        let step = match p.vibrato {
+            true => (step as f32 * vib) as u32,
+            false => step,
+        };
+        self.phase = (self.phase + step) & ((1 << Opll::PHASE_BITS) - 1);
+
+        let rks = match p.ksr {
+            true => rks,
+            false => rks >> 2,
+        };
+        let rate = match self.state {
+            EnvelopeState::Attack => p.attack,
+            EnvelopeState::Decay => p.decay,
+            EnvelopeState::Sustain if p.sustained => 0,
+            EnvelopeState::Sustain => p.release,
+            EnvelopeState::Release if sustain => 5,
+            EnvelopeState::Release if p.sustained => p.release,
+            EnvelopeState::Release => 7,
+        };
+
+        let inc = Self::envelope_increment(rate, rks, eg_counter);
+        match self.state {
+            EnvelopeState::Attack if p.attack == 15 => self.env = 0,
+            EnvelopeState::Attack => {
+                let delta = (self.env as u16 * inc as u16) >> 3;
+                let delta = delta.max(inc.min(1) as u16) as u8;
+                self.env = self.env.saturating_sub(delta);
+            }
+            _ => self.env = (self.env + inc).min(Opll::ENV_MAX),
+        }
+
+        self.state = match self.state {
+            EnvelopeState::Attack if self.env == 0 => EnvelopeState::Decay,
+            EnvelopeState::Decay if self.env >= p.sustain_level * 8 => EnvelopeState::Sustain,
+            state => state,
+        };
+    }
+
+    /// How far the envelope moves this sample, for a 4 bit rate scaled by key
+    fn envelope_increment(rate: u8, rks: u8, eg_counter: u32) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+
+        let rate = (rate * 4 + rks).min(63);
+        let (shift, row) = match rate >> 2 {
+            r @ 0..=12 => (13 - r as u32, (rate & 3) as usize),
+            15 => (0, 12),
+            r => (0, (rate & 3) as usize + 4 * (r as usize - 12)),
+        };
+
+        if (eg_counter & ((1 << shift) - 1)) != 0 {
+            return 0;
+        }
+
+        EG_INCREMENTS[row][((eg_counter >> shift) & 7) as usize]
+    }
+
+    fn output(
+        &self,
+        p: &OperatorPatch,
+        base_db: f32,
+        ksl_db: f32,
+        am_db: f32,
+        modulation: f32,
+        sine: &[f32],
+    ) -> f32 {
+        if self.env >= Opll::ENV_MAX {
+            return 0.0;
+        }
+
+        let ksl_db = match p.ksl {
+            0 => 0.0,
+            n => ksl_db / (1 << (3 - n)) as f32,
+        };
+        let am_db = match p.am {
+            true => am_db,
+            false => 0.0,
+        };
+        let db = self.env as f32 * Opll::ENV_STEP_DB + base_db + ksl_db + am_db;
+
+        let size = sine.len() as f32;
+        let index = (self.phase >> (Opll::PHASE_BITS - Opll::SINE_BITS)) as f32 + modulation * size;
+        let wave = sine[index.rem_euclid(size) as usize % sine.len()];
+        let wave = match p.rectified && wave < 0.0 {
+            true => 0.0,
+            false => wave,
+        };
+
+        wave * 10f32.powf(-db / 20.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(opll: &mut Opll, cycles: usize) -> f32 {
+        let mut peak = 0f32;
+        for _ in 0..cycles {
+            opll.clock();
+            peak = peak.max(opll.output().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn test_opll_key_on_off() {
+        let mut opll = Opll::new();
+        assert_eq!(0.0, play(&mut opll, 36 * 100));
+
+        // Flute at full volume, A4
+        let writes = [(0x30, 0x40), (0x10, 0x22), (0x20, 0x19)];
+        writes.iter().for_each(|(a, d)| {
+            opll.write_address(*a);
+            opll.write_data(*d);
+        });
+        assert!(play(&mut opll, 36 * 2000) > 0.01);
+
+        opll.write_address(0x20);
+        opll.write_data(0x09);
+        play(&mut opll, 36 * 50_000);
+        assert_eq!(0.0, play(&mut opll, 36 * 100));
+    }
+}
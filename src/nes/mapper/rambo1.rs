@@ -0,0 +1,245 @@
+use super::*;
+
+/**
+ * Mapper 64: Tengen RAMBO-1, Tengen's take on the MMC3.
+ *
+ * PRG:  three switchable 8kB banks (R6, R7, RF) and the last bank fixed.
+ *       The PRG mode bit rotates which of them sits at $8000.
+ * CHR:  as the MMC3, plus R8 and R9 turning R0 and R1 into four 1kB banks.
+ * IRQ:  an 8 bit counter, clocked either by scanlines or every 4 CPU cycles.
+ *
+ * Registers follow the MMC3 layout, with 16 bank registers instead of 8 and
+ * the IRQ mode in bit 0 of $C001. There is no PRG RAM.
+ */
+pub struct Rambo1 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+
+    bank_select: u8,
+    banks: [u8; 16],
+    mirror: MirrorDirection,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// Count CPU cycles instead of scanlines
+    irq_cycle_mode: bool,
+    prescaler: u8,
+}
+
+impl Rambo1 {
+    const CYCLES_PER_CLOCK: u8 = 4;
+
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirror: MirrorDirection) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            bank_select: 0,
+            banks: [0; 16],
+            mirror,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            irq_cycle_mode: false,
+            prescaler: 0,
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let slot = ((addr >> 13) & 0b11) as usize;
+        let bank = match ((self.bank_select & 0x40) != 0, slot) {
+            (_, 3) => self.prg.len() / 0x2000 - 1,
+            (false, _) => self.banks[[6, 7, 15][slot]] as usize,
+            (true, _) => self.banks[[15, 6, 7][slot]] as usize,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let slot = (addr as usize >> 10) & 7;
+        let slot = match (self.bank_select & 0x80) != 0 {
+            true => slot ^ 4,
+            false => slot,
+        };
+        let full_1k = (self.bank_select & 0x20) != 0;
+
+        let bank = match (slot, full_1k) {
+            (0..=3, false) => (self.banks[slot / 2] & 0xfe) | (slot & 1) as u8,
+            (0..=3, true) => self.banks[[0, 8, 1, 9][slot]],
+            (_, _) => self.banks[slot - 2],
+        };
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+
+    fn clock_irq(&mut self) {
+        if self.irq_reload {
+            self.irq_counter = self.irq_latch.saturating_add(match self.irq_latch {
+                0 | 1 => 1,
+                _ => 2,
+            });
+            self.irq_reload = false;
+        } else if self.irq_counter == 0 {
+            self.irq_counter = self.irq_latch.saturating_add(1);
+        }
+
+        self.irq_counter -= 1;
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Rambo1 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr & 0xe001 {
+            0x8000 => self.bank_select = v,
+            0x8001 => self.banks[(self.bank_select & 0x0f) as usize] = v,
+            0xa000 => {
+                self.mirror = match v & 1 {
+                    0 => MirrorDirection::Vertical,
+                    _ => MirrorDirection::Horizontal,
+                };
+            }
+            0xc000 => self.irq_latch = v,
+            0xc001 => {
+                self.irq_cycle_mode = (v & 1) != 0;
+                self.irq_reload = true;
+                self.prescaler = 0;
+            }
+            0xe000 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xe001 => self.irq_enabled = true,
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        if !self.irq_cycle_mode {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.prescaler += 1;
+            if self.prescaler == Self::CYCLES_PER_CLOCK {
+                self.prescaler = 0;
+                self.clock_irq();
+            }
+        }
+    }
+
+    fn scanline(&mut self) {
+        if !self.irq_cycle_mode {
+            self.clock_irq();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rambo() -> Rambo1 {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..=255).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Rambo1::new(prg, chr, Default::default())
+    }
+
+    fn write_bank(m: &mut Rambo1, select: u8, v: u8) {
+        m.store_prg(0x8000, select);
+        m.store_prg(0x8001, v);
+    }
+
+    #[test]
+    fn test_rambo1_banking() {
+        let mut m = rambo();
+        write_bank(&mut m, 6, 1);
+        write_bank(&mut m, 7, 2);
+        write_bank(&mut m, 15, 3);
+        assert_eq!(
+            [1, 2, 3, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+
+        m.store_prg(0x8000, 0x40);
+        assert_eq!(
+            [3, 1, 2, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+
+        // 1kB mode splits R0 and R1 with R8 and R9
+        write_bank(&mut m, 0x20, 0x10);
+        write_bank(&mut m, 0x28, 0x33);
+        assert_eq!(0x10, m.load_chr(0x0000));
+        assert_eq!(0x33, m.load_chr(0x0400));
+    }
+
+    #[test]
+    fn test_rambo1_cycle_irq() {
+        let mut m = rambo();
+        m.store_prg(0xc000, 3);
+        m.store_prg(0xc001, 1);
+        m.store_prg(0xe001, 0);
+
+        // Reloads to latch + 2, then counts down every 4 cycles
+        m.tick(4 * 4);
+        assert!(!m.irq());
+        m.tick(4);
+        assert!(m.irq());
+
+        // Scanlines are ignored in cycle mode
+        m.store_prg(0xe000, 0);
+        m.store_prg(0xe001, 0);
+        (0..10).for_each(|_| m.scanline());
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn test_rambo1_scanline_irq() {
+        let mut m = rambo();
+        m.store_prg(0xc000, 1);
+        m.store_prg(0xc001, 0);
+        m.store_prg(0xe001, 0);
+
+        m.tick(100);
+        assert!(!m.irq());
+        m.scanline();
+        assert!(!m.irq());
+        m.scanline();
+        assert!(m.irq());
+    }
+}
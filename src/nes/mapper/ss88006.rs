@@ -0,0 +1,243 @@
+use super::*;
+
+/**
+ * Mapper 18: Jaleco SS88006.
+ *
+ * PRG:  three switchable 8kB banks, the last bank fixed at $E000.
+ *       $6000-$7FFF 8kB RAM, battery backed on some boards.
+ * CHR:  eight switchable 1kB banks.
+ * IRQ:  a 16 bit counter decremented every CPU cycle, of which only the low
+ *       4, 8, 12 or all 16 bits count.
+ *
+ * Every bank number is written a nibble at a time, low nibble first:
+ *   $8000-$8003 $9000-$9001  PRG banks     $9002 ......WE  PRG RAM write, enable
+ *   $A000-$DFFF              CHR banks, two per 4kB of CPU space
+ *   $E000-$E003  IRQ reload value, lowest nibble first
+ *   $F000  reload the IRQ counter      $F001 ....SSSE  IRQ size, enable
+ *   $F002  ......MM  mirroring         $F003 sound (not emulated)
+ */
+pub struct Ss88006 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    has_battery: bool,
+    ram_enabled: bool,
+    ram_writable: bool,
+
+    prg_regs: [u8; 3],
+    chr_regs: [u8; 8],
+    mirror: MirrorDirection,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_mask: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+/// Set one nibble of a bank register, `high` picking which
+fn set_nibble(reg: &mut u8, high: bool, v: u8) {
+    *reg = match high {
+        false => (*reg & 0xf0) | (v & 0x0f),
+        true => (*reg & 0x0f) | ((v & 0x0f) << 4),
+    };
+}
+
+impl Ss88006 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, has_battery: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            ram_enabled: false,
+            ram_writable: false,
+            prg_regs: [0; 3],
+            chr_regs: [0; 8],
+            mirror: MirrorDirection::Horizontal,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_mask: 0xffff,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let bank = match addr {
+            0x8000..=0x9fff => self.prg_regs[0] as usize,
+            0xa000..=0xbfff => self.prg_regs[1] as usize,
+            0xc000..=0xdfff => self.prg_regs[2] as usize,
+            _ => self.prg.len() / 0x2000 - 1,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 10) & 7];
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for Ss88006 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        let high = (addr & 1) != 0;
+        let reg = addr & 0xf003;
+        match reg {
+            0x6000..=0x7fff if self.ram_enabled && self.ram_writable => {
+                self.ram[(addr - 0x6000) as usize] = v;
+            }
+            0x8000..=0x8003 => set_nibble(&mut self.prg_regs[(addr & 2) as usize >> 1], high, v),
+            0x9000 | 0x9001 => set_nibble(&mut self.prg_regs[2], high, v),
+            0x9002 => {
+                self.ram_enabled = (v & 1) != 0;
+                self.ram_writable = (v & 2) != 0;
+            }
+            0xa000..=0xdfff => {
+                let bank = (((reg - 0xa000) >> 11) | ((reg >> 1) & 1)) as usize;
+                set_nibble(&mut self.chr_regs[bank], high, v);
+            }
+            0xe000..=0xe003 => {
+                let shift = (addr & 3) * 4;
+                self.irq_reload = (self.irq_reload & !(0xf << shift)) | ((v as u16 & 0xf) << shift);
+            }
+            0xf000 => {
+                self.irq_counter = self.irq_reload;
+                self.irq_pending = false;
+            }
+            0xf001 => {
+                self.irq_enabled = (v & 1) != 0;
+                self.irq_mask = if (v & 0x08) != 0 {
+                    0x000f
+                } else if (v & 0x04) != 0 {
+                    0x00ff
+                } else if (v & 0x02) != 0 {
+                    0x0fff
+                } else {
+                    0xffff
+                };
+                self.irq_pending = false;
+            }
+            0xf002 => {
+                self.mirror = match v & 0b11 {
+                    0 => MirrorDirection::Horizontal,
+                    1 => MirrorDirection::Vertical,
+                    2 => MirrorDirection::SingleScreenLower,
+                    _ => MirrorDirection::SingleScreenUpper,
+                };
+            }
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    /// Only the bits under the size mask count down, the rest hold still
+    fn tick(&mut self, cycles: usize) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        for _ in 0..cycles {
+            let count = (self.irq_counter & self.irq_mask).wrapping_sub(1) & self.irq_mask;
+            if count == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = (self.irq_counter & !self.irq_mask) | count;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some(self.ram.clone()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jaleco() -> Ss88006 {
+        let prg = (0..32).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..=255).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Ss88006::new(prg, chr, false)
+    }
+
+    #[test]
+    fn test_ss88006_banking() {
+        let mut m = jaleco();
+        m.store_prg(0x8002, 0x05);
+        m.store_prg(0x8003, 0x01);
+        m.store_prg(0x9000, 0x07);
+        assert_eq!(
+            [0, 0x15, 7, 31],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+
+        m.store_prg(0xd002, 0x0c);
+        m.store_prg(0xd003, 0x0a);
+        assert_eq!(0xac, m.load_chr(0x1c00));
+
+        // RAM needs both enable and write bits to take a write
+        m.store_prg(0x9002, 0x01);
+        m.store_prg(0x6000, 0x42);
+        assert_eq!(0, m.load_prg(0x6000));
+        m.store_prg(0x9002, 0x03);
+        m.store_prg(0x6000, 0x42);
+        assert_eq!(0x42, m.load_prg(0x6000));
+    }
+
+    #[test]
+    fn test_ss88006_irq_size() {
+        let mut m = jaleco();
+        m.store_prg(0xe000, 0x3);
+        m.store_prg(0xe003, 0xf);
+        m.store_prg(0xf000, 0);
+
+        // 4 bit counter, the high bits stay put
+        m.store_prg(0xf001, 0x09);
+        m.tick(2);
+        assert!(!m.irq());
+        m.tick(1);
+        assert!(m.irq());
+        assert_eq!(0xf000, m.irq_counter);
+
+        m.store_prg(0xf001, 0x00);
+        assert!(!m.irq());
+    }
+}
@@ -0,0 +1,216 @@
+use super::*;
+
+/**
+ * Mapper 68: Sunsoft-4.
+ *
+ * PRG:  16kB switchable at $8000, last bank fixed at $C000,
+ *       $6000-$7FFF 8kB RAM, battery backed on some boards.
+ * CHR:  four switchable 2kB banks.
+ * Nametables: console VRAM, or two 1kB banks from the top 128kB of CHR ROM.
+ *
+ *   $8000-$BFFF CHR banks, one register per 4kB of CPU space
+ *   $C000 $D000 nametable banks
+ *   $E000       ...N..MM  nametables from CHR ROM, mirroring
+ *   $F000       ...RPPPP  PRG RAM enable, PRG bank
+ *
+ * With CHR ROM nametables the mirroring bits still say which of the two
+ * nametable banks each quadrant of $2000-$2FFF shows.
+ */
+pub struct Sunsoft4 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    has_battery: bool,
+
+    prg_bank: u8,
+    ram_enabled: bool,
+    chr_banks: [u8; 4],
+    nt_banks: [u8; 2],
+    /// $E000
+    control: u8,
+}
+
+impl Sunsoft4 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, has_battery: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            prg_bank: 0,
+            ram_enabled: false,
+            chr_banks: [0; 4],
+            nt_banks: [0; 2],
+            control: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_banks[(addr as usize >> 11) & 3];
+        bank_offset(&self.chr, 0x800, bank as usize, addr as usize)
+    }
+
+    fn chr_nametables(&self) -> bool {
+        (self.control & 0x10) != 0
+    }
+
+    /// CHR ROM offset of a nametable address, in CHR ROM nametable mode
+    fn nametable_offset(&self, addr: Addr) -> usize {
+        // The quadrant's VRAM page picks one of the two banks instead
+        let page = vram_offset(self.mirror(), addr) / 0x400;
+        let bank = 0x80 | self.nt_banks[page] as usize;
+        bank_offset(&self.chr, 0x400, bank, addr as usize)
+    }
+}
+
+impl Mapper for Sunsoft4 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xbfff => {
+                self.prg[bank_offset(&self.prg, 0x4000, self.prg_bank as usize, addr as usize)]
+            }
+            0xc000..=0xffff => {
+                let last = self.prg.len() / 0x4000 - 1;
+                self.prg[bank_offset(&self.prg, 0x4000, last, addr as usize)]
+            }
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled => self.ram[(addr - 0x6000) as usize] = v,
+            0x8000..=0xbfff => self.chr_banks[((addr - 0x8000) >> 12) as usize] = v,
+            0xc000..=0xdfff => self.nt_banks[((addr - 0xc000) >> 12) as usize] = v & 0x7f,
+            0xe000..=0xefff => self.control = v,
+            0xf000..=0xffff => {
+                self.prg_bank = v & 0x0f;
+                self.ram_enabled = (v & 0x10) != 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        match self.control & 0b11 {
+            0 => MirrorDirection::Vertical,
+            1 => MirrorDirection::Horizontal,
+            2 => MirrorDirection::SingleScreenLower,
+            _ => MirrorDirection::SingleScreenUpper,
+        }
+    }
+
+    fn load_ppu(&self, addr: Addr, vram: &[u8]) -> u8 {
+        match (addr, self.chr_nametables()) {
+            (0x0000..=0x1fff, _) => self.load_chr(addr),
+            (_, true) => self.chr[self.nametable_offset(addr)],
+            (_, false) => vram[vram_offset(self.mirror(), addr)],
+        }
+    }
+
+    fn store_ppu(&mut self, addr: Addr, v: u8, vram: &mut [u8]) {
+        match (addr, self.chr_nametables()) {
+            (0x0000..=0x1fff, _) => self.store_chr(addr, v),
+            (_, true) => (),
+            (_, false) => vram[vram_offset(self.mirror(), addr)] = v,
+        }
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some(self.ram.clone()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sunsoft4_chr_nametables() {
+        // 256kB of CHR, each 1kB bank filled with its own number
+        let chr = (0..=255).flat_map(|b| vec![b as u8; 0x400]).collect();
+        let mut m = Sunsoft4::new(vec![0; 0x20000], chr, false);
+        let mut vram = vec![0; VRAM_SIZE];
+
+        m.store_prg(0xc000, 0x05);
+        m.store_prg(0xd000, 0x06);
+        m.store_ppu(0x2400, 0x11, &mut vram);
+        assert_eq!(0x11, m.load_ppu(0x2400, &vram));
+
+        // Vertical mirroring, from CHR ROM banks $85 and $86
+        m.store_prg(0xe000, 0x10);
+        assert_eq!(0x85, m.load_ppu(0x2000, &vram));
+        assert_eq!(0x86, m.load_ppu(0x2400, &vram));
+        assert_eq!(0x85, m.load_ppu(0x2800, &vram));
+
+        // Horizontal
+        m.store_prg(0xe000, 0x11);
+        assert_eq!(0x85, m.load_ppu(0x2400, &vram));
+        assert_eq!(0x86, m.load_ppu(0x2800, &vram));
+
+        // CHR ROM can't be written through the nametables
+        m.store_ppu(0x2000, 0xff, &mut vram);
+        assert_eq!(0x85, m.load_ppu(0x2000, &vram));
+    }
+
+    #[test]
+    fn test_sunsoft4_banking() {
+        let prg = (0..8).flat_map(|b| vec![b as u8; 0x4000]).collect();
+        let chr = (0..128).flat_map(|b| vec![b as u8; 0x800]).collect();
+        let mut m = Sunsoft4::new(prg, chr, false);
+
+        m.store_prg(0xf000, 0x03);
+        m.store_prg(0xb000, 0x42);
+        assert_eq!(3, m.load_prg(0x8000));
+        assert_eq!(7, m.load_prg(0xc000));
+        assert_eq!(0x42, m.load_chr(0x1800));
+
+        // PRG RAM is only there when enabled
+        m.store_prg(0x6000, 0x99);
+        assert_eq!(0x60, m.load_prg(0x6000));
+        m.store_prg(0xf000, 0x13);
+        m.store_prg(0x6000, 0x99);
+        assert_eq!(0x99, m.load_prg(0x6000));
+    }
+
+    #[test]
+    fn test_sunsoft4_battery_ram() {
+        let prg = vec![0; 0x8000];
+        assert!(Sunsoft4::new(prg.clone(), vec![], false)
+            .battery_ram()
+            .is_none());
+
+        let mut m = Sunsoft4::new(prg.clone(), vec![], true);
+        m.store_prg(0xf000, 0x10);
+        m.store_prg(0x6123, 0x42);
+        let saved = m.battery_ram().unwrap();
+
+        let mut other = Sunsoft4::new(prg, vec![], true);
+        other.restore_battery_ram(&saved);
+        other.store_prg(0xf000, 0x10);
+        assert_eq!(0x42, other.load_prg(0x6123));
+    }
+}
@@ -0,0 +1,179 @@
+use super::mmc3::ScanlineCounter;
+use super::*;
+
+/**
+ * Mappers 33 and 48: Taito TC0190 and its successor, the TC0690.
+ *
+ * PRG:  two switchable 8kB banks and the last two banks fixed.
+ * CHR:  two switchable 2kB banks then four 1kB banks.
+ *
+ *   $8000 .MPPPPPP  mirroring (TC0190 only), PRG bank at $8000
+ *   $8001 ..PPPPPP  PRG bank at $A000
+ *   $8002 $8003     2kB CHR banks
+ *   $A000-$A003     1kB CHR banks
+ *
+ * The TC0690 moves mirroring to bit 6 of $E000 and adds an MMC3 style
+ * scanline IRQ: $C000 latch, $C001 reload, $C002 enable, $C003 disable.
+ */
+pub struct Tc0190 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// The TC0690, with an IRQ
+    tc0690: bool,
+
+    prg_regs: [u8; 2],
+    chr_regs: [u8; 6],
+    mirror: MirrorDirection,
+
+    irq: ScanlineCounter,
+}
+
+impl Tc0190 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, tc0690: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            tc0690,
+            prg_regs: [0; 2],
+            chr_regs: [0; 6],
+            mirror: MirrorDirection::Vertical,
+            irq: ScanlineCounter::default(),
+        }
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let last = self.prg.len() / 0x2000 - 1;
+        let bank = match (addr >> 13) & 0b11 {
+            0 => self.prg_regs[0] as usize,
+            1 => self.prg_regs[1] as usize,
+            2 => last - 1,
+            _ => last,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let slot = (addr as usize >> 10) & 7;
+        let bank = match slot {
+            0..=3 => ((self.chr_regs[slot / 2] as usize) << 1) | (slot & 1),
+            _ => self.chr_regs[slot - 2] as usize,
+        };
+        bank_offset(&self.chr, 0x400, bank, addr as usize)
+    }
+
+    fn set_mirror(&mut self, v: u8) {
+        self.mirror = match v & 0x40 {
+            0 => MirrorDirection::Vertical,
+            _ => MirrorDirection::Horizontal,
+        };
+    }
+}
+
+impl Mapper for Tc0190 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match (addr & 0xe003, self.tc0690) {
+            (0x8000, false) => {
+                self.prg_regs[0] = v & 0x3f;
+                self.set_mirror(v);
+            }
+            (0x8000, true) => self.prg_regs[0] = v & 0x3f,
+            (0x8001, _) => self.prg_regs[1] = v & 0x3f,
+            (0x8002, _) => self.chr_regs[0] = v,
+            (0x8003, _) => self.chr_regs[1] = v,
+            (0xa000..=0xa003, _) => self.chr_regs[2 + (addr & 3) as usize] = v,
+            (0xc000, true) => self.irq.latch = v,
+            (0xc001, true) => self.irq.reload(),
+            (0xc002, true) => self.irq.enabled = true,
+            (0xc003, true) => self.irq.disable(),
+            (0xe000, true) => self.set_mirror(v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        self.mirror
+    }
+
+    fn scanline(&mut self) {
+        if self.tc0690 {
+            self.irq.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn taito(tc0690: bool) -> Tc0190 {
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..=255).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Tc0190::new(prg, chr, tc0690)
+    }
+
+    #[test]
+    fn test_tc0190_banking() {
+        let mut m = taito(false);
+        m.store_prg(0x8000, 0x43);
+        m.store_prg(0x8001, 0x04);
+        m.store_prg(0x8003, 0x10);
+        m.store_prg(0xa002, 0x77);
+
+        assert_eq!(
+            [3, 4, 14, 15],
+            [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.load_prg(a))
+        );
+        assert_eq!(0x21, m.load_chr(0x0c00));
+        assert_eq!(0x77, m.load_chr(0x1800));
+        assert_eq!(MirrorDirection::Horizontal, m.mirror());
+
+        // No IRQ on the TC0190
+        m.store_prg(0xc002, 0);
+        (0..300).for_each(|_| m.scanline());
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn test_tc0690() {
+        let mut m = taito(true);
+        m.store_prg(0x8000, 0x43);
+        assert_eq!(MirrorDirection::Vertical, m.mirror());
+        m.store_prg(0xe000, 0x40);
+        assert_eq!(MirrorDirection::Horizontal, m.mirror());
+
+        m.store_prg(0xc000, 1);
+        m.store_prg(0xc001, 0);
+        m.store_prg(0xc002, 0);
+        m.scanline();
+        assert!(!m.irq());
+        m.scanline();
+        assert!(m.irq());
+        m.store_prg(0xc003, 0);
+        assert!(!m.irq());
+    }
+}
@@ -0,0 +1,300 @@
+use super::vrc_irq::VrcIrq;
+use super::*;
+
+/**
+ * Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4.
+ *
+ * PRG:  two switchable 8kB banks plus the last two banks fixed. The VRC4 can
+ *       swap the switchable $8000 bank with the fixed $C000 one.
+ * CHR:  eight 1kB banks, each written a nibble at a time.
+ * IRQ:  the VRC IRQ counter (VRC4 only).
+ *
+ * The chip only sees two register address lines, and every board wires them
+ * to different CPU address lines. The mapper number and NES 2.0 submapper say
+ * which, and when the submapper is unknown both candidate wirings are ORed
+ * together, which works for every known game.
+ *
+ * VRC2 boards without PRG RAM have a one bit latch at $6000-$6FFF instead,
+ * meant for a microwire EEPROM that was never fitted, and some games check it.
+ */
+pub struct Vrc24 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Option<Vec<u8>>,
+    has_battery: bool,
+    chip: VrcChip,
+    /// CPU address bits feeding the chip's A0 and A1
+    lines: [Addr; 2],
+    /// VRC2a ignores the lowest bit of its CHR banks
+    chr_shift: u8,
+
+    prg_regs: [u8; 2],
+    prg_swap: bool,
+    chr_regs: [u16; 8],
+    mirror: u8,
+    microwire: u8,
+
+    irq: VrcIrq,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VrcChip {
+    Vrc2,
+    Vrc4,
+}
+
+impl Vrc24 {
+    const A0: Addr = 1 << 0;
+    const A1: Addr = 1 << 1;
+    const A2: Addr = 1 << 2;
+    const A3: Addr = 1 << 3;
+    const A6: Addr = 1 << 6;
+    const A7: Addr = 1 << 7;
+
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mapper: u8, submapper: u8, has_battery: bool) -> Self {
+        let (chip, lines) = Self::wiring(mapper, submapper);
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+
+        let ram = match (chip, has_battery) {
+            (VrcChip::Vrc2, false) => None,
+            _ => Some(vec![0; PRG_RAM_SIZE]),
+        };
+
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram,
+            has_battery,
+            chip,
+            lines,
+            chr_shift: (mapper == 22) as u8,
+            prg_regs: [0; 2],
+            prg_swap: false,
+            chr_regs: [0; 8],
+            mirror: 0,
+            microwire: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    /// Which chip sits on the board, and the CPU address lines used as its A0 and A1
+    pub fn wiring(mapper: u8, submapper: u8) -> (VrcChip, [Addr; 2]) {
+        match (mapper, submapper) {
+            (21, 1) => (VrcChip::Vrc4, [Self::A1, Self::A2]),
+            (21, 2) => (VrcChip::Vrc4, [Self::A6, Self::A7]),
+            (21, _) => (VrcChip::Vrc4, [Self::A1 | Self::A6, Self::A2 | Self::A7]),
+            (22, _) => (VrcChip::Vrc2, [Self::A1, Self::A0]),
+            (23, 1) => (VrcChip::Vrc4, [Self::A0, Self::A1]),
+            (23, 2) => (VrcChip::Vrc4, [Self::A2, Self::A3]),
+            (23, 3) => (VrcChip::Vrc2, [Self::A0, Self::A1]),
+            (23, _) => (VrcChip::Vrc4, [Self::A0 | Self::A2, Self::A1 | Self::A3]),
+            (25, 1) => (VrcChip::Vrc4, [Self::A1, Self::A0]),
+            (25, 2) => (VrcChip::Vrc4, [Self::A3, Self::A2]),
+            (25, 3) => (VrcChip::Vrc2, [Self::A1, Self::A0]),
+            (_, _) => (VrcChip::Vrc4, [Self::A1 | Self::A3, Self::A0 | Self::A2]),
+        }
+    }
+
+    /// Translates a CPU address into the $x000-$x003 register it selects
+    fn register(&self, addr: Addr) -> Addr {
+        let a0 = ((addr & self.lines[0]) != 0) as Addr;
+        let a1 = ((addr & self.lines[1]) != 0) as Addr;
+        (addr & 0xf000) | (a1 << 1) | a0
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let last = self.prg.len() / 0x2000 - 1;
+        let bank = match (addr & 0x6000, self.prg_swap) {
+            (0x0000, false) => self.prg_regs[0] as usize,
+            (0x0000, true) => last - 1,
+            (0x2000, _) => self.prg_regs[1] as usize,
+            (0x4000, false) => last - 1,
+            (0x4000, true) => self.prg_regs[0] as usize,
+            (_, _) => last,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 10) & 7] >> self.chr_shift;
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+
+    fn write_register(&mut self, addr: Addr, v: u8) {
+        let reg = self.register(addr);
+        match (reg, self.chip) {
+            (0x8000..=0x8003, _) => self.prg_regs[0] = v & 0x1f,
+            (0x9000..=0x9003, VrcChip::Vrc2) => self.mirror = v & 1,
+            (0x9000..=0x9001, VrcChip::Vrc4) => self.mirror = v & 0b11,
+            // Bit 0 is meant to write protect PRG RAM, but as with most
+            // emulators the RAM is left always enabled
+            (0x9002..=0x9003, VrcChip::Vrc4) => self.prg_swap = (v & 0b10) != 0,
+            (0xa000..=0xa003, _) => self.prg_regs[1] = v & 0x1f,
+            (0xb000..=0xefff, _) => self.write_chr(reg, v),
+            (0xf000, VrcChip::Vrc4) => self.irq.set_latch_low(v),
+            (0xf001, VrcChip::Vrc4) => self.irq.set_latch_high(v),
+            (0xf002, VrcChip::Vrc4) => self.irq.set_control(v),
+            (0xf003, VrcChip::Vrc4) => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+
+    /// $B000-$E003: every pair of registers holds the low and high nibble of one bank
+    fn write_chr(&mut self, reg: Addr, v: u8) {
+        let n = (((reg - 0xb000) >> 12) * 2 + ((reg >> 1) & 1)) as usize;
+        let high_mask = match self.chip {
+            VrcChip::Vrc2 => 0x0f,
+            VrcChip::Vrc4 => 0x1f,
+        };
+
+        self.chr_regs[n] = match reg & 1 {
+            0 => (self.chr_regs[n] & 0x1f0) | (v & 0x0f) as u16,
+            _ => (self.chr_regs[n] & 0x00f) | (((v & high_mask) as u16) << 4),
+        };
+    }
+}
+
+impl Mapper for Vrc24 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match (addr, &self.ram) {
+            (0x6000..=0x7fff, Some(ram)) => ram[(addr - 0x6000) as usize],
+            (0x6000..=0x6fff, None) => (open_bus(addr) & 0xfe) | self.microwire,
+            (0x8000..=0xffff, _) => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match (addr, &mut self.ram) {
+            (0x6000..=0x7fff, Some(ram)) => ram[(addr - 0x6000) as usize] = v,
+            (0x6000..=0x6fff, None) => self.microwire = v & 1,
+            (0x8000..=0xffff, _) => self.write_register(addr, v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        match self.mirror {
+            0 => MirrorDirection::Vertical,
+            1 => MirrorDirection::Horizontal,
+            2 => MirrorDirection::SingleScreenLower,
+            _ => MirrorDirection::SingleScreenUpper,
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        if self.chip == VrcChip::Vrc4 {
+            (0..cycles).for_each(|_| self.irq.clock());
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => self.ram.clone(),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        if let Some(ram) = self.ram.as_mut() {
+            let len = data.len().min(ram.len());
+            ram[..len].copy_from_slice(&data[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vrc(mapper: u8, submapper: u8) -> Vrc24 {
+        // 32 banks of 8kB PRG and 256 banks of 1kB CHR, each filled with its bank number
+        let prg = (0..32).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..256).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Vrc24::new(prg, chr, mapper, submapper, false)
+    }
+
+    #[test]
+    fn test_vrc24_submapper_wiring() {
+        // CHR bank 1 low nibble is the chip's $B002
+        [(21, 1, 0xb004), (21, 2, 0xb080), (23, 1, 0xb002), (23, 2, 0xb008)]
+            .iter()
+            .for_each(|(mapper, submapper, addr)| {
+                let mut m = vrc(*mapper, *submapper);
+                m.store_prg(*addr, 0x7);
+                assert_eq!(7, m.load_chr(0x0400), "mapper {} sub {}", mapper, submapper);
+            });
+
+        // Unknown submappers respond on both wirings
+        // VRC4b has the chip's $B003 at $B003, VRC4d has $B002 at $B004
+        let mut m = vrc(25, 0);
+        m.store_prg(0xb003, 0x1);
+        m.store_prg(0xb004, 0x3);
+        assert_eq!(0x13, m.load_chr(0x0400));
+    }
+
+    #[test]
+    fn test_vrc4_prg_swap() {
+        let mut m = vrc(23, 1);
+        m.store_prg(0x8000, 4);
+        assert_eq!(4, m.load_prg(0x8000));
+        assert_eq!(30, m.load_prg(0xc000));
+        assert_eq!(31, m.load_prg(0xe000));
+
+        m.store_prg(0x9002, 0b10);
+        assert_eq!(30, m.load_prg(0x8000));
+        assert_eq!(4, m.load_prg(0xc000));
+    }
+
+    #[test]
+    fn test_vrc2_microwire_latch() {
+        let mut m = vrc(22, 0);
+        m.store_prg(0x6000, 0xff);
+        assert_eq!(1, m.load_prg(0x6000) & 1);
+        m.store_prg(0x6000, 0xfe);
+        assert_eq!(0, m.load_prg(0x6000) & 1);
+    }
+
+    #[test]
+    fn test_vrc24_battery_ram() {
+        // VRC4 boards always have RAM, but only keep it with a battery
+        let mut m = vrc(25, 0);
+        m.store_prg(0x7000, 0x42);
+        assert!(m.battery_ram().is_none());
+
+        let prg: Vec<u8> = vec![0; 0x8000];
+        let mut m = Vrc24::new(prg.clone(), vec![], 22, 0, true);
+        m.store_prg(0x7000, 0x42);
+        let saved = m.battery_ram().unwrap();
+
+        let mut other = Vrc24::new(prg, vec![], 22, 0, true);
+        other.restore_battery_ram(&saved);
+        assert_eq!(0x42, other.load_prg(0x7000));
+    }
+
+    #[test]
+    fn test_vrc2a_chr_shift() {
+        let mut m = vrc(22, 0);
+        // Chip $B001 is CPU $B002 on VRC2a
+        m.store_prg(0xb002, 0x1);
+        m.store_prg(0xb000, 0x5);
+        assert_eq!(0x0a, m.load_chr(0x0000));
+    }
+}
@@ -0,0 +1,407 @@
+use super::vrc_irq::VrcIrq;
+use super::*;
+
+/**
+ * Mappers 24 and 26: Konami VRC6.
+ *
+ * PRG:  $8000-$BFFF 16kB switchable, $C000-$DFFF 8kB switchable,
+ *       $E000-$FFFF 8kB fixed to the last bank, $6000-$7FFF 8kB RAM,
+ *       battery backed on some boards.
+ * CHR:  eight 1kB registers, combined into 2kB banks by some banking modes.
+ * IRQ:  the VRC IRQ counter.
+ * Audio: two pulse channels and a sawtooth.
+ *
+ * Mapper 26 (VRC6b) wires CPU A0 and A1 to the chip the other way around.
+ */
+pub struct Vrc6 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    has_battery: bool,
+    swap_lines: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_regs: [u8; 8],
+    /// $B003: W.PNMMDD
+    banking: u8,
+
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, swap_lines: bool, has_battery: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            swap_lines,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_regs: [0; 8],
+            banking: 0,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::default(),
+        }
+    }
+
+    /// Registers are decoded from A15-A12 plus A1 and A0
+    fn register(&self, addr: Addr) -> Addr {
+        let addr = addr & 0xf003;
+        match self.swap_lines {
+            true => (addr & 0xf000) | ((addr & 1) << 1) | ((addr & 2) >> 1),
+            false => addr,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        (self.banking & 0x80) != 0
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let slot = (addr as usize >> 10) & 7;
+        let bank = match (self.banking & 0b11, slot) {
+            (0, _) => self.chr_regs[slot],
+            (1, _) => self.chr_2k(self.chr_regs[slot / 2], addr),
+            (_, 0..=3) => self.chr_regs[slot],
+            (_, _) => self.chr_2k(self.chr_regs[4 + (slot - 4) / 2], addr),
+        };
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+
+    /// 2kB banks take CHR A10 from the PPU, unless bit 5 of $B003 says otherwise
+    fn chr_2k(&self, reg: u8, addr: Addr) -> u8 {
+        match self.banking & 0x20 {
+            0 => (reg & 0xfe) | ((addr >> 10) & 1) as u8,
+            _ => reg,
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xbfff => {
+                self.prg[bank_offset(&self.prg, 0x4000, self.prg_16k as usize, addr as usize)]
+            }
+            0xc000..=0xdfff => {
+                self.prg[bank_offset(&self.prg, 0x2000, self.prg_8k as usize, addr as usize)]
+            }
+            0xe000..=0xffff => {
+                let last = self.prg.len() / 0x2000 - 1;
+                self.prg[bank_offset(&self.prg, 0x2000, last, addr as usize)]
+            }
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        if addr < 0x8000 {
+            if (0x6000..=0x7fff).contains(&addr) && self.ram_enabled() {
+                self.ram[(addr - 0x6000) as usize] = v;
+            }
+            return;
+        }
+
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8003 => self.prg_16k = v & 0x0f,
+            0x9000..=0x9003 | 0xa000..=0xa002 | 0xb000..=0xb002 => self.audio.store(reg, v),
+            0xb003 => self.banking = v,
+            0xc000..=0xc003 => self.prg_8k = v & 0x1f,
+            0xd000..=0xd003 => self.chr_regs[addr_low(reg) as usize] = v,
+            0xe000..=0xe003 => self.chr_regs[4 + addr_low(reg) as usize] = v,
+            0xf000 => self.irq.set_latch(v),
+            0xf001 => self.irq.set_control(v),
+            0xf002 => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        match (self.banking >> 2) & 0b11 {
+            0 => MirrorDirection::Vertical,
+            1 => MirrorDirection::Horizontal,
+            2 => MirrorDirection::SingleScreenLower,
+            _ => MirrorDirection::SingleScreenUpper,
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some(self.ram.clone()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+fn addr_low(addr: Addr) -> u8 {
+    (addr & 0b11) as u8
+}
+
+/**
+ * VRC6 expansion audio, clocked at the CPU rate.
+ */
+#[derive(Default)]
+pub(super) struct Vrc6Audio {
+    pulse: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halt: bool,
+    /// Right shift applied to every channel period by $9003
+    freq_shift: u8,
+}
+
+impl Vrc6Audio {
+    /// A VRC6 pulse at full volume is about as loud as a 2A03 pulse at full volume
+    const LEVEL: f32 = 0.1494 / 15.0;
+
+    /// Writes $9000-$9003, $A000-$A002 or $B000-$B002, with the address
+    /// lines already in the standard order
+    pub(super) fn store(&mut self, reg: Addr, v: u8) {
+        match reg {
+            0x9000..=0x9002 => self.pulse[0].write(addr_low(reg), v),
+            0x9003 => self.write_control(v),
+            0xa000..=0xa002 => self.pulse[1].write(addr_low(reg), v),
+            0xb000..=0xb002 => self.saw.write(addr_low(reg), v),
+            _ => (),
+        }
+    }
+
+    fn write_control(&mut self, v: u8) {
+        self.halt = (v & 1) != 0;
+        self.freq_shift = match v & 0b110 {
+            0b000 => 0,
+            0b010 => 4,
+            _ => 8,
+        };
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        let shift = self.freq_shift;
+        self.pulse.iter_mut().for_each(|p| p.clock(shift));
+        self.saw.clock(shift);
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        let level = self.pulse[0].output() + self.pulse[1].output() + self.saw.output();
+        level as f32 * Self::LEVEL
+    }
+}
+
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    /// Ignore the duty cycle and output the volume constantly
+    constant: bool,
+    period: u16,
+    enabled: bool,
+    divider: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, reg: u8, v: u8) {
+        match reg {
+            0 => {
+                self.constant = (v & 0x80) != 0;
+                self.duty = (v >> 4) & 0b111;
+                self.volume = v & 0x0f;
+            }
+            1 => self.period = (self.period & 0x0f00) | v as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (((v & 0x0f) as u16) << 8);
+                self.enabled = (v & 0x80) != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.divider == 0 {
+            self.divider = self.period >> shift;
+            self.step = self.step.checked_sub(1).unwrap_or(15);
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        match self.enabled && (self.constant || self.step <= self.duty) {
+            true => self.volume,
+            false => 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    divider: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, reg: u8, v: u8) {
+        match reg {
+            0 => self.rate = v & 0x3f,
+            1 => self.period = (self.period & 0x0f00) | v as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (((v & 0x0f) as u16) << 8);
+                self.enabled = (v & 0x80) != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    /// The rate is added on every second step, and the 14th step resets the accumulator
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if (self.step & 1) == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// Only the top 5 bits of the accumulator reach the DAC
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vrc6(swap_lines: bool) -> Vrc6 {
+        // 16 banks of 8kB, each filled with its own bank number
+        let prg = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        Vrc6::new(prg, vec![], swap_lines, false)
+    }
+
+    #[test]
+    fn test_vrc6_prg_banking() {
+        let mut m = vrc6(false);
+        assert_eq!(15, m.load_prg(0xe000));
+
+        m.store_prg(0x8000, 3);
+        assert_eq!(6, m.load_prg(0x8000));
+        assert_eq!(7, m.load_prg(0xa000));
+
+        m.store_prg(0xc000, 9);
+        assert_eq!(9, m.load_prg(0xdfff));
+    }
+
+    #[test]
+    fn test_vrc6_swapped_lines() {
+        let mut a = vrc6(false);
+        let mut b = vrc6(true);
+
+        // Mirroring lives in $B003 on both boards, whatever the wiring
+        a.store_prg(0xb003, 0b0100);
+        b.store_prg(0xb003, 0b0100);
+        assert_eq!(MirrorDirection::Horizontal, a.mirror());
+        assert_eq!(MirrorDirection::Horizontal, b.mirror());
+
+        // $D001 on VRC6b is CHR register 2
+        b.store_prg(0xd001, 0x5a);
+        assert_eq!(0x5a, b.chr_regs[2]);
+    }
+
+    #[test]
+    fn test_vrc6_battery_ram() {
+        assert!(vrc6(false).battery_ram().is_none());
+
+        let prg = vec![0; 0x8000];
+        let mut m = Vrc6::new(prg.clone(), vec![], false, true);
+        m.store_prg(0xb003, 0x80);
+        m.store_prg(0x6001, 0x42);
+        let saved = m.battery_ram().unwrap();
+        assert_eq!(0x42, saved[1]);
+
+        let mut other = Vrc6::new(prg, vec![], false, true);
+        other.restore_battery_ram(&saved);
+        other.store_prg(0xb003, 0x80);
+        assert_eq!(0x42, other.load_prg(0x6001));
+    }
+
+    #[test]
+    fn test_vrc6_saw() {
+        let mut m = vrc6(false);
+        m.store_prg(0xb000, 42);
+        m.store_prg(0xb001, 0);
+        m.store_prg(0xb002, 0x80);
+
+        let levels: Vec<u8> = (0..14)
+            .map(|_| {
+                m.tick(1);
+                m.audio.saw.output()
+            })
+            .collect();
+        assert_eq!(vec![0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0], levels);
+    }
+}
@@ -0,0 +1,243 @@
+use super::opll::Opll;
+use super::vrc_irq::VrcIrq;
+use super::*;
+
+/**
+ * Mapper 85: Konami VRC7.
+ *
+ * PRG:  three switchable 8kB banks and the last bank fixed at $E000,
+ *       $6000-$7FFF 8kB RAM, battery backed on Lagrange Point.
+ * CHR:  eight switchable 1kB banks.
+ * IRQ:  the VRC IRQ counter.
+ * Audio: six channel FM synthesis (see `Opll`).
+ *
+ * Registers are decoded from A15-A12 plus one more address line: A4 on
+ * VRC7a (Lagrange Point) and A3 on VRC7b (Tiny Toon Adventures 2). NES 2.0
+ * submappers 2 and 1 select them, otherwise both are accepted.
+ */
+pub struct Vrc7 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    ram: Vec<u8>,
+    has_battery: bool,
+    line: Addr,
+
+    prg_regs: [u8; 3],
+    chr_regs: [u8; 8],
+    /// $E000: RS....MM
+    control: u8,
+
+    irq: VrcIrq,
+    audio: Opll,
+}
+
+impl Vrc7 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, submapper: u8, has_battery: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(chr);
+        let line = match submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+
+        Self {
+            prg,
+            chr,
+            chr_is_ram,
+            ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            line,
+            prg_regs: [0; 3],
+            chr_regs: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            audio: Opll::new(),
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        (self.control & 0x80) != 0
+    }
+
+    fn audio_silenced(&self) -> bool {
+        (self.control & 0x40) != 0
+    }
+
+    fn prg_offset(&self, addr: Addr) -> usize {
+        let bank = match addr {
+            0x8000..=0x9fff => self.prg_regs[0] as usize,
+            0xa000..=0xbfff => self.prg_regs[1] as usize,
+            0xc000..=0xdfff => self.prg_regs[2] as usize,
+            _ => self.prg.len() / 0x2000 - 1,
+        };
+        bank_offset(&self.prg, 0x2000, bank, addr as usize)
+    }
+
+    fn chr_offset(&self, addr: Addr) -> usize {
+        let bank = self.chr_regs[(addr as usize >> 10) & 7];
+        bank_offset(&self.chr, 0x400, bank as usize, addr as usize)
+    }
+
+    fn write_register(&mut self, addr: Addr, v: u8) {
+        // The sound chip ports sit on A5 and A4 whatever the board
+        match addr & 0xf030 {
+            0x9010 => return self.audio.write_address(v),
+            0x9030 => return self.audio.write_data(v),
+            _ => (),
+        }
+
+        let high = (addr & self.line) != 0;
+        match (addr & 0xf000, high) {
+            (0x8000, false) => self.prg_regs[0] = v & 0x3f,
+            (0x8000, true) => self.prg_regs[1] = v & 0x3f,
+            (0x9000, false) => self.prg_regs[2] = v & 0x3f,
+            (0xa000..=0xd000, _) => {
+                let n = (((addr - 0xa000) >> 12) * 2) as usize + high as usize;
+                self.chr_regs[n] = v;
+            }
+            (0xe000, false) => {
+                self.control = v;
+                if self.audio_silenced() {
+                    self.audio.reset();
+                }
+            }
+            (0xe000, true) => self.irq.set_latch(v),
+            (0xf000, false) => self.irq.set_control(v),
+            (0xf000, true) => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn load_prg(&self, addr: Addr) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg[self.prg_offset(addr)],
+            _ => open_bus(addr),
+        }
+    }
+
+    fn store_prg(&mut self, addr: Addr, v: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.ram[(addr - 0x6000) as usize] = v,
+            0x8000..=0xffff => self.write_register(addr, v),
+            _ => (),
+        }
+    }
+
+    fn load_chr(&self, addr: Addr) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn store_chr(&mut self, addr: Addr, v: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = v;
+        }
+    }
+
+    fn mirror(&self) -> MirrorDirection {
+        match self.control & 0b11 {
+            0 => MirrorDirection::Vertical,
+            1 => MirrorDirection::Horizontal,
+            2 => MirrorDirection::SingleScreenLower,
+            _ => MirrorDirection::SingleScreenUpper,
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            if !self.audio_silenced() {
+                self.audio.clock();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        match self.audio_silenced() {
+            true => 0.0,
+            false => self.audio.output(),
+        }
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        match self.has_battery {
+            true => Some(self.ram.clone()),
+            false => None,
+        }
+    }
+
+    fn restore_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vrc7(submapper: u8) -> Vrc7 {
+        let prg = (0..64).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chr = (0..256).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Vrc7::new(prg, chr, submapper, false)
+    }
+
+    #[test]
+    fn test_vrc7_register_lines() {
+        let mut a = vrc7(2);
+        a.store_prg(0x8010, 5);
+        a.store_prg(0x8008, 9);
+        assert_eq!(5, a.load_prg(0xa000));
+        assert_eq!(9, a.load_prg(0x8000));
+
+        let mut b = vrc7(1);
+        b.store_prg(0x8008, 5);
+        assert_eq!(5, b.load_prg(0xa000));
+
+        let mut any = vrc7(0);
+        any.store_prg(0xd008, 0x33);
+        any.store_prg(0xd010, 0x44);
+        assert_eq!(0x44, any.load_chr(0x1c00));
+        assert_eq!(63, any.load_prg(0xe000));
+    }
+
+    #[test]
+    fn test_vrc7_audio_silence() {
+        let mut m = vrc7(2);
+        [(0x30, 0x40), (0x10, 0x22), (0x20, 0x19)]
+            .iter()
+            .for_each(|(a, d)| {
+                m.store_prg(0x9010, *a);
+                m.store_prg(0x9030, *d);
+            });
+        m.tick(36 * 1000);
+        assert!(m.audio_output() != 0.0);
+
+        m.store_prg(0xe000, 0x40);
+        assert_eq!(0.0, m.audio_output());
+    }
+
+    #[test]
+    fn test_vrc7_battery_ram() {
+        assert!(vrc7(2).battery_ram().is_none());
+
+        let prg = vec![0; 0x8000];
+        let mut m = Vrc7::new(prg.clone(), vec![], 2, true);
+        m.store_prg(0xe000, 0x80);
+        m.store_prg(0x6010, 0x42);
+        let saved = m.battery_ram().unwrap();
+
+        let mut other = Vrc7::new(prg, vec![], 2, true);
+        other.restore_battery_ram(&saved);
+        other.store_prg(0xe000, 0x80);
+        assert_eq!(0x42, other.load_prg(0x6010));
+    }
+}
@@ -0,0 +1,123 @@
+/**
+ * The IRQ counter shared by Konami's VRC4, VRC6 and VRC7.
+ *
+ * An 8 bit counter counts up towards $FF and reloads from the latch when it
+ * overflows, raising an IRQ. In cycle mode it is clocked by every CPU cycle.
+ * In scanline mode a prescaler divides the CPU clock by 113.667 (341 / 3), so
+ * the counter advances once per scanline without watching the PPU.
+ */
+#[derive(Default)]
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    const PRESCALER_RELOAD: i16 = 341;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_latch(&mut self, v: u8) {
+        self.latch = v;
+    }
+
+    /// The VRC4 writes its latch a nibble at a time
+    pub fn set_latch_low(&mut self, v: u8) {
+        self.latch = (self.latch & 0xf0) | (v & 0x0f);
+    }
+
+    pub fn set_latch_high(&mut self, v: u8) {
+        self.latch = (self.latch & 0x0f) | ((v & 0x0f) << 4);
+    }
+
+    /// Writes the control register: bit 0 enable-after-ack, bit 1 enable, bit 2 cycle mode
+    pub fn set_control(&mut self, v: u8) {
+        self.enable_after_ack = (v & 0b001) != 0;
+        self.enabled = (v & 0b010) != 0;
+        self.cycle_mode = (v & 0b100) != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = Self::PRESCALER_RELOAD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Clocked once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += Self::PRESCALER_RELOAD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vrc_irq_cycle_mode() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch(0xfd);
+        irq.set_control(0b110);
+
+        // $fd -> $fe -> $ff -> reload
+        (0..2).for_each(|_| irq.clock());
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        irq.acknowledge();
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(!irq.pending());
+    }
+
+    #[test]
+    fn test_vrc_irq_scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch(0xff);
+        irq.set_control(0b011);
+
+        // One scanline takes 113 or 114 CPU cycles
+        (0..113).for_each(|_| irq.clock());
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+    }
+}
@@ -1,4 +1,8 @@
 use crate::error::*;
+use crate::nes::apu::Apu;
+use crate::nes::mapper::{Mapper, Nrom, VRAM_SIZE};
+use crate::nes::ppu::{self, Ppu};
+use crate::nes::timing::Timing;
 
 use log::*;
 use std::fmt;
@@ -35,21 +39,30 @@ const MEM_PPU_SIZE: usize = 0x8;
 const MEM_REG_BEGIN: Addr = 0x4000;
 const MEM_REG_END: Addr = 0x401f;
 const MEM_REG_SIZE: usize = 0x20;
+const MEM_OAM_DMA: usize = 0x14;
+const MEM_APU_STATUS: usize = 0x15;
 
-const MEM_BATT_ROM_BEGIN: Addr = 0x6000;
-const MEM_BATT_ROM_END: Addr = 0x7fff;
-const MEM_BATT_ROM_SIZE: usize = 0x2000;
+/// CPU cycles a sprite DMA takes, plus one more when it starts on an odd cycle
+const OAM_DMA_CYCLES: usize = 513;
+
+const MEM_CART_BEGIN: Addr = 0x4020;
+const MEM_CART_END: Addr = 0xffff;
 
-const MEM_PROG_ROM_BEGIN: Addr = 0x8000;
-const MEM_PROG_ROM_END: Addr = 0xffff;
 const MEM_PROG_ROM_SIZE: usize = 0x8000;
 
 pub struct Memory {
     ram: [u8; MEM_RAM_SIZE],
-    ppu_reg: [u8; MEM_PPU_SIZE],
     other_reg: [u8; MEM_REG_SIZE],
-    rom_batt: [u8; MEM_BATT_ROM_SIZE],
-    rom_prog: [u8; MEM_PROG_ROM_SIZE],
+    vram: [u8; VRAM_SIZE],
+    ppu: Ppu,
+    apu: Apu,
+    cartridge: Box<dyn Mapper>,
+    /// CPU cycles owed to a sprite DMA
+    stall: usize,
+    /// Master clocks the CPU has run ahead of the PPU
+    master_clocks: usize,
+    /// CPU cycles of the current instruction already ticked by `catch_up`
+    caught_up: usize,
 }
 
 // Convenience class to handle the really weird memory access patterns
@@ -57,9 +70,7 @@ enum MemoryAccess {
     RAM(usize),
     REG(usize),
     PPU(usize),
-    ROMB(usize),
-    ROMP(usize),
-    ILLEGAL,
+    Cart(Addr),
 }
 
 impl fmt::Display for MemoryAccess {
@@ -68,9 +79,7 @@ impl fmt::Display for MemoryAccess {
             MemoryAccess::RAM(addr) => write!(f, "RAM {:04x}", addr),
             MemoryAccess::PPU(addr) => write!(f, "PPU {:04x}", addr),
             MemoryAccess::REG(addr) => write!(f, "REG {:04x}", addr),
-            MemoryAccess::ROMB(addr) => write!(f, "ROMB {:04x}", addr),
-            MemoryAccess::ROMP(addr) => write!(f, "ROMP {:04x}", addr),
-            MemoryAccess::ILLEGAL => write!(f, "ILLEGAL"),
+            MemoryAccess::Cart(addr) => write!(f, "CART {:04x}", addr),
         }
     }
 }
@@ -79,24 +88,32 @@ impl Memory {
     pub fn new() -> Self {
         Self {
             ram: [0; MEM_RAM_SIZE],
-            ppu_reg: [0; MEM_PPU_SIZE],
             other_reg: [0; MEM_REG_SIZE],
-            rom_batt: [0; MEM_BATT_ROM_SIZE],
-            rom_prog: [0; MEM_PROG_ROM_SIZE],
+            vram: [0; VRAM_SIZE],
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            cartridge: Box::new(Nrom::new(
+                vec![0; MEM_PROG_ROM_SIZE],
+                Vec::new(),
+                Default::default(),
+            )),
+            stall: 0,
+            master_clocks: 0,
+            caught_up: 0,
         }
     }
 
-    pub fn load_rom(&mut self, prog_rom: &Vec<u8>) -> IronNesResult<()> {
+    /// Plugs in a mapper-less cartridge holding only PRG ROM
+    pub fn load_rom(&mut self, prog_rom: &[u8]) -> IronNesResult<()> {
         const ONE_PAGE: usize = MEM_PROG_ROM_SIZE / 2;
         match prog_rom.len() {
-            ONE_PAGE => {
-                self.rom_prog[0..ONE_PAGE].clone_from_slice(&prog_rom);
-                // Gets mirrored on lower bytes
-                self.rom_prog[ONE_PAGE..].clone_from_slice(&prog_rom);
-            }
-            MEM_PROG_ROM_SIZE => {
-                self.rom_prog
-                    .clone_from_slice(&prog_rom[0..MEM_PROG_ROM_SIZE]);
+            // A single page gets mirrored on the upper bytes
+            ONE_PAGE | MEM_PROG_ROM_SIZE => {
+                self.load_mapper(Box::new(Nrom::new(
+                    prog_rom.to_vec(),
+                    Vec::new(),
+                    Default::default(),
+                )));
             }
             _ => {
                 error!("CARTRIDGE DOESN'T FIT");
@@ -107,6 +124,90 @@ impl Memory {
         Ok(())
     }
 
+    pub fn load_mapper(&mut self, mapper: Box<dyn Mapper>) {
+        self.cartridge = mapper;
+    }
+
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.cartridge.as_ref()
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.cartridge.as_mut()
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// Clocks the PPU and APU the way the region's console does
+    pub fn set_timing(&mut self, timing: Timing) {
+        info!("{:?} timing", timing.region);
+        self.ppu.set_timing(timing);
+        self.apu.set_region(timing.region);
+        self.master_clocks = 0;
+    }
+
+    /**
+     * Advances the PPU, APU and cartridge hardware by a number of CPU cycles.
+     * The PPU gets as many dots as fit in the master clocks those took, which
+     * on PAL isn't a whole number a cycle.
+     */
+    pub fn tick(&mut self, cycles: usize) {
+        let timing = *self.ppu.timing();
+        self.master_clocks += cycles * timing.cpu_divider;
+        let dots = self.master_clocks / timing.ppu_divider;
+        self.master_clocks %= timing.ppu_divider;
+
+        self.ppu.tick(dots, self.cartridge.as_mut(), &self.vram);
+        self.apu.tick(cycles, self.cartridge.as_ref());
+        self.cartridge.tick(cycles);
+    }
+
+    /**
+     * Ticks ahead to a cycle partway through the instruction being run, so a
+     * PPU register access sees the PPU as it is on the cycle the access lands
+     * on and not as it was when the instruction began.
+     */
+    pub fn catch_up(&mut self, cycles: usize) {
+        if cycles > self.caught_up {
+            self.tick(cycles - self.caught_up);
+            self.caught_up = cycles;
+        }
+    }
+
+    /// Takes the cycles of the last instruction already ticked by `catch_up`
+    pub fn take_caught_up(&mut self) -> usize {
+        std::mem::take(&mut self.caught_up)
+    }
+
+    /// Takes the cycles the CPU was halted for by a sprite DMA
+    pub fn take_stall(&mut self) -> usize {
+        std::mem::take(&mut self.stall)
+    }
+
+    /// Takes the PPU's vblank NMI, if it has raised one
+    pub fn nmi(&mut self) -> bool {
+        self.ppu.take_nmi()
+    }
+
+    pub fn irq(&self) -> bool {
+        self.apu.irq() || self.cartridge.irq()
+    }
+
+    /// The 2A03's channels mixed with any cartridge expansion audio
+    pub fn audio_output(&self) -> f32 {
+        self.apu.output() + self.cartridge.audio_output()
+    }
+
+    pub fn is_ppu_register(addr: Addr) -> bool {
+        (MEM_PPU_BEGIN..=MEM_PPU_END).contains(&addr)
+    }
+
     // Since the NES has really messy memory access patterns
     fn translate_addr(addr: Addr) -> MemoryAccess {
         let a = match addr {
@@ -117,13 +218,7 @@ impl Memory {
                 MemoryAccess::PPU(((addr - MEM_PPU_BEGIN) % (MEM_PPU_SIZE as Addr)) as usize)
             }
             MEM_REG_BEGIN..=MEM_REG_END => MemoryAccess::REG((addr - MEM_REG_BEGIN) as usize),
-            MEM_BATT_ROM_BEGIN..=MEM_BATT_ROM_END => {
-                MemoryAccess::ROMB((addr - MEM_BATT_ROM_BEGIN) as usize)
-            }
-            MEM_PROG_ROM_BEGIN..=MEM_PROG_ROM_END => {
-                MemoryAccess::ROMP((addr - MEM_PROG_ROM_BEGIN) as usize)
-            }
-            _ => MemoryAccess::ILLEGAL,
+            MEM_CART_BEGIN..=MEM_CART_END => MemoryAccess::Cart(addr),
         };
         trace!("Access {:04x} -> {}", addr, a);
         a
@@ -131,16 +226,12 @@ impl Memory {
 
     pub fn load(&self, addr: Addr) -> IronNesResult<u8> {
         let v = match Self::translate_addr(addr) {
-            MemoryAccess::RAM(addr) => Ok(self.ram[addr]),
-            MemoryAccess::PPU(addr) => Ok(self.ppu_reg[addr]),
-            MemoryAccess::REG(addr) => Ok(self.other_reg[addr]),
-            MemoryAccess::ROMB(addr) => Ok(self.rom_batt[addr]),
-            MemoryAccess::ROMP(addr) => Ok(self.rom_prog[addr]),
-            MemoryAccess::ILLEGAL => Err(IronNesError::MemoryError(format!(
-                "Illegal load ${:04x}",
-                addr
-            ))),
-        }?;
+            MemoryAccess::RAM(addr) => self.ram[addr],
+            MemoryAccess::PPU(reg) => self.ppu.load(reg, self.cartridge.as_ref(), &self.vram),
+            MemoryAccess::REG(MEM_APU_STATUS) => self.apu.status(),
+            MemoryAccess::REG(addr) => self.other_reg[addr],
+            MemoryAccess::Cart(addr) => self.cartridge.load_prg(addr),
+        };
         trace!("mem: [{:04x}] => {:02x}", addr, v);
         Ok(v)
     }
@@ -149,17 +240,43 @@ impl Memory {
         trace!("mem: {:02x} => store[{:04x}]", v, addr);
         match Self::translate_addr(addr) {
             MemoryAccess::RAM(addr) => Ok(self.ram[addr] = v),
-            MemoryAccess::PPU(addr) => Ok(self.ppu_reg[addr] = v),
-            MemoryAccess::REG(addr) => Ok(self.other_reg[addr] = v),
-            MemoryAccess::ROMB(addr) => Ok(self.rom_batt[addr] = v),
-            MemoryAccess::ROMP(addr) => Ok(self.rom_prog[addr] = v),
-            MemoryAccess::ILLEGAL => Err(IronNesError::MemoryError(format!(
-                "Illegal store ${:04x}",
-                addr
-            ))),
+            MemoryAccess::PPU(reg) => {
+                let cartridge = self.cartridge.as_mut();
+                Ok(self.ppu.store(reg, v, cartridge, &mut self.vram))
+            }
+            MemoryAccess::REG(MEM_OAM_DMA) => {
+                self.other_reg[MEM_OAM_DMA] = v;
+                self.oam_dma(v)
+            }
+            MemoryAccess::REG(reg) => {
+                self.apu.store(addr, v);
+                Ok(self.other_reg[reg] = v)
+            }
+            MemoryAccess::Cart(addr) => Ok(self.cartridge.store_prg(addr, v)),
         }
     }
 
+    /// Copies a page of CPU memory into OAM, halting the CPU while it does
+    fn oam_dma(&mut self, page: u8) -> IronNesResult<()> {
+        let begin = (page as Addr) << 8;
+        for i in 0..=0xff {
+            let v = self.load(begin | i)?;
+            self.ppu.store_oam(v);
+        }
+        self.stall = OAM_DMA_CYCLES;
+        Ok(())
+    }
+
+    /// PPU bus read, $3000-$3EFF mirrors the nametables at $2000-$2EFF
+    pub fn load_ppu(&self, addr: Addr) -> u8 {
+        self.cartridge.load_ppu(ppu::bus_addr(addr), &self.vram)
+    }
+
+    pub fn store_ppu(&mut self, addr: Addr, v: u8) {
+        let addr = ppu::bus_addr(addr);
+        self.cartridge.store_ppu(addr, v, &mut self.vram)
+    }
+
     fn get_high_addr(addr: Addr) -> Addr {
         match addr {
             0..=MEM_RAM_END if ((addr & 0xff) == 0xff) => addr & 0xff00,
@@ -242,6 +359,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_ppu_nametable_mirror() {
+        let mut mem = Memory::new();
+
+        // Horizontal mirroring by default: $2000 and $2400 are the same table
+        mem.store_ppu(0x2001, 0x12);
+        assert_eq!(0x12, mem.load_ppu(0x2401));
+        assert_eq!(0x12, mem.load_ppu(0x3001));
+        assert_eq!(0x00, mem.load_ppu(0x2801));
+    }
+
     #[test]
     #[should_panic(expected = "Stack Overflow")]
     fn test_stack_overflow() {
@@ -0,0 +1,383 @@
+use crate::error::*;
+use crate::nes::cartridge::CartridgeRegion;
+use crate::nes::memory::Addr;
+use crate::nes::timing::Timing;
+
+use log::*;
+use std::convert::TryFrom;
+
+/**
+ * NES Sound Format (.nsf file)
+ *
+ * Byte    | Contents
+ * --------|------------------------------------------------------------------
+ * 0-4     | String "NESM^Z".
+ * 5       | Version.
+ * 6       | Number of songs.
+ * 7       | First song to play, counting from 1.
+ * 8-13    | Load, init and play addresses, little endian.
+ * 14-109  | Title, artist and copyright, 32 byte NUL padded strings.
+ * 110-111 | NTSC play speed, in microseconds.
+ * 112-119 | Initial 4kB banks for $8000-$FFFF, all zeroes without bankswitching.
+ * 120-121 | PAL play speed, in microseconds.
+ * 122     | Region: bit 0 PAL, bit 1 both.
+ * 123     | Expansion chips, see the `CHIP_` constants.
+ * 124-127 | NSF2 flags and program length, unused.
+ * 128-... | Program data.
+ *
+ * NSFe (.nsfe file) has the same information in chunks, after "NSFE": each is
+ * a 4 byte little endian length, a 4 byte ID, then the data.
+ *
+ * INFO    | Load, init and play addresses, region, chips, songs, first song.
+ * DATA    | Program data.
+ * BANK    | Initial banks.
+ * RATE    | NTSC and PAL play speeds.
+ * auth    | Title, artist, copyright and ripper, NUL terminated.
+ * tlbl    | Track names, NUL terminated.
+ * time    | Track lengths in milliseconds, 4 bytes each, negative if unknown.
+ * NEND    | End of the file.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: u8,
+    /// First song to play, counting from 0
+    pub start_song: u8,
+
+    pub load_addr: Addr,
+    pub init_addr: Addr,
+    pub play_addr: Addr,
+    /// Microseconds between calls to the play routine
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub pal: bool,
+    /// Initial banks, when the tune is bankswitched
+    pub banks: Option<[u8; 8]>,
+    pub chips: u8,
+    pub data: Vec<u8>,
+
+    /// Names and lengths in milliseconds of each track, NSFe only
+    pub track_names: Vec<String>,
+    pub track_times: Vec<Option<u32>>,
+}
+
+impl Nsf {
+    pub const HEADER: [u8; 5] = [0x4e, 0x45, 0x53, 0x4d, 0x1a];
+    pub const NSFE_HEADER: [u8; 4] = [0x4e, 0x53, 0x46, 0x45];
+    const HEADER_SIZE: usize = 0x80;
+
+    pub const CHIP_VRC6: u8 = 0x01;
+    pub const CHIP_VRC7: u8 = 0x02;
+    pub const CHIP_FDS: u8 = 0x04;
+    pub const CHIP_MMC5: u8 = 0x08;
+    pub const CHIP_N163: u8 = 0x10;
+    pub const CHIP_5B: u8 = 0x20;
+
+    const NTSC_SPEED: u16 = 16639;
+    const PAL_SPEED: u16 = 19997;
+
+    /// Whether the file is an NSF or NSFe
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(&Self::HEADER) || data.starts_with(&Self::NSFE_HEADER)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> IronNesResult<Self> {
+        let nsf = match data.starts_with(&Self::NSFE_HEADER) {
+            true => Self::from_nsfe(data)?,
+            false => Self::from_nsf(data)?,
+        };
+
+        if nsf.data.is_empty() || nsf.songs == 0 {
+            error!("NSF has no program data or no songs");
+            return Err(IronNesError::CartridgeError);
+        }
+        if nsf.chips & (Self::CHIP_MMC5 | Self::CHIP_5B) != 0 {
+            warn!("NSF uses MMC5 or Sunsoft 5B audio, which isn't emulated");
+        }
+        Ok(nsf)
+    }
+
+    fn from_nsf(data: &[u8]) -> IronNesResult<Self> {
+        if data.len() <= Self::HEADER_SIZE || !data.starts_with(&Self::HEADER) {
+            error!("NSF has an invalid header");
+            return Err(IronNesError::CartridgeError);
+        }
+
+        let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&data[0x70..0x78]);
+
+        Ok(Self {
+            title: string(&data[0x0e..0x2e]),
+            artist: string(&data[0x2e..0x4e]),
+            copyright: string(&data[0x4e..0x6e]),
+            songs: data[6],
+            start_song: data[7].saturating_sub(1),
+            load_addr: word(8),
+            init_addr: word(0x0a),
+            play_addr: word(0x0c),
+            ntsc_speed: word(0x6e),
+            pal_speed: word(0x78),
+            pal: (data[0x7a] & 0b11) == 1,
+            banks: Some(banks).filter(|b| b.iter().any(|&b| b != 0)),
+            chips: data[0x7b],
+            data: data[Self::HEADER_SIZE..].to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn from_nsfe(data: &[u8]) -> IronNesResult<Self> {
+        let mut nsf = Self {
+            ntsc_speed: Self::NTSC_SPEED,
+            pal_speed: Self::PAL_SPEED,
+            songs: 1,
+            ..Default::default()
+        };
+        let mut has_info = false;
+
+        let mut pos = Self::NSFE_HEADER.len();
+        while pos + 8 <= data.len() {
+            let len = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+                as usize;
+            let id = &data[pos + 4..pos + 8];
+            pos += 8;
+
+            let chunk = match data.get(pos..pos + len) {
+                Some(chunk) => chunk,
+                None => {
+                    error!("NSFe chunk {} runs past the end of the file", show_id(id));
+                    return Err(IronNesError::CartridgeError);
+                }
+            };
+            pos += len;
+
+            let word = |i: usize| {
+                let b = |i: usize| chunk.get(i).copied().unwrap_or(0);
+                u16::from_le_bytes([b(i), b(i + 1)])
+            };
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        error!("NSFe INFO chunk is too short");
+                        return Err(IronNesError::CartridgeError);
+                    }
+                    has_info = true;
+                    nsf.load_addr = word(0);
+                    nsf.init_addr = word(2);
+                    nsf.play_addr = word(4);
+                    nsf.pal = (chunk[6] & 0b11) == 1;
+                    nsf.chips = chunk[7];
+                    nsf.songs = chunk.get(8).copied().unwrap_or(1);
+                    nsf.start_song = chunk.get(9).copied().unwrap_or(0);
+                }
+                b"DATA" => nsf.data = chunk.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    let len = chunk.len().min(8);
+                    banks[..len].copy_from_slice(&chunk[..len]);
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" => {
+                    nsf.ntsc_speed = word(0);
+                    if chunk.len() >= 4 {
+                        nsf.pal_speed = word(2);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = chunk.split(|&b| b == 0).map(string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_names = chunk.split(|&b| b == 0).map(string).collect();
+                    nsf.track_names.pop();
+                }
+                b"time" => {
+                    nsf.track_times = chunk
+                        .chunks_exact(4)
+                        .map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]]))
+                        .map(|t| u32::try_from(t).ok())
+                        .collect();
+                }
+                b"NEND" => break,
+                // Upper case chunks are ones a player has to understand
+                [c, ..] if c.is_ascii_uppercase() => {
+                    error!("NSFe has an unsupported chunk {}", show_id(id));
+                    return Err(IronNesError::CartridgeError);
+                }
+                _ => trace!("Skipping NSFe chunk {}", show_id(id)),
+            }
+        }
+
+        if !has_info {
+            error!("NSFe has no INFO chunk");
+            return Err(IronNesError::CartridgeError);
+        }
+        Ok(nsf)
+    }
+
+    /// Microseconds between play calls in the tune's region
+    pub fn speed(&self) -> u16 {
+        self.speed_for(self.pal)
+    }
+
+    /// Microseconds between play calls when played on a PAL or NTSC console
+    pub fn speed_for(&self, pal: bool) -> u16 {
+        match (pal, self.pal_speed, self.ntsc_speed) {
+            (true, speed, _) if speed > 0 => speed,
+            (false, _, speed) if speed > 0 => speed,
+            (true, _, _) => Self::PAL_SPEED,
+            (false, _, _) => Self::NTSC_SPEED,
+        }
+    }
+
+    /// Name of a track counting from 0, if the file has one
+    pub fn track_name(&self, song: u8) -> Option<&str> {
+        self.track_names
+            .get(song as usize)
+            .map(|n| n.as_str())
+            .filter(|n| !n.is_empty())
+    }
+
+    /// Length of a track counting from 0, in milliseconds
+    pub fn track_time(&self, song: u8) -> Option<u32> {
+        self.track_times.get(song as usize).copied().flatten()
+    }
+}
+
+/**
+ * Where an NSF is between calls into its code. The init and play routines are
+ * called like subroutines returning to `RETURN_ADDR`, and the CPU idles there
+ * until the next play call is due.
+ */
+pub(super) struct NsfPlayback {
+    pub nsf: Nsf,
+    pub song: u8,
+    /// Whether the console playing it is a 50Hz one, which init is told in X
+    pub pal: bool,
+    /// CPU cycles between play calls, and when the next one is due
+    pub period: f64,
+    pub next_play: f64,
+}
+
+impl NsfPlayback {
+    /// Open bus on the player hardware, which no tune runs code from
+    pub const RETURN_ADDR: Addr = 0x4100;
+    /// How far the CPU moves on in one step while idling
+    pub const IDLE_CYCLES: usize = 4;
+
+    /// Plays the tune at the speed and pitch of the console `timing` is for
+    pub fn new(nsf: Nsf, timing: &Timing) -> Self {
+        let pal = timing.region != CartridgeRegion::NTSC;
+        let period = nsf.speed_for(pal) as f64 * timing.cpu_frequency() / 1_000_000.0;
+        Self {
+            song: nsf.start_song,
+            nsf,
+            pal,
+            period,
+            next_play: 0.0,
+        }
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn show_id(id: &[u8]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u32).to_le_bytes(), id, data].concat()
+    }
+
+    #[test]
+    fn test_nsf_header() {
+        let mut file = Nsf::HEADER.to_vec();
+        file.extend([1, 5, 2]);
+        file.extend([0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        file.extend(b"Song\0");
+        file.resize(0x6e, 0);
+        file.extend(16639u16.to_le_bytes());
+        file.extend([0, 1, 2, 3, 4, 5, 6, 7]);
+        file.extend(19997u16.to_le_bytes());
+        file.extend([0, Nsf::CHIP_VRC6, 0, 0, 0, 0]);
+        file.extend([0x60; 0x10]);
+
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        assert_eq!("Song", nsf.title);
+        assert_eq!((5, 1), (nsf.songs, nsf.start_song));
+        assert_eq!(
+            [0x8000, 0x8003, 0x8006],
+            [nsf.load_addr, nsf.init_addr, nsf.play_addr]
+        );
+        assert_eq!(Some([0, 1, 2, 3, 4, 5, 6, 7]), nsf.banks);
+        assert_eq!(16639, nsf.speed());
+        assert_eq!(Nsf::CHIP_VRC6, nsf.chips);
+        assert_eq!(0x10, nsf.data.len());
+
+        // Played on a PAL console it's called at the PAL rate, in PAL's
+        // slower CPU cycles
+        let ntsc = NsfPlayback::new(nsf.clone(), &Timing::NTSC);
+        assert!(!ntsc.pal);
+        assert!((ntsc.period - 29780.0).abs() < 1.0);
+        let pal = NsfPlayback::new(nsf, &Timing::PAL);
+        assert!(pal.pal);
+        assert!((pal.period - 33247.2).abs() < 1.0);
+
+        assert!(Nsf::from_bytes(&file[..0x80]).is_err());
+    }
+
+    #[test]
+    fn test_nsfe_chunks() {
+        let mut info = [0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 1, 0].to_vec();
+        info.extend([3, 1]);
+        let mut times = 90_000i32.to_le_bytes().to_vec();
+        times.extend((-1i32).to_le_bytes());
+
+        let file = [
+            Nsf::NSFE_HEADER.to_vec(),
+            chunk(b"INFO", &info),
+            chunk(b"DATA", &[0x60; 0x10]),
+            chunk(b"RATE", &[0x10, 0x27]),
+            chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"),
+            chunk(b"tlbl", b"Intro\0\0Ending\0"),
+            chunk(b"time", &times),
+            chunk(b"text", b"Notes"),
+            chunk(b"NEND", &[]),
+        ]
+        .concat();
+
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        assert!(nsf.pal);
+        assert_eq!((3, 1), (nsf.songs, nsf.start_song));
+        assert_eq!(10000, nsf.ntsc_speed);
+        assert_eq!(Nsf::PAL_SPEED, nsf.speed());
+        assert_eq!(("Title", "Artist"), (&nsf.title[..], &nsf.artist[..]));
+        assert_eq!(
+            [Some("Intro"), None, Some("Ending")],
+            [0, 1, 2].map(|t| nsf.track_name(t))
+        );
+        assert_eq!([Some(90_000), None], [0, 1].map(|t| nsf.track_time(t)));
+        assert_eq!(None, nsf.banks);
+
+        // Chunks a player must understand can't be skipped
+        let file = [
+            Nsf::NSFE_HEADER.to_vec(),
+            chunk(b"INFO", &info),
+            chunk(b"DATA", &[0x60; 0x10]),
+            chunk(b"ZZZZ", &[]),
+        ]
+        .concat();
+        assert!(Nsf::from_bytes(&file).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use crate::error::*;
+use crate::hash::crc32;
+
+use log::*;
+use std::convert::TryFrom;
+
+/**
+ * Soft-patching: IPS, UPS and BPS patches applied to a ROM image in memory.
+ *
+ * IPS     | "PATCH", then records of a 3 byte offset and a 2 byte size, both
+ *         | big endian, followed by that many bytes. A size of 0 is a run: a 2
+ *         | byte count and the byte to repeat. "EOF" ends the records, and can
+ *         | be followed by a 3 byte size to truncate the ROM to.
+ * UPS     | "UPS1", the source and target sizes, then hunks: bytes to skip and
+ *         | bytes to XOR with the source, up to a 0. Ends with the CRC32s of
+ *         | the source, the target and the patch.
+ * BPS     | "BPS1", the source, target and metadata sizes, the metadata, then
+ *         | actions copying from the source, the patch or the target written
+ *         | so far. Ends with CRC32s like UPS.
+ *
+ * UPS and BPS numbers are variable length, 7 bits a byte with the top bit
+ * marking the last byte.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+/// No ROM is anywhere near this big, so a patch asking for more is broken
+const MAX_TARGET_SIZE: usize = 0x400_0000;
+
+impl PatchFormat {
+    pub const EXTENSIONS: [&'static str; 3] = ["ips", "ups", "bps"];
+
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        match patch {
+            [b'P', b'A', b'T', b'C', b'H', ..] => Some(Self::Ips),
+            [b'U', b'P', b'S', b'1', ..] => Some(Self::Ups),
+            [b'B', b'P', b'S', b'1', ..] => Some(Self::Bps),
+            _ => None,
+        }
+    }
+}
+
+/// Applies a patch of any supported format to a ROM image
+pub fn apply(patch: &[u8], rom: &[u8]) -> IronNesResult<Vec<u8>> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(patch, rom),
+        Some(PatchFormat::Ups) => apply_ups(patch, rom),
+        Some(PatchFormat::Bps) => apply_bps(patch, rom),
+        None => Err(patch_error("not an IPS, UPS or BPS patch")),
+    }
+}
+
+fn patch_error(msg: &str) -> IronNesError {
+    error!("Patch failed: {}", msg);
+    IronNesError::PatchError(msg.to_string())
+}
+
+/// Reads through a patch, failing instead of panicking when it runs short
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> IronNesResult<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| patch_error("patch is truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> IronNesResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, len: usize) -> IronNesResult<usize> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |v, &b| (v << 8) | b as usize))
+    }
+
+    fn number(&mut self) -> IronNesResult<usize> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let b = self.byte()?;
+            value = value
+                .checked_add((b & 0x7f) as usize * shift)
+                .ok_or_else(|| patch_error("number is too large"))?;
+            if (b & 0x80) != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_shl(7)
+                .ok_or_else(|| patch_error("number is too large"))?;
+            value += shift;
+        }
+    }
+}
+
+fn apply_ips(patch: &[u8], rom: &[u8]) -> IronNesResult<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let mut reader = PatchReader::new(patch, 5);
+
+    loop {
+        if reader.bytes(3)? == b"EOF" {
+            break;
+        }
+        reader.pos -= 3;
+
+        let offset = reader.be(3)?;
+        let (data, len) = match reader.be(2)? {
+            0 => {
+                let len = reader.be(2)?;
+                (None, len)
+            }
+            len => (Some(reader.bytes(len)?), len),
+        };
+
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => out[offset..offset + len].copy_from_slice(data),
+            None => {
+                let v = reader.byte()?;
+                out[offset..offset + len].fill(v);
+            }
+        }
+    }
+
+    if let Ok(size) = reader.be(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+/// Checks the CRC32s at the end of a UPS or BPS patch and returns the
+/// expected source and target CRCs
+fn check_footer(patch: &[u8]) -> IronNesResult<(u32, u32)> {
+    if patch.len() < 16 {
+        return Err(patch_error("patch is truncated"));
+    }
+
+    let footer = &patch[patch.len() - 12..];
+    let crc =
+        |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    if crc32(&patch[..patch.len() - 4]) != crc(8) {
+        return Err(patch_error("patch is corrupt, its checksum doesn't match"));
+    }
+    Ok((crc(0), crc(4)))
+}
+
+fn target_size(reader: &mut PatchReader) -> IronNesResult<usize> {
+    match reader.number()? {
+        size if size > MAX_TARGET_SIZE => Err(patch_error("patched ROM would be too large")),
+        size => Ok(size),
+    }
+}
+
+fn check_source(rom: &[u8], expected: u32) -> IronNesResult<()> {
+    match crc32(rom) == expected {
+        true => Ok(()),
+        false => Err(patch_error("patch is for a different ROM")),
+    }
+}
+
+fn check_target(out: &[u8], expected: u32) -> IronNesResult<()> {
+    match crc32(out) == expected {
+        true => Ok(()),
+        false => Err(patch_error(
+            "patched ROM doesn't match the patch's checksum",
+        )),
+    }
+}
+
+fn apply_ups(patch: &[u8], rom: &[u8]) -> IronNesResult<Vec<u8>> {
+    let (source_crc, target_crc) = check_footer(patch)?;
+    check_source(rom, source_crc)?;
+
+    let end = patch.len() - 12;
+    let mut reader = PatchReader::new(&patch[..end], 4);
+    let _source_size = reader.number()?;
+    let target_size = target_size(&mut reader)?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+
+    let mut pos = 0;
+    while reader.pos < end {
+        pos += reader.number()?;
+        loop {
+            let x = reader.byte()?;
+            if x == 0 {
+                pos += 1;
+                break;
+            }
+            if let Some(b) = out.get_mut(pos) {
+                *b ^= x;
+            }
+            pos += 1;
+        }
+    }
+
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+fn apply_bps(patch: &[u8], rom: &[u8]) -> IronNesResult<Vec<u8>> {
+    let (source_crc, target_crc) = check_footer(patch)?;
+    check_source(rom, source_crc)?;
+
+    let end = patch.len() - 12;
+    let mut reader = PatchReader::new(&patch[..end], 4);
+    let _source_size = reader.number()?;
+    let target_size = target_size(&mut reader)?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    let out_of_range = || patch_error("patch copies from outside the ROM");
+
+    while reader.pos < end {
+        let data = reader.number()?;
+        let len = (data >> 2) + 1;
+        // Lengths aren't limited by the target size, so a copy could go on
+        // for as long as memory lasts
+        if out.len() + len > target_size {
+            return Err(patch_error("patch writes past the end of the patched ROM"));
+        }
+
+        match data & 0b11 {
+            // Source read, from the same place in the ROM
+            0 => {
+                let pos = out.len();
+                let bytes = rom.get(pos..pos + len).ok_or_else(out_of_range)?;
+                out.extend_from_slice(bytes);
+            }
+            // Target read, from the patch
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // Source and target copies move a cursor by a signed amount first
+            command => {
+                let d = reader.number()?;
+                let delta = (d >> 1) as isize * if (d & 1) != 0 { -1 } else { 1 };
+                if command == 2 {
+                    source_offset += delta;
+                    let start = usize::try_from(source_offset).map_err(|_| out_of_range())?;
+                    let bytes = rom.get(start..start + len).ok_or_else(out_of_range)?;
+                    out.extend_from_slice(bytes);
+                    source_offset += len as isize;
+                } else {
+                    target_offset += delta;
+                    // Byte by byte, as the copy can overlap what it writes
+                    for _ in 0..len {
+                        let start = usize::try_from(target_offset).map_err(|_| out_of_range())?;
+                        let b = *out.get(start).ok_or_else(out_of_range)?;
+                        out.push(b);
+                        target_offset += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(patch_error("patched ROM is the wrong size"));
+    }
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut v: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let x = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                out.push(0x80 | x);
+                return out;
+            }
+            out.push(x);
+            v -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_number() {
+        for v in [0, 1, 127, 128, 300, 0x12345] {
+            let bytes = number(v);
+            assert_eq!(v, PatchReader::new(&bytes, 0).number().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_ips() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0, 0, 1, 0, 2, 0xaa, 0xbb]);
+        // A run of four past the end grows the ROM
+        patch.extend([0, 0, 7, 0, 0, 0, 4, 0xcc]);
+        patch.extend(b"EOF");
+
+        let out = apply(&patch, &rom).unwrap();
+        assert_eq!(vec![0, 0xaa, 0xbb, 0, 0, 0, 0, 0xcc, 0xcc, 0xcc, 0xcc], out);
+
+        // Truncation after EOF
+        patch.extend([0, 0, 4]);
+        assert_eq!(vec![0, 0xaa, 0xbb, 0], apply(&patch, &rom).unwrap());
+
+        assert!(apply(b"PATCH\x00\x00", &rom).is_err());
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = b"Hello World".to_vec();
+        let target = b"Hello Wirld!".to_vec();
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        // Skip 7, XOR 'o' with 'i', then skip 2 and write '!' past the end
+        patch.extend(number(7));
+        patch.extend([b'o' ^ b'i', 0]);
+        patch.extend(number(2));
+        patch.extend([b'!', 0]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(target, apply(&patch, &source).unwrap());
+        assert!(apply(&patch, b"Hello There").is_err());
+
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 1;
+        assert!(apply(&corrupt, &source).is_err());
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"abcdef".to_vec();
+        let target = b"abcXYXYXdef".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        // Source read 3, target read "XY", target copy 3 from offset 3, then
+        // source copy 3 from offset 3
+        patch.extend(number(2 << 2));
+        patch.extend(number((1 << 2) | 1));
+        patch.extend(b"XY");
+        patch.extend(number((2 << 2) | 3));
+        patch.extend(number(3 << 1));
+        patch.extend(number((2 << 2) | 2));
+        patch.extend(number(3 << 1));
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(target, apply(&patch, &source).unwrap());
+        assert!(apply(&patch, b"abcdeg").is_err());
+
+        // A target copy far longer than the ROM it's making
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        patch.extend(number((1 << 2) | 1));
+        patch.extend(b"XY");
+        patch.extend(number((0xfff_ffff << 2) | 3));
+        patch.extend(number(0));
+        let patch = with_footer(patch, &source, &target);
+        assert!(matches!(
+            apply(&patch, &source),
+            Err(IronNesError::PatchError(e)) if e.contains("past the end")
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+use super::Palette;
+use crate::error::*;
+
+use std::str::FromStr;
+
+/**
+ * A picture the PPU drew, as the 9-bit pixels it put out: emphasis << 6 |
+ * colour, greyscale already applied. Which RGB those are is up to the palette,
+ * so frontends, screenshots and tests all start from this and convert to the
+ * layout they want.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pixels: Vec<u16>,
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+
+    pub fn new() -> Self {
+        Frame {
+            pixels: vec![0; Self::WIDTH * Self::HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        Self::WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        Self::HEIGHT
+    }
+
+    /// All the pixels, row by row
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, pixel: u16) {
+        self.pixels[y * Self::WIDTH + x] = pixel;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * Self::WIDTH + x]
+    }
+
+    /**
+     * The picture in the colours of a palette, packed as `format` wants, with
+     * the overscan cut off the edges
+     */
+    pub fn convert(&self, palette: &Palette, format: PixelFormat, overscan: &Overscan) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(overscan.width() * overscan.height() * format.size());
+        for y in overscan.top..Self::HEIGHT - overscan.bottom {
+            for x in overscan.left..Self::WIDTH - overscan.right {
+                let (r, g, b) = palette.pixel_rgb(self.pixel(x, y));
+                match format {
+                    PixelFormat::Rgb24 => bytes.extend_from_slice(&[r, g, b]),
+                    PixelFormat::Rgba32 => bytes.extend_from_slice(&[r, g, b, 0xff]),
+                    PixelFormat::Bgra32 => bytes.extend_from_slice(&[b, g, r, 0xff]),
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn to_rgb24(&self, palette: &Palette, overscan: &Overscan) -> Vec<u8> {
+        self.convert(palette, PixelFormat::Rgb24, overscan)
+    }
+
+    pub fn to_rgba32(&self, palette: &Palette, overscan: &Overscan) -> Vec<u8> {
+        self.convert(palette, PixelFormat::Rgba32, overscan)
+    }
+
+    pub fn to_bgra(&self, palette: &Palette, overscan: &Overscan) -> Vec<u8> {
+        self.convert(palette, PixelFormat::Bgra32, overscan)
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Byte layouts a frame can be converted to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Rgb24,
+    /// R, G, B then an opaque alpha
+    Rgba32,
+    /// B, G, R then an opaque alpha, what little endian ARGB8888 textures hold
+    Bgra32,
+}
+
+impl PixelFormat {
+    /// Bytes to a pixel
+    pub fn size(&self) -> usize {
+        match self {
+            PixelFormat::Rgb24 => 3,
+            PixelFormat::Rgba32 | PixelFormat::Bgra32 => 4,
+        }
+    }
+}
+
+/**
+ * Pixels to cut off each edge of the picture. TVs hid some of the border
+ * behind the bezel, usually about 8 lines top and bottom, and games left
+ * garbage there that was never meant to be seen.
+ *
+ * Parsed from one number for every edge or four for top, bottom, left and
+ * right, e.g. "8" or "8,8,0,0".
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    /// What's cut off by a typical NTSC TV
+    pub const NTSC: Self = Self {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+
+    /// Width of the picture left over
+    pub fn width(&self) -> usize {
+        Frame::WIDTH - self.left - self.right
+    }
+
+    /// Height of the picture left over
+    pub fn height(&self) -> usize {
+        Frame::HEIGHT - self.top - self.bottom
+    }
+}
+
+impl FromStr for Overscan {
+    type Err = IronNesError;
+
+    fn from_str(s: &str) -> IronNesResult<Self> {
+        let bad = || IronNesError::OverscanError(s.to_string());
+        let edges = s
+            .split(',')
+            .map(|edge| edge.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad())?;
+
+        let overscan = match edges[..] {
+            [n] => Self {
+                top: n,
+                bottom: n,
+                left: n,
+                right: n,
+            },
+            [top, bottom, left, right] => Self {
+                top,
+                bottom,
+                left,
+                right,
+            },
+            _ => return Err(bad()),
+        };
+
+        // Leave at least a pixel to show
+        match overscan.top + overscan.bottom < Frame::HEIGHT
+            && overscan.left + overscan.right < Frame::WIDTH
+        {
+            true => Ok(overscan),
+            false => Err(bad()),
+        }
+    }
+}
+
+/**
+ * An RGB24 picture of any size, for what isn't a frame off the PPU: the
+ * pattern table viewer's sheet and the NTSC filter's wider output.
+ */
+pub struct Image {
+    width: usize,
+    height: usize,
+    /// RGB24 pixels, row by row
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Bytes per row, as SDL textures want it
+    pub fn pitch(&self) -> usize {
+        self.width * 3
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        let offset = (y * self.width + x) * 3;
+
+        self.pixels[offset] = rgb.0;
+        self.pixels[offset + 1] = rgb.1;
+        self.pixels[offset + 2] = rgb.2;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = (y * self.width + x) * 3;
+        let p = &self.pixels[offset..offset + 3];
+        (p[0], p[1], p[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let palette = Palette::default();
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, 0x16);
+        frame.set_pixel(1, 0, 0b001 << 6 | 0x30);
+        frame.set_pixel(8, 8, 0x2a);
+
+        let (r, g, b) = palette.rgb(0x16, 0);
+        let rgb = frame.to_rgb24(&palette, &Overscan::default());
+        assert_eq!(Frame::WIDTH * Frame::HEIGHT * 3, rgb.len());
+        assert_eq!(&[r, g, b], &rgb[0..3]);
+        let (r, g, b) = palette.rgb(0x30, 0b001);
+        assert_eq!(&[r, g, b], &rgb[3..6]);
+
+        let rgba = frame.to_rgba32(&palette, &Overscan::default());
+        assert_eq!(&[r, g, b, 0xff], &rgba[4..8]);
+        let bgra = frame.to_bgra(&palette, &Overscan::default());
+        assert_eq!(&[b, g, r, 0xff], &bgra[4..8]);
+
+        // Cropping 8 off every edge moves (8, 8) to the top left
+        let overscan: Overscan = "8".parse().unwrap();
+        let rgb = frame.to_rgb24(&palette, &overscan);
+        assert_eq!(240 * 224 * 3, rgb.len());
+        let (r, g, b) = palette.rgb(0x2a, 0);
+        assert_eq!(&[r, g, b], &rgb[0..3]);
+    }
+
+    #[test]
+    fn test_overscan() {
+        assert_eq!(Overscan::NTSC, "8,8,0,0".parse().unwrap());
+        assert_eq!(Overscan::default(), "0".parse().unwrap());
+        assert!("8,8".parse::<Overscan>().is_err());
+        assert!("top".parse::<Overscan>().is_err());
+        assert!("120".parse::<Overscan>().is_err());
+        assert_eq!(
+            (256, 224),
+            (Overscan::NTSC.width(), Overscan::NTSC.height())
+        );
+    }
+}
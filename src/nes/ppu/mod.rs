@@ -1,81 +1,461 @@
-pub struct Frame(pub Vec<u8>);
+mod frame;
+pub mod ntsc;
+mod palette;
+mod render;
+mod sprites;
 
-impl Frame {
-    const WIDTH: usize = 256 * 2;
-    const HEIGHT: usize = 240;
+use crate::nes::cartridge::CartridgeRegion;
+use crate::nes::mapper::Mapper;
+use crate::nes::memory::Addr;
+use crate::nes::timing::Timing;
 
-    fn new() -> Self {
-        Frame {
-            0: vec![0; Self::WIDTH * Self::HEIGHT * 3],
+use log::*;
+use std::cell::Cell;
+
+pub use frame::{Frame, Image, Overscan, PixelFormat};
+pub use palette::Palette;
+pub use render::pattern_tables;
+
+/**
+ * The 2C02 picture processing unit, seen from the CPU as the eight registers
+ * at $2000-$2007.
+ *
+ * Scrolling follows the "loopy" registers the real chip has: `v` is the VRAM
+ * address, which while rendering points at the tile being drawn, `t` is where
+ * writes to $2005/$2006 go before they're copied into `v`, `x` is the fine X
+ * scroll and `w` picks the first or second write to $2005/$2006.
+ *   yyy NN YYYYY XXXXX
+ *   fine Y, nametable, coarse Y, coarse X
+ *
+ * Reading $2002 and $2007 changes the PPU, those parts live in Cells so loads
+ * can stay `&self`.
+ *
+ * The PPU runs 341 dots a scanline, with as many scanlines a frame as its
+ * region's timing says: 262 on NTSC, one dot shorter on odd frames while
+ * rendering, and 312 on PAL and Dendy. Its NMI output is the vblank flag
+ * ANDed with the NMI enable bit in $2000, and the CPU sees an NMI when that
+ * line goes high.
+ */
+pub struct Ppu {
+    timing: Timing,
+    ctrl: u8,
+    mask: u8,
+    status: Cell<u8>,
+    v: Cell<Addr>,
+    t: Addr,
+    x: u8,
+    w: Cell<bool>,
+    /// $2007 reads come from a buffer filled by the read before
+    read_buffer: Cell<u8>,
+    /// Last value written to a register, what reads see in unused bits
+    io_latch: Cell<u8>,
+    palette: [u8; PALETTE_SIZE],
+    /// What palette RAM's colour indexes look like
+    colours: Palette,
+    oam: [u8; OAM_SIZE],
+    oam_addr: u8,
+    /// Secondary OAM, the sprites on the scanline being drawn
+    sprites: Vec<sprites::SpriteRow>,
+    /// Dot the sprite 0 hit flag gets set at on this scanline
+    sprite_zero_hit: Option<usize>,
+    dot: usize,
+    scanline: usize,
+    frame_count: u64,
+    is_odd_frame: bool,
+    /// Level of the NMI output, and whether it's gone high since last taken
+    nmi_line: Cell<bool>,
+    nmi: Cell<bool>,
+    /// Set by a $2002 read just before vblank starts, which stops it starting
+    suppress_vblank: Cell<bool>,
+    /// Frame being drawn, and the last one finished
+    back: Frame,
+    front: Frame,
+}
+
+const PALETTE_SIZE: usize = 0x20;
+const PALETTE_BEGIN: Addr = 0x3f00;
+const OAM_SIZE: usize = 0x100;
+
+const REG_CTRL: usize = 0;
+const REG_MASK: usize = 1;
+const REG_STATUS: usize = 2;
+const REG_OAM_ADDR: usize = 3;
+const REG_OAM_DATA: usize = 4;
+const REG_SCROLL: usize = 5;
+const REG_ADDR: usize = 6;
+const REG_DATA: usize = 7;
+
+const CTRL_INCREMENT: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BG_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_SIZE: u8 = 0b0010_0000;
+const CTRL_NMI: u8 = 0b1000_0000;
+
+const MASK_GREYSCALE: u8 = 0b0000_0001;
+const MASK_BG_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BG: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+const MASK_EMPHASIS: u8 = 0b1110_0000;
+
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_ZERO: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+impl Ppu {
+    pub const DOTS: usize = 341;
+
+    pub fn new() -> Self {
+        Self {
+            timing: Timing::NTSC,
+            ctrl: 0,
+            mask: 0,
+            status: Cell::new(0),
+            v: Cell::new(0),
+            t: 0,
+            x: 0,
+            w: Cell::new(false),
+            read_buffer: Cell::new(0),
+            io_latch: Cell::new(0),
+            palette: [0; PALETTE_SIZE],
+            colours: Palette::default(),
+            oam: [0; OAM_SIZE],
+            oam_addr: 0,
+            sprites: Vec::with_capacity(Self::MAX_SPRITES),
+            sprite_zero_hit: None,
+            dot: 0,
+            scanline: 0,
+            frame_count: 0,
+            is_odd_frame: false,
+            nmi_line: Cell::new(false),
+            nmi: Cell::new(false),
+            suppress_vblank: Cell::new(false),
+            back: Frame::default(),
+            front: Frame::default(),
         }
     }
 
-    fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
-        let offset = y * Self::WIDTH + x;
-        let offset = offset * 3;
+    /// The last whole frame drawn
+    pub fn frame(&self) -> &Frame {
+        &self.front
+    }
 
-        self.0[offset] = rgb.0;
-        self.0[offset + 1] = rgb.1;
-        self.0[offset + 2] = rgb.2;
+    /// Number of frames finished since power on
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
     }
-}
 
-pub struct Ppu {
-    vram: Vec<u8>,
-}
+    pub fn scanline(&self) -> usize {
+        self.scanline
+    }
 
-impl Ppu {
-    pub fn new(vram: Vec<u8>) -> Self {
-        Self { vram }
+    pub fn dot(&self) -> usize {
+        self.dot
+    }
+
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Switches to another region's frame, starting over at the top
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.scanline = 0;
+        self.dot = 0;
     }
 
-    pub fn render(&self) -> Frame {
-        let mut frame = Frame::new();
+    /// The colours the frames are shown in
+    pub fn colours(&self) -> &Palette {
+        &self.colours
+    }
 
-        let num_rows = Frame::WIDTH / 8 / 2;
-        let num_cols = Frame::HEIGHT / 8;
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.colours = palette;
+    }
 
-        for i in 0..256 {
-            let frame_x = i % num_rows;
-            let frame_y = i / num_rows;
-            self.load_tile(&mut frame, 8 * frame_x, 8 * frame_y, 0, i);
+    /**
+     * The PPUMASK emphasis bits as red, green and blue in bits 0-2. The 2C07
+     * and Dendy PPUs have the red and green bits the other way round.
+     */
+    pub fn emphasis(&self) -> u8 {
+        let bits = (self.mask & MASK_EMPHASIS) >> 5;
+        match self.timing.region {
+            CartridgeRegion::NTSC => bits,
+            _ => (bits & 0b100) | ((bits & 0b01) << 1) | ((bits & 0b10) >> 1),
         }
+    }
+
+    fn is_rendering(&self) -> bool {
+        (self.mask & (MASK_BG | MASK_SPRITES)) != 0
+    }
+
+    /// Whether the PPU is pulling the CPU's NMI line
+    pub fn nmi_line(&self) -> bool {
+        self.nmi_line.get()
+    }
 
-        frame
+    /// Takes the NMI raised by the line going high, if there is one
+    pub fn take_nmi(&mut self) -> bool {
+        self.nmi.take()
     }
 
-    fn load_tile(
-        &self,
-        frame: &mut Frame,
-        frame_x: usize,
-        frame_y: usize,
-        bank: usize,
-        n_tile: usize,
-    ) {
-        let bank = bank * 1000usize;
+    /// Raises an NMI on the line's rising edge
+    fn update_nmi(&self) {
+        let line = (self.status.get() & STATUS_VBLANK) != 0 && (self.ctrl & CTRL_NMI) != 0;
+        if line && !self.nmi_line.get() {
+            self.nmi.set(true);
+        }
+        self.nmi_line.set(line);
+    }
+
+    /// CPU read of a register, 0-7
+    pub fn load(&self, reg: usize, mapper: &dyn Mapper, vram: &[u8]) -> u8 {
+        let v = match reg {
+            REG_STATUS => {
+                // Reading as vblank starts races with it: a dot early and the
+                // flag never gets set, on the dot or just after and it's seen
+                // but there's no NMI
+                if self.scanline == self.timing.vblank_scanline {
+                    match self.dot {
+                        1 => self.suppress_vblank.set(true),
+                        2..=3 => self.nmi.set(false),
+                        _ => (),
+                    }
+                }
+
+                let status = self.status.get();
+                self.status.set(status & !STATUS_VBLANK);
+                self.w.set(false);
+                self.update_nmi();
+                (status & 0xe0) | (self.io_latch.get() & 0x1f)
+            }
+            // The attribute byte's unused bits read back as 0
+            REG_OAM_DATA => match self.oam_addr & 0b11 {
+                2 => self.oam[self.oam_addr as usize] & 0xe3,
+                _ => self.oam[self.oam_addr as usize],
+            },
+            REG_DATA => {
+                let addr = self.v.get();
+                let buffered = self.read_buffer.get();
+                self.increment_v();
+
+                // Palette reads skip the buffer, which gets the nametable
+                // byte underneath instead
+                let addr = addr & 0x3fff;
+                self.read_buffer.set(mapper.load_ppu(bus_addr(addr), vram));
+                match addr {
+                    PALETTE_BEGIN..=0x3fff => {
+                        (self.palette[palette_offset(addr)] & 0x3f) | (self.io_latch.get() & 0xc0)
+                    }
+                    _ => buffered,
+                }
+            }
+            _ => self.io_latch.get(),
+        };
+        self.io_latch.set(v);
+        v
+    }
+
+    /// CPU write of a register, 0-7
+    pub fn store(&mut self, reg: usize, v: u8, mapper: &mut dyn Mapper, vram: &mut [u8]) {
+        self.io_latch.set(v);
+        match reg {
+            REG_CTRL => {
+                // Turning NMIs on during vblank raises one straight away
+                self.ctrl = v;
+                self.update_nmi();
+                self.t = (self.t & !0x0c00) | ((v as Addr & 0b11) << 10);
+            }
+            REG_MASK => self.mask = v,
+            REG_OAM_ADDR => self.oam_addr = v,
+            REG_OAM_DATA => self.store_oam(v),
+            REG_SCROLL => {
+                match self.w.get() {
+                    false => {
+                        self.t = (self.t & !0x001f) | (v as Addr >> 3);
+                        self.x = v & 0b111;
+                    }
+                    true => {
+                        self.t = (self.t & !0x73e0)
+                            | ((v as Addr & 0b111) << 12)
+                            | ((v as Addr >> 3) << 5);
+                    }
+                }
+                self.w.set(!self.w.get());
+            }
+            REG_ADDR => {
+                match self.w.get() {
+                    false => self.t = (self.t & 0x00ff) | ((v as Addr & 0x3f) << 8),
+                    true => {
+                        self.t = (self.t & 0xff00) | v as Addr;
+                        self.v.set(self.t);
+                    }
+                }
+                self.w.set(!self.w.get());
+            }
+            REG_DATA => {
+                let addr = self.v.get() & 0x3fff;
+                match addr {
+                    PALETTE_BEGIN..=0x3fff => self.palette[palette_offset(addr)] = v,
+                    _ => mapper.store_ppu(bus_addr(addr), v, vram),
+                }
+                self.increment_v();
+            }
+            _ => trace!("PPU register {} not handled, {:02x}", reg, v),
+        }
+    }
+
+    /// Writes a byte to OAM at OAMADDR, as $2004 and sprite DMA do
+    pub fn store_oam(&mut self, v: u8) {
+        self.oam[self.oam_addr as usize] = v;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    /// $2007 accesses move `v` on by 1 or 32
+    fn increment_v(&self) {
+        let step = match self.ctrl & CTRL_INCREMENT {
+            0 => 1,
+            _ => 32,
+        };
+        self.v.set(self.v.get().wrapping_add(step) & 0x7fff);
+    }
+
+    /**
+     * Runs the PPU for a number of dots, three for every CPU cycle. Scanlines
+     * are drawn whole as they start, with the sprite 0 hit flag held back to
+     * the dot the hit happens at.
+     */
+    pub fn tick(&mut self, dots: usize, mapper: &mut dyn Mapper, vram: &[u8]) {
+        for _ in 0..dots {
+            self.step(mapper, vram);
+        }
+    }
+
+    fn step(&mut self, mapper: &mut dyn Mapper, vram: &[u8]) {
+        let is_visible = self.scanline < Frame::HEIGHT;
+        let is_pre_render = self.scanline == self.timing.pre_render_scanline();
+        let is_vblank_start = self.scanline == self.timing.vblank_scanline;
+        let is_rendering = self.is_rendering();
+
+        match (self.dot, self.scanline) {
+            (1, _) if is_vblank_start => {
+                if !self.suppress_vblank.take() {
+                    self.status.set(self.status.get() | STATUS_VBLANK);
+                    self.update_nmi();
+                }
+                std::mem::swap(&mut self.front, &mut self.back);
+                self.frame_count += 1;
+            }
+            (1, _) if is_pre_render => {
+                self.status.set(0);
+                self.update_nmi();
+            }
+            (1, _) if is_visible => self.render_scanline(mapper, vram),
+            (256, _) if (is_visible || is_pre_render) && is_rendering => self.increment_y(),
+            (257, _) if (is_visible || is_pre_render) && is_rendering => {
+                self.copy_x();
+                self.oam_addr = 0;
+                match is_visible {
+                    true => self.evaluate_sprites(mapper, vram),
+                    false => self.sprites.clear(),
+                }
+            }
+            (260, _) if (is_visible || is_pre_render) && is_rendering => mapper.scanline(),
+            (304, _) if is_pre_render && is_rendering => self.copy_y(),
+            _ => (),
+        }
 
-        let tile = &self.vram[(bank + n_tile * 16)..(bank + n_tile * 16 + 16)];
+        if self.sprite_zero_hit == Some(self.dot) {
+            self.status.set(self.status.get() | STATUS_SPRITE_ZERO);
+            self.sprite_zero_hit = None;
+        }
 
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
+        self.dot += 1;
 
-            for x in (0..=7).rev() {
-                let value = ((1 & upper) << 1) | (1 & lower);
-                upper = upper >> 1;
-                lower = lower >> 1;
+        // Odd frames skip the last dot of the pre-render line when rendering
+        let skips_dot = self.timing.skips_odd_dot && self.is_odd_frame && is_rendering;
+        if is_pre_render && skips_dot && self.dot == Self::DOTS - 1 {
+            self.dot = Self::DOTS;
+        }
 
-                let rgb = match value {
-                    0 => PALLETE[0x01],
-                    1 => PALLETE[0x23],
-                    2 => PALLETE[0x27],
-                    3 => PALLETE[0x30],
-                    _ => panic!("Impossible palette {}", value),
-                };
+        if self.dot == Self::DOTS {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % self.timing.scanlines;
+            if self.scanline == 0 {
+                self.is_odd_frame = !self.is_odd_frame;
+            }
+        }
+    }
 
-                frame.set_pixel(frame_x + x, frame_y + y, rgb);
+    /// Moves `v` down a row of pixels, into the next nametable after row 29
+    fn increment_y(&mut self) {
+        let mut v = self.v.get();
+        if (v & 0x7000) != 0x7000 {
+            v += 0x1000;
+        } else {
+            v &= !0x7000;
+            let mut coarse_y = (v & 0x03e0) >> 5;
+            match coarse_y {
+                29 => {
+                    coarse_y = 0;
+                    v ^= 0x0800;
+                }
+                31 => coarse_y = 0,
+                _ => coarse_y += 1,
             }
+            v = (v & !0x03e0) | (coarse_y << 5);
         }
+        self.v.set(v);
+    }
+
+    /// Back to the left of the screen for the next scanline
+    fn copy_x(&mut self) {
+        self.v.set((self.v.get() & !0x041f) | (self.t & 0x041f));
+    }
+
+    /// Back to the top of the screen for the next frame
+    fn copy_y(&mut self) {
+        self.v.set((self.v.get() & !0x7be0) | (self.t & 0x7be0));
+    }
+
+    /// Palette RAM, as a colour index into PALLETE
+    fn palette_entry(&self, index: usize) -> u8 {
+        self.palette[palette_offset(PALETTE_BEGIN + index as Addr)] & 0x3f
+    }
+
+    /**
+     * A palette RAM entry as PPUMASK has the PPU put it out. Greyscale keeps
+     * only the brightness column, and the emphasis bits go on top.
+     */
+    fn pixel(&self, index: usize) -> u16 {
+        let mut colour = self.palette_entry(index);
+        if (self.mask & MASK_GREYSCALE) != 0 {
+            colour &= 0x30;
+        }
+        ((self.emphasis() as u16) << 6) | colour as u16
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// $3000-$3EFF mirrors the nametables at $2000-$2EFF
+pub fn bus_addr(addr: Addr) -> Addr {
+    match addr & 0x3fff {
+        addr @ 0x3000..=0x3fff => addr - 0x1000,
+        addr => addr,
+    }
+}
+
+/// $3F10/$3F14/$3F18/$3F1C are the same bytes as $3F00/$3F04/$3F08/$3F0C
+fn palette_offset(addr: Addr) -> usize {
+    let offset = addr as usize & (PALETTE_SIZE - 1);
+    match offset & 0x13 {
+        0x10 => offset & !0x10,
+        _ => offset,
     }
 }
 
@@ -95,3 +475,160 @@ pub static PALLETE: [(u8,u8,u8); 64] = [
    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cartridge::MirrorDirection;
+    use crate::nes::mapper::{Nrom, VRAM_SIZE};
+
+    fn setup() -> (Ppu, Nrom, Vec<u8>) {
+        let mapper = Nrom::new(vec![0; 0x4000], vec![0; 0x2000], MirrorDirection::Vertical);
+        (Ppu::new(), mapper, vec![0; VRAM_SIZE])
+    }
+
+    #[test]
+    fn test_scroll_registers() {
+        let (mut ppu, mut mapper, mut vram) = setup();
+
+        // The example from the nesdev wiki's PPU scrolling page
+        ppu.store(REG_CTRL, 0b11, &mut mapper, &mut vram);
+        ppu.load(REG_STATUS, &mapper, &vram);
+        ppu.store(REG_SCROLL, 0b0111_1101, &mut mapper, &mut vram);
+        assert_eq!((0x0c0f, 0b101), (ppu.t, ppu.x));
+        ppu.store(REG_SCROLL, 0b0101_1110, &mut mapper, &mut vram);
+        assert_eq!(0x6d6f, ppu.t);
+        ppu.store(REG_ADDR, 0b0011_1101, &mut mapper, &mut vram);
+        assert_eq!(0x3d6f, ppu.t);
+        ppu.store(REG_ADDR, 0b1111_0000, &mut mapper, &mut vram);
+        assert_eq!((0x3df0, 0x3df0), (ppu.t, ppu.v.get()));
+    }
+
+    #[test]
+    fn test_data_port() {
+        let (mut ppu, mut mapper, mut vram) = setup();
+
+        let set_addr = |ppu: &mut Ppu, mapper: &mut Nrom, vram: &mut Vec<u8>, addr: Addr| {
+            ppu.store(REG_ADDR, (addr >> 8) as u8, mapper, vram);
+            ppu.store(REG_ADDR, addr as u8, mapper, vram);
+        };
+
+        set_addr(&mut ppu, &mut mapper, &mut vram, 0x2400);
+        ppu.store(REG_DATA, 0x12, &mut mapper, &mut vram);
+        ppu.store(REG_DATA, 0x34, &mut mapper, &mut vram);
+
+        // Vertical mirroring, $2C00 is $2400. Reads lag one behind
+        set_addr(&mut ppu, &mut mapper, &mut vram, 0x2c00);
+        ppu.load(REG_DATA, &mapper, &vram);
+        assert_eq!(0x12, ppu.load(REG_DATA, &mapper, &vram));
+        assert_eq!(0x34, ppu.load(REG_DATA, &mapper, &vram));
+
+        // Palette reads don't, and $3F10 is $3F00
+        set_addr(&mut ppu, &mut mapper, &mut vram, 0x3f10);
+        ppu.store(REG_DATA, 0x2a, &mut mapper, &mut vram);
+        set_addr(&mut ppu, &mut mapper, &mut vram, 0x3f00);
+        assert_eq!(0x2a, ppu.load(REG_DATA, &mapper, &vram) & 0x3f);
+
+        // Going down a row at a time
+        ppu.store(REG_CTRL, CTRL_INCREMENT, &mut mapper, &mut vram);
+        set_addr(&mut ppu, &mut mapper, &mut vram, 0x2000);
+        ppu.store(REG_DATA, 0x56, &mut mapper, &mut vram);
+        assert_eq!(0x2020, ppu.v.get());
+    }
+
+    #[test]
+    fn test_vblank() {
+        let (mut ppu, mut mapper, vram) = setup();
+        ppu.ctrl = CTRL_NMI;
+
+        let to_vblank = Timing::NTSC.vblank_scanline * Ppu::DOTS + 2;
+        ppu.tick(to_vblank, &mut mapper, &vram);
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+        assert_eq!(1, ppu.frame_count());
+
+        // Reading the status clears the flag
+        assert_eq!(
+            STATUS_VBLANK,
+            ppu.load(REG_STATUS, &mapper, &vram) & STATUS_VBLANK
+        );
+        assert_eq!(0, ppu.load(REG_STATUS, &mapper, &vram) & STATUS_VBLANK);
+    }
+
+    #[test]
+    fn test_vblank_race() {
+        let (mut ppu, mut mapper, vram) = setup();
+        ppu.ctrl = CTRL_NMI;
+
+        // A read the dot before vblank starts means it never does
+        let to_vblank = Timing::NTSC.vblank_scanline * Ppu::DOTS;
+        ppu.tick(to_vblank + 1, &mut mapper, &vram);
+        assert_eq!(0, ppu.load(REG_STATUS, &mapper, &vram) & STATUS_VBLANK);
+        ppu.tick(1, &mut mapper, &vram);
+        assert_eq!(0, ppu.status.get() & STATUS_VBLANK);
+        assert!(!ppu.take_nmi());
+
+        // Reading on the dot after sees the flag, but there's no NMI
+        ppu.tick(Timing::NTSC.scanlines * Ppu::DOTS + 1, &mut mapper, &vram);
+        assert_eq!(
+            STATUS_VBLANK,
+            ppu.load(REG_STATUS, &mapper, &vram) & STATUS_VBLANK
+        );
+        assert!(!ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_odd_frame_skip() {
+        let (mut ppu, mut mapper, vram) = setup();
+        let frame = Timing::NTSC.scanlines * Ppu::DOTS;
+
+        ppu.tick(frame, &mut mapper, &vram);
+        assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+
+        // Odd frames are a dot short, but only while rendering
+        ppu.tick(frame, &mut mapper, &vram);
+        assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+        ppu.mask = MASK_BG;
+        ppu.tick(frame, &mut mapper, &vram);
+        assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+        ppu.tick(frame - 1, &mut mapper, &vram);
+        assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+    }
+
+    #[test]
+    fn test_pal_frame() {
+        let (mut ppu, mut mapper, vram) = setup();
+        ppu.set_timing(Timing::PAL);
+        ppu.mask = MASK_BG | 0b0010_0000;
+        assert_eq!(0b010, ppu.emphasis());
+
+        // 312 scanlines, and no dot skipped on odd frames
+        let frame = Timing::PAL.scanlines * Ppu::DOTS;
+        ppu.tick(2 * frame - 1, &mut mapper, &vram);
+        assert_eq!((311, 340), (ppu.scanline(), ppu.dot()));
+        assert_eq!(2, ppu.frame_count());
+
+        // Dendy's vblank starts 50 scanlines later
+        ppu.set_timing(Timing::DENDY);
+        let to_vblank = Timing::NTSC.vblank_scanline * Ppu::DOTS;
+        ppu.tick(to_vblank + 2, &mut mapper, &vram);
+        assert_eq!(0, ppu.status.get() & STATUS_VBLANK);
+        ppu.tick(50 * Ppu::DOTS, &mut mapper, &vram);
+        assert_ne!(0, ppu.status.get() & STATUS_VBLANK);
+    }
+
+    #[test]
+    fn test_greyscale_and_emphasis() {
+        let (mut ppu, _, _) = setup();
+        ppu.palette[0] = 0x16;
+        assert_eq!(0x16, ppu.pixel(0));
+
+        ppu.mask = MASK_GREYSCALE;
+        assert_eq!(0x10, ppu.pixel(0));
+
+        ppu.mask = 0b0100_0000;
+        assert_eq!(0b010 << 6 | 0x16, ppu.pixel(0));
+        ppu.set_timing(Timing::PAL);
+        assert_eq!(0b001 << 6 | 0x16, ppu.pixel(0));
+    }
+}
@@ -0,0 +1,330 @@
+use super::{Frame, Image, Palette};
+use crate::error::*;
+
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/**
+ * The 2C02 doesn't output RGB, it draws each pixel as 8 samples of a square
+ * wave between two voltages, with the colour index picking the wave's phase
+ * out of 12 and the levels. Colour 0 is the high level only, $D-$F the low
+ * only, so they have no hue. Emphasis darkens the wave during the phases of
+ * the emphasised colours.
+ *
+ * Levels are relative to sync, from the nesdev wiki's measurements.
+ */
+const LEVELS_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const LEVELS_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+/// Phases of the colour subcarrier a pixel's wave is sampled at
+pub const PHASES: usize = 12;
+
+/// The colour burst's phase, which the decoder measures hues from
+const BURST_PHASE: f32 = 4.5;
+
+fn in_colour_phase(colour: usize, phase: usize) -> bool {
+    (colour + phase) % PHASES < 6
+}
+
+/**
+ * The signal for a 9-bit pixel (emphasis << 6 | colour) at one of the 12
+ * phases, scaled so black is 0.0 and white 1.0
+ */
+pub fn signal(pixel: u16, phase: usize) -> f32 {
+    let colour = (pixel & 0x0f) as usize;
+    let emphasis = (pixel >> 6) & 0b111;
+    let level = match colour {
+        0x0e..=0x0f => 1,
+        _ => ((pixel >> 4) & 0b11) as usize,
+    };
+
+    let low = match colour {
+        0x00 => LEVELS_HIGH[level],
+        _ => LEVELS_LOW[level],
+    };
+    let high = match colour {
+        0x0d..=0x0f => LEVELS_LOW[level],
+        _ => LEVELS_HIGH[level],
+    };
+    let mut v = match in_colour_phase(colour, phase) {
+        true => high,
+        false => low,
+    };
+
+    // Red, green and blue emphasis darken the wave in the phases of colours
+    // $C, $4 and $8
+    let is_attenuated = [0x0c, 0x04, 0x08]
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| (emphasis >> bit) & 1 == 1)
+        .any(|(_, &colour)| in_colour_phase(colour, phase));
+    if is_attenuated && colour < 0x0e {
+        v *= EMPHASIS_ATTENUATION;
+    }
+
+    (v - BLACK) / (WHITE - BLACK)
+}
+
+/**
+ * How to decode the 2C02's signal into colours, the knobs on a TV: hue in
+ * degrees, saturation and contrast as multipliers, brightness added to the
+ * luma and the gamma of the display the colours are for. The defaults show
+ * the signal as it is, on a 2.2 gamma display.
+ *
+ * Parsed from "hue=-5,saturation=1.2", with any left out at their defaults.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NtscParams {
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    pub gamma: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+impl FromStr for NtscParams {
+    type Err = IronNesError;
+
+    fn from_str(s: &str) -> IronNesResult<Self> {
+        let mut params = Self::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let bad = || IronNesError::PaletteError(format!("can't use '{}'", setting));
+            let mut parts = setting.splitn(2, '=');
+            let name = parts.next().unwrap_or_default();
+            let value: f32 = parts
+                .next()
+                .and_then(|v| v.trim().parse().ok())
+                .ok_or_else(bad)?;
+
+            match name.trim() {
+                "hue" => params.hue = value,
+                "saturation" => params.saturation = value,
+                "contrast" => params.contrast = value,
+                "brightness" => params.brightness = value,
+                "gamma" if value > 0.0 => params.gamma = value,
+                _ => return Err(bad()),
+            }
+        }
+        Ok(params)
+    }
+}
+
+impl NtscParams {
+    /// The subcarrier's cosine and sine at each phase, with the hue turned
+    fn carrier(&self) -> [(f32, f32); PHASES] {
+        let mut carrier = [(0.0, 0.0); PHASES];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            let angle = PI * (phase as f32 + BURST_PHASE) / 6.0 + self.hue.to_radians();
+            *c = (angle.cos(), angle.sin());
+        }
+        carrier
+    }
+
+    /// Luma and the I and Q chroma of a pixel, averaged over a colour cycle
+    pub fn yiq(&self, pixel: u16) -> (f32, f32, f32) {
+        let samples = (0..PHASES).map(|phase| (phase, signal(pixel, phase)));
+        self.decode(samples, &self.carrier())
+    }
+
+    /// Demodulates a colour cycle's worth of samples, given with their phases
+    fn decode(
+        &self,
+        samples: impl Iterator<Item = (usize, f32)>,
+        carrier: &[(f32, f32); PHASES],
+    ) -> (f32, f32, f32) {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for (phase, v) in samples {
+            let (cos, sin) = carrier[phase];
+            y += v;
+            i += v * cos;
+            q += v * sin;
+        }
+
+        let scale = PHASES as f32;
+        let y = (y / scale) * self.contrast + self.brightness;
+        let chroma = self.saturation * self.contrast / scale;
+        (y, i * chroma, q * chroma)
+    }
+
+    /// FCC's YIQ to RGB, gamma corrected for the display
+    pub fn rgb(&self, (y, i, q): (f32, f32, f32)) -> (u8, u8, u8) {
+        let channel = |v: f32| {
+            let v = v.clamp(0.0, 1.0).powf(2.2 / self.gamma);
+            (v * 255.0).round() as u8
+        };
+        (
+            channel(y + 0.946_882 * i + 0.623_557 * q),
+            channel(y - 0.274_788 * i - 0.635_691 * q),
+            channel(y - 1.108_545 * i + 1.709_007 * q),
+        )
+    }
+}
+
+/// Decodes all 512 colours the 2C02 can make, emphasis included
+pub fn generate_palette(params: &NtscParams) -> Palette {
+    let colours: Vec<(u8, u8, u8)> = (0..Palette::EMPHASIS_COLOURS as u16)
+        .map(|pixel| params.rgb(params.yiq(pixel)))
+        .collect();
+    Palette::from_colours(colours)
+}
+
+/**
+ * Turns the PPU's 9-bit pixels back into the composite signal a TV gets and
+ * decodes that, like blargg's nes_ntsc. Colours bleed into their neighbours,
+ * and the pattern crawls from frame to frame as the subcarrier's phase moves,
+ * which some games count on to blend stripes into colours no palette has.
+ *
+ * Each PPU pixel is 8 samples of the signal, 12 make a cycle of the colour
+ * subcarrier, and each scanline starts 4 samples further round. Decoding
+ * averages a cycle of samples around each output pixel, 7 to every 3 the PPU
+ * drew, so the output is 602 pixels wide.
+ */
+pub struct NtscFilter {
+    params: NtscParams,
+    carrier: [(f32, f32); PHASES],
+    /// Phase the frame starts at, which flips between frames on NTSC
+    frame_phase: usize,
+    image: Image,
+}
+
+impl NtscFilter {
+    pub const WIDTH: usize = 602;
+    const SAMPLES_PER_PIXEL: usize = 8;
+    const LINE_PHASE: usize = 4;
+
+    pub fn new(params: NtscParams) -> Self {
+        Self {
+            params,
+            carrier: params.carrier(),
+            frame_phase: 0,
+            image: Image::new(Self::WIDTH, Frame::HEIGHT),
+        }
+    }
+
+    /// Filters a frame into a picture 602 wide
+    pub fn apply(&mut self, frame: &Frame) -> &Image {
+        let mut samples = vec![0.0; Frame::WIDTH * Self::SAMPLES_PER_PIXEL];
+        for (y, row) in frame.pixels().chunks_exact(Frame::WIDTH).enumerate() {
+            let line_phase = self.frame_phase + y * Self::LINE_PHASE;
+            for (n, sample) in samples.iter_mut().enumerate() {
+                let pixel = row[n / Self::SAMPLES_PER_PIXEL];
+                *sample = signal(pixel, (line_phase + n) % PHASES);
+            }
+
+            for x in 0..Self::WIDTH {
+                // A cycle of samples either side of the output pixel's centre,
+                // blank past the ends of the line
+                let centre = (2 * x + 1) * samples.len() / (2 * Self::WIDTH);
+                let start = centre as isize - (PHASES / 2) as isize;
+                let cycle = (start..start + PHASES as isize).map(|n| {
+                    let v = match n {
+                        0.. => samples.get(n as usize).copied().unwrap_or(0.0),
+                        _ => 0.0,
+                    };
+                    (
+                        (line_phase as isize + n).rem_euclid(PHASES as isize) as usize,
+                        v,
+                    )
+                });
+                let yiq = self.params.decode(cycle, &self.carrier);
+                self.image.set_pixel(x, y, self.params.rgb(yiq));
+            }
+        }
+
+        self.frame_phase ^= Self::LINE_PHASE;
+        &self.image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_palette() {
+        let palette = generate_palette(&NtscParams::default());
+
+        // Greys have no chroma, and $0D is blacker than black
+        assert_eq!((0, 0, 0), palette.rgb(0x0f, 0));
+        assert_eq!((0, 0, 0), palette.rgb(0x0d, 0));
+        assert_eq!((255, 255, 255), palette.rgb(0x20, 0));
+        let (r, g, b) = palette.rgb(0x10, 0);
+        assert!(r == g && g == b && r > 0x80 && r < 0xff);
+
+        let (r, g, b) = palette.rgb(0x16, 0);
+        assert!(r > g && r > b);
+        let (r, g, b) = palette.rgb(0x1a, 0);
+        assert!(g > r && g > b);
+        let (r, g, b) = palette.rgb(0x12, 0);
+        assert!(b > r && b > g);
+
+        // Red emphasis darkens green and blue more than red
+        let (r, g, _) = palette.rgb(0x30, 0);
+        let (er, eg, _) = palette.rgb(0x30, 0b001);
+        assert!(er as f32 / r as f32 > eg as f32 / g as f32);
+    }
+
+    #[test]
+    fn test_params() {
+        let params: NtscParams = "hue=-5, saturation=1.5".parse().unwrap();
+        assert_eq!(
+            (-5.0, 1.5, 2.2),
+            (params.hue, params.saturation, params.gamma)
+        );
+        assert_eq!(NtscParams::default(), "".parse().unwrap());
+        assert!("tint=3".parse::<NtscParams>().is_err());
+        assert!("hue".parse::<NtscParams>().is_err());
+
+        let grey = "saturation=0".parse::<NtscParams>().unwrap();
+        let (r, g, b) = generate_palette(&grey).rgb(0x16, 0);
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn test_ntsc_filter() {
+        let params = NtscParams::default();
+        let palette = generate_palette(&params);
+        let mut filter = NtscFilter::new(params);
+
+        // Flat colour comes out as the palette has it, away from the edges
+        let mut frame = Frame::new();
+        fill(&mut frame, |_| 0x16);
+        let image = filter.apply(&frame);
+        assert_eq!(NtscFilter::WIDTH, image.width());
+        let (r, g, b) = image.pixel(300, 100);
+        let (pr, pg, pb) = palette.rgb(0x16, 0);
+        assert!((r as i32 - pr as i32).abs() <= 2);
+        assert!((g as i32 - pg as i32).abs() <= 2);
+        assert!((b as i32 - pb as i32).abs() <= 2);
+
+        // Black and white stripes pick up colour, which moves between frames
+        fill(&mut frame, |x| [0x0f, 0x30][x % 2]);
+        let first = filter.apply(&frame).pixel(300, 100);
+        let (r, g, b) = first;
+        assert!(r != g || g != b);
+        assert_ne!(first, filter.apply(&frame).pixel(300, 100));
+    }
+
+    fn fill(frame: &mut Frame, pixel: impl Fn(usize) -> u16) {
+        for y in 0..Frame::HEIGHT {
+            for x in 0..Frame::WIDTH {
+                frame.set_pixel(x, y, pixel(x));
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use super::PALLETE;
+use crate::error::*;
+use crate::nes::cartridge::CartridgeRegion;
+
+use std::fs;
+
+/**
+ * The RGB colours the PPU's 64 colour indexes are shown as, under each of the
+ * 8 combinations of PPUMASK's emphasis bits: index = emphasis << 6 | colour,
+ * with emphasis being red, green and blue in bits 0-2.
+ *
+ * .pal files are those tables as RGB triples, either just the 64 colours or
+ * all 512. Without the emphasised colours they're worked out by dimming the
+ * channels that aren't emphasised, as the 2C02 darkens the whole signal
+ * outside the emphasised hue.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colours: Vec<(u8, u8, u8)>,
+}
+
+/// How much each emphasis bit darkens the other two channels
+const ATTENUATION: f32 = 0.816_328;
+
+impl Palette {
+    pub const COLOURS: usize = 64;
+    pub const EMPHASIS_COLOURS: usize = 512;
+
+    /// Palettes that come with IronNES, for the CLI
+    pub const BUILTIN: [&'static str; 5] = ["2c02", "2c03", "2c07", "fceux", "smooth"];
+
+    /// Builds the emphasised colours from the 64 plain ones
+    pub fn new(colours: &[(u8, u8, u8)]) -> Self {
+        let colours = (0..Self::EMPHASIS_COLOURS)
+            .map(|i| {
+                let (r, g, b) = colours[i % Self::COLOURS];
+                let emphasis = (i / Self::COLOURS) as u8;
+
+                // Columns $E and $F are black, which emphasis can't darken
+                if emphasis == 0 || (i & 0x0e) == 0x0e {
+                    return (r, g, b);
+                }
+
+                let dim = |v: u8, channel: u8| {
+                    let others = (emphasis & !(1 << channel)).count_ones() as i32;
+                    (v as f32 * ATTENUATION.powi(others)).round() as u8
+                };
+                (dim(r, 0), dim(g, 1), dim(b, 2))
+            })
+            .collect();
+        Self { colours }
+    }
+
+    /// A palette of all 512 colours, emphasised ones included
+    pub fn from_colours(colours: Vec<(u8, u8, u8)>) -> Self {
+        assert_eq!(Self::EMPHASIS_COLOURS, colours.len());
+        Self { colours }
+    }
+
+    /// A .pal file's contents, 64 or 512 RGB triples
+    pub fn from_bytes(data: &[u8]) -> IronNesResult<Self> {
+        let colours: Vec<(u8, u8, u8)> = data.chunks_exact(3).map(|c| (c[0], c[1], c[2])).collect();
+        match (data.len() % 3, colours.len()) {
+            (0, Self::COLOURS) => Ok(Self::new(&colours)),
+            (0, Self::EMPHASIS_COLOURS) => Ok(Self::from_colours(colours)),
+            _ => Err(IronNesError::PaletteError(format!(
+                "{} bytes isn't 64 or 512 RGB colours",
+                data.len()
+            ))),
+        }
+    }
+
+    /// Loads a .pal file
+    pub fn open(path: &str) -> IronNesResult<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /**
+     * One of the palettes in BUILTIN: the 2C02 colours IronNES defaults to,
+     * the 2C03's from the arcade RGB PPU, the PAL 2C07's, FCEUX's default and
+     * FirebrandX's Smooth.
+     */
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "2c02" => Some(Self::default()),
+            "2c03" => Some(rgb_ppu()),
+            "2c07" => Some(Self::new(&RP2C07)),
+            "fceux" => Some(Self::new(&FCEUX)),
+            "smooth" => Some(Self::new(&SMOOTH)),
+            _ => None,
+        }
+    }
+
+    /// The colours of the PPU a region's consoles have, the 2C07 on PAL and Dendy
+    pub fn from_region(region: CartridgeRegion) -> Self {
+        match region {
+            CartridgeRegion::NTSC => Self::default(),
+            CartridgeRegion::PAL | CartridgeRegion::Dendy => Self::new(&RP2C07),
+        }
+    }
+
+    /// RGB of a colour index with the PPU's emphasis bits, red green blue
+    pub fn rgb(&self, colour: u8, emphasis: u8) -> (u8, u8, u8) {
+        self.pixel_rgb(((emphasis as u16) << 6) | (colour as u16 & 0x3f))
+    }
+
+    /// RGB of a 9-bit pixel, emphasis << 6 | colour
+    pub fn pixel_rgb(&self, pixel: u16) -> (u8, u8, u8) {
+        self.colours[pixel as usize & (Self::EMPHASIS_COLOURS - 1)]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new(&PALLETE)
+    }
+}
+
+/**
+ * The 2C03 has RGB outputs with 8 levels a channel. Its emphasis bits don't
+ * darken anything, they turn their channel full on.
+ */
+fn rgb_ppu() -> Palette {
+    let level = |l: u16| (l * 255 / 7) as u8;
+    let colours = (0..Palette::EMPHASIS_COLOURS)
+        .map(|i| {
+            let rgb = RGB_PPU[i % Palette::COLOURS];
+            let emphasis = i / Palette::COLOURS;
+            let channel = |shift: u16, bit: usize| match (emphasis >> bit) & 1 {
+                1 => 255,
+                _ => level((rgb >> shift) & 0b111),
+            };
+            (channel(6, 0), channel(3, 1), channel(0, 2))
+        })
+        .collect();
+    Palette { colours }
+}
+
+/// 2C03 levels, as the octal RGB digits the datasheets give
+#[rustfmt::skip]
+const RGB_PPU: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/**
+ * The 2C07's colours. It puts out the 2C02's levels, but a PAL TV decodes its
+ * hues 15 degrees round from where an NTSC one puts the 2C02's, which turns
+ * the blues greener and the reds more orange.
+ */
+#[rustfmt::skip]
+const RP2C07: [(u8, u8, u8); 64] = [
+    (0x66, 0x66, 0x66), (0x04, 0x28, 0x64), (0x1A, 0x19, 0x78), (0x33, 0x0D, 0x76), (0x48, 0x07, 0x5E),
+    (0x53, 0x09, 0x36), (0x52, 0x12, 0x09), (0x44, 0x20, 0x00), (0x2E, 0x2F, 0x00), (0x15, 0x3B, 0x00),
+    (0x00, 0x41, 0x00), (0x00, 0x3F, 0x12), (0x00, 0x36, 0x3F), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00), (0xAE, 0xAE, 0xAE), (0x2D, 0x5D, 0xAC), (0x4A, 0x49, 0xC7), (0x6B, 0x39, 0xC4),
+    (0x87, 0x31, 0xA4), (0x96, 0x33, 0x6F), (0x94, 0x3F, 0x34), (0x82, 0x52, 0x02), (0x64, 0x66, 0x00),
+    (0x43, 0x76, 0x00), (0x28, 0x7E, 0x0B), (0x19, 0x7B, 0x3F), (0x1B, 0x6F, 0x7B), (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0x7C, 0xAC, 0xFD), (0x9A, 0x98, 0xFF),
+    (0xBB, 0x88, 0xFF), (0xD7, 0x80, 0xF4), (0xE6, 0x82, 0xBF), (0xE4, 0x8E, 0x83), (0xD2, 0xA1, 0x51),
+    (0xB4, 0xB5, 0x35), (0x92, 0xC6, 0x39), (0x77, 0xCE, 0x59), (0x68, 0xCB, 0x8F), (0x6A, 0xBF, 0xCA),
+    (0x4E, 0x4E, 0x4E), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0xC9, 0xDD, 0xFE),
+    (0xD5, 0xD5, 0xFF), (0xE3, 0xCE, 0xFF), (0xEE, 0xCB, 0xFB), (0xF5, 0xCC, 0xE5), (0xF4, 0xD1, 0xCC),
+    (0xEC, 0xD8, 0xB7), (0xE0, 0xE1, 0xAC), (0xD2, 0xE7, 0xAD), (0xC7, 0xEB, 0xBB), (0xC1, 0xEA, 0xD1),
+    (0xC1, 0xE5, 0xE9), (0xB6, 0xB6, 0xB6), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+#[rustfmt::skip]
+const FCEUX: [(u8, u8, u8); 64] = [
+    (0x74, 0x74, 0x74), (0x24, 0x18, 0x8C), (0x00, 0x00, 0xA8), (0x44, 0x00, 0x9C), (0x8C, 0x00, 0x74),
+    (0xA8, 0x00, 0x10), (0xA4, 0x00, 0x00), (0x7C, 0x08, 0x00), (0x40, 0x2C, 0x00), (0x00, 0x44, 0x00),
+    (0x00, 0x50, 0x00), (0x00, 0x3C, 0x14), (0x18, 0x3C, 0x5C), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00), (0xBC, 0xBC, 0xBC), (0x00, 0x70, 0xEC), (0x20, 0x38, 0xEC), (0x80, 0x00, 0xF0),
+    (0xBC, 0x00, 0xBC), (0xE4, 0x00, 0x58), (0xD8, 0x28, 0x00), (0xC8, 0x4C, 0x0C), (0x88, 0x70, 0x00),
+    (0x00, 0x94, 0x00), (0x00, 0xA8, 0x00), (0x00, 0x90, 0x38), (0x00, 0x80, 0x88), (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0xFC, 0xFC, 0xFC), (0x3C, 0xBC, 0xFC), (0x5C, 0x94, 0xFC),
+    (0xCC, 0x88, 0xFC), (0xF4, 0x78, 0xFC), (0xFC, 0x74, 0xB4), (0xFC, 0x74, 0x60), (0xFC, 0x98, 0x38),
+    (0xF0, 0xBC, 0x3C), (0x80, 0xD0, 0x10), (0x4C, 0xDC, 0x48), (0x58, 0xF8, 0x98), (0x00, 0xE8, 0xD8),
+    (0x78, 0x78, 0x78), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0xFC, 0xFC, 0xFC), (0xA8, 0xE4, 0xFC),
+    (0xC4, 0xD4, 0xFC), (0xD4, 0xC8, 0xFC), (0xFC, 0xC4, 0xFC), (0xFC, 0xC4, 0xD8), (0xFC, 0xBC, 0xB0),
+    (0xFC, 0xD8, 0xA8), (0xFC, 0xE4, 0xA0), (0xE0, 0xFC, 0xA0), (0xA8, 0xF0, 0xBC), (0xB0, 0xFC, 0xCC),
+    (0x9C, 0xFC, 0xF0), (0xC4, 0xC4, 0xC4), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+#[rustfmt::skip]
+const SMOOTH: [(u8, u8, u8); 64] = [
+    (0x6A, 0x6D, 0x6A), (0x00, 0x13, 0x80), (0x1E, 0x00, 0x8A), (0x39, 0x00, 0x7A), (0x55, 0x00, 0x56),
+    (0x5A, 0x00, 0x18), (0x4F, 0x10, 0x00), (0x3D, 0x1C, 0x00), (0x25, 0x32, 0x00), (0x00, 0x3D, 0x00),
+    (0x00, 0x40, 0x00), (0x00, 0x39, 0x24), (0x00, 0x2E, 0x55), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00), (0xB9, 0xBC, 0xB9), (0x18, 0x50, 0xC7), (0x4B, 0x30, 0xE3), (0x73, 0x22, 0xD6),
+    (0x95, 0x1F, 0xA9), (0x9D, 0x28, 0x5C), (0x98, 0x37, 0x00), (0x7F, 0x4C, 0x00), (0x5E, 0x64, 0x00),
+    (0x22, 0x77, 0x00), (0x02, 0x7E, 0x02), (0x00, 0x76, 0x45), (0x00, 0x6E, 0x8A), (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0x68, 0xA6, 0xFF), (0x8C, 0x9C, 0xFF),
+    (0xB5, 0x86, 0xFF), (0xD9, 0x75, 0xFD), (0xE3, 0x77, 0xB9), (0xE5, 0x8D, 0x68), (0xD4, 0x9D, 0x29),
+    (0xB3, 0xAF, 0x0C), (0x7B, 0xC2, 0x11), (0x55, 0xCA, 0x47), (0x46, 0xCB, 0x81), (0x47, 0xC1, 0xC5),
+    (0x4A, 0x4D, 0x4A), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0xCC, 0xEA, 0xFF),
+    (0xDD, 0xDE, 0xFF), (0xEC, 0xDA, 0xFF), (0xF8, 0xD7, 0xFE), (0xFC, 0xD6, 0xF5), (0xFD, 0xDB, 0xCF),
+    (0xF9, 0xE7, 0xB5), (0xF1, 0xF0, 0xAA), (0xDA, 0xFA, 0xA9), (0xC9, 0xFF, 0xBC), (0xC3, 0xFB, 0xD7),
+    (0xC4, 0xF6, 0xF6), (0xBE, 0xC1, 0xBE), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_files() {
+        let plain: Vec<u8> = (0..64 * 3).map(|i| i as u8).collect();
+        let palette = Palette::from_bytes(&plain).unwrap();
+        assert_eq!((3, 4, 5), palette.rgb(1, 0));
+
+        // Red emphasis dims green and blue, all three dim everything twice
+        let (r, g, b) = palette.rgb(0x21, 0b001);
+        assert_eq!((r, g, b), (99, 82, 82));
+        assert!(palette.rgb(0x21, 0b111).0 < r);
+        assert_eq!(palette.rgb(0x0e, 0), palette.rgb(0x0e, 0b111));
+
+        let full: Vec<u8> = (0..512 * 3).map(|i| (i / 3 / 64) as u8).collect();
+        let palette = Palette::from_bytes(&full).unwrap();
+        assert_eq!((5, 5, 5), palette.rgb(0x3f, 5));
+
+        assert!(Palette::from_bytes(&plain[1..]).is_err());
+    }
+
+    #[test]
+    fn test_builtin_palettes() {
+        for name in Palette::BUILTIN.iter() {
+            assert!(Palette::builtin(name).is_some());
+        }
+        assert!(Palette::builtin("nope").is_none());
+
+        let rgb = Palette::builtin("2C03").unwrap();
+        assert_eq!((0x6d, 0x6d, 0x6d), rgb.rgb(0x00, 0));
+        assert_eq!((0xff, 0x6d, 0x6d), rgb.rgb(0x00, 0b001));
+
+        assert_eq!(
+            Palette::default(),
+            Palette::from_region(CartridgeRegion::NTSC)
+        );
+        let pal = Palette::builtin("2c07").unwrap();
+        assert_eq!(pal, Palette::from_region(CartridgeRegion::Dendy));
+        assert_ne!(Palette::default().rgb(0x11, 0), pal.rgb(0x11, 0));
+    }
+}
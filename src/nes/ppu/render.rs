@@ -0,0 +1,179 @@
+use super::*;
+
+/// Two bitplanes of a tile row, and which of the four palettes it uses
+#[derive(Clone, Copy, Default)]
+struct TileRow {
+    low: u8,
+    high: u8,
+    palette: u8,
+}
+
+impl TileRow {
+    /// Colour 0-3 of a pixel, counting from the left
+    fn pixel(&self, x: u8) -> u8 {
+        let bit = 7 - x;
+        (((self.high >> bit) & 1) << 1) | ((self.low >> bit) & 1)
+    }
+}
+
+impl Ppu {
+    /**
+     * Draws the scanline `v` points at into the back frame, stepping a copy
+     * of `v` across the nametables the way the PPU does at every 8th dot, and
+     * puts the scanline's sprites over or behind it.
+     */
+    pub(super) fn render_scanline(&mut self, mapper: &dyn Mapper, vram: &[u8]) {
+        let y = self.scanline;
+        let sprites = self.sprite_line();
+        let background = self.background_line(mapper, vram);
+
+        for x in 0..Frame::WIDTH {
+            let (bg, sprite) = (background[x], sprites[x]);
+            let is_bg_opaque = (bg & 0b11) != 0;
+
+            // Sprite 0 hits never happen at x = 255, and only once a frame
+            if sprite.is_zero
+                && sprite.is_opaque()
+                && is_bg_opaque
+                && x != Frame::WIDTH - 1
+                && (self.status.get() & STATUS_SPRITE_ZERO) == 0
+                && self.sprite_zero_hit.is_none()
+            {
+                self.sprite_zero_hit = Some(x + 2);
+            }
+
+            let index = match (sprite.is_opaque(), is_bg_opaque) {
+                (true, false) => sprite.index,
+                (true, true) if !sprite.is_behind => sprite.index,
+                (_, true) => bg,
+                _ => 0,
+            };
+            let pixel = self.pixel(index as usize);
+            self.back.set_pixel(x, y, pixel);
+        }
+    }
+
+    /// Palette RAM indexes of the background, 0 where it's transparent
+    fn background_line(&self, mapper: &dyn Mapper, vram: &[u8]) -> [u8; Frame::WIDTH] {
+        let mut line = [0; Frame::WIDTH];
+        if (self.mask & MASK_BG) == 0 {
+            return line;
+        }
+
+        let mut v = self.v.get();
+        let mut fine_x = self.x;
+        let mut tile = self.fetch_tile(v, mapper, vram);
+
+        for (x, index) in line.iter_mut().enumerate() {
+            let colour = match x < 8 && (self.mask & MASK_BG_LEFT) == 0 {
+                true => 0,
+                false => tile.pixel(fine_x),
+            };
+            if colour != 0 {
+                *index = (tile.palette * 4) + colour;
+            }
+
+            fine_x += 1;
+            if fine_x == 8 {
+                fine_x = 0;
+                v = increment_x(v);
+                tile = self.fetch_tile(v, mapper, vram);
+            }
+        }
+        line
+    }
+
+    /// Nametable, attribute and pattern fetches for the tile at `v`
+    fn fetch_tile(&self, v: Addr, mapper: &dyn Mapper, vram: &[u8]) -> TileRow {
+        let name = mapper.load_ppu(0x2000 | (v & 0x0fff), vram);
+
+        // Each attribute byte covers 4x4 tiles, two bits per 2x2 of them
+        let attribute_addr = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let attribute = mapper.load_ppu(attribute_addr, vram);
+        let shift = ((v >> 4) & 0b100) | (v & 0b10);
+
+        let table: Addr = match self.ctrl & CTRL_BG_TABLE {
+            0 => 0x0000,
+            _ => 0x1000,
+        };
+        let pattern = table + (name as Addr * 16) + ((v >> 12) & 0b111);
+
+        TileRow {
+            low: mapper.load_ppu(pattern, vram),
+            high: mapper.load_ppu(pattern + 8, vram),
+            palette: (attribute >> shift) & 0b11,
+        }
+    }
+}
+
+/// Next tile to the right, into the next nametable after column 31
+fn increment_x(v: Addr) -> Addr {
+    match v & 0x001f {
+        31 => (v & !0x001f) ^ 0x0400,
+        _ => v + 1,
+    }
+}
+
+/**
+ * Both pattern tables side by side, 16x16 tiles each, in four shades of grey.
+ * Used to look at a cartridge's CHR.
+ */
+pub fn pattern_tables(chr: &[u8]) -> Image {
+    const SHADES: [u8; 4] = [0x0f, 0x00, 0x10, 0x30];
+    let mut frame = Image::new(256, 128);
+
+    for (n, tile) in chr.chunks_exact(16).take(512).enumerate() {
+        let (table, n) = (n / 256, n % 256);
+        let (left, top) = (table * 128 + (n % 16) * 8, (n / 16) * 8);
+
+        for y in 0..8 {
+            let row = TileRow {
+                low: tile[y],
+                high: tile[y + 8],
+                palette: 0,
+            };
+            for x in 0..8 {
+                let shade = SHADES[row.pixel(x) as usize];
+                frame.set_pixel(left + x as usize, top + y, PALLETE[shade as usize]);
+            }
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cartridge::MirrorDirection;
+    use crate::nes::mapper::{Nrom, VRAM_SIZE};
+
+    #[test]
+    fn test_render_background() {
+        // Tile 1 is solid colour 3
+        let mut chr = vec![0; 0x2000];
+        chr[16..32].iter_mut().for_each(|b| *b = 0xff);
+        let mut mapper = Nrom::new(vec![0; 0x4000], chr, MirrorDirection::Vertical);
+        let mut vram = vec![0; VRAM_SIZE];
+
+        // Tile 1 at the top left of the second nametable, with palette 2
+        vram[0x400] = 1;
+        vram[0x400 + 0x3c0] = 0b10;
+
+        let mut ppu = Ppu::new();
+        ppu.palette[0] = 0x0f;
+        ppu.palette[2 * 4 + 3] = 0x30;
+        ppu.mask = MASK_BG | MASK_BG_LEFT;
+
+        // Scroll 4 pixels into the first nametable's right hand edge
+        ppu.t = 31;
+        ppu.x = 4;
+        ppu.v.set(ppu.t);
+
+        ppu.tick(Ppu::DOTS * 8, &mut mapper, &vram);
+        let frame = &ppu.back;
+        assert_eq!(0x0f, frame.pixel(3, 0));
+        assert_eq!(0x30, frame.pixel(4, 0));
+        assert_eq!(0x30, frame.pixel(11, 7));
+        assert_eq!(0x0f, frame.pixel(12, 0));
+    }
+}
@@ -0,0 +1,240 @@
+use super::*;
+
+/// One sprite's row of pixels, picked for the next scanline
+#[derive(Clone, Copy)]
+pub(super) struct SpriteRow {
+    x: u8,
+    low: u8,
+    high: u8,
+    attribute: u8,
+    is_zero: bool,
+}
+
+/// A sprite pixel over the background
+#[derive(Clone, Copy, Default)]
+pub(super) struct SpritePixel {
+    /// Palette RAM index, 0 when no sprite is here
+    pub index: u8,
+    pub is_behind: bool,
+    pub is_zero: bool,
+}
+
+impl SpritePixel {
+    pub fn is_opaque(&self) -> bool {
+        (self.index & 0b11) != 0
+    }
+}
+
+const ATTRIBUTE_PALETTE: u8 = 0b0000_0011;
+const ATTRIBUTE_BEHIND: u8 = 0b0010_0000;
+const ATTRIBUTE_FLIP_X: u8 = 0b0100_0000;
+const ATTRIBUTE_FLIP_Y: u8 = 0b1000_0000;
+
+impl Ppu {
+    pub const MAX_SPRITES: usize = 8;
+
+    fn sprite_height(&self) -> isize {
+        match self.ctrl & CTRL_SPRITE_SIZE {
+            0 => 8,
+            _ => 16,
+        }
+    }
+
+    /**
+     * Sprite evaluation, which the PPU does while drawing a scanline for the
+     * one after: the first 8 sprites in range are copied to secondary OAM and
+     * their patterns fetched. Past the 8th, the search for a 9th goes wrong on
+     * real hardware, it steps through the byte of each sprite it compares as
+     * well as the sprite, so the overflow flag is set for the wrong sprites or
+     * not at all.
+     */
+    pub(super) fn evaluate_sprites(&mut self, mapper: &dyn Mapper, vram: &[u8]) {
+        self.sprites.clear();
+        let height = self.sprite_height();
+        let in_range = |y: u8| (0..height).contains(&(self.scanline as isize - y as isize));
+
+        let mut n = 0;
+        let mut found = Vec::with_capacity(Self::MAX_SPRITES);
+        while n < 64 && found.len() < Self::MAX_SPRITES {
+            if in_range(self.oam[n * 4]) {
+                found.push(n);
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status.set(self.status.get() | STATUS_OVERFLOW);
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+
+        for n in found {
+            let sprite = &self.oam[n * 4..n * 4 + 4];
+            let row = self.fetch_sprite(sprite, mapper, vram);
+            self.sprites.push(SpriteRow {
+                is_zero: n == 0,
+                ..row
+            });
+        }
+    }
+
+    fn fetch_sprite(&self, sprite: &[u8], mapper: &dyn Mapper, vram: &[u8]) -> SpriteRow {
+        let (y, tile, attribute, x) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+        let height = self.sprite_height();
+
+        let mut row = self.scanline as isize - y as isize;
+        if (attribute & ATTRIBUTE_FLIP_Y) != 0 {
+            row = height - 1 - row;
+        }
+        let row = row as Addr;
+
+        // 8x16 sprites take their table from bit 0 of the tile number, and are
+        // two tiles one above the other
+        let addr = match height {
+            16 => {
+                let table = (tile as Addr & 1) * 0x1000;
+                let tile = (tile as Addr & 0xfe) + (row / 8);
+                table + tile * 16 + (row % 8)
+            }
+            _ => {
+                let table: Addr = match self.ctrl & CTRL_SPRITE_TABLE {
+                    0 => 0x0000,
+                    _ => 0x1000,
+                };
+                table + tile as Addr * 16 + row
+            }
+        };
+
+        let (mut low, mut high) = (mapper.load_ppu(addr, vram), mapper.load_ppu(addr + 8, vram));
+        if (attribute & ATTRIBUTE_FLIP_X) != 0 {
+            low = low.reverse_bits();
+            high = high.reverse_bits();
+        }
+
+        SpriteRow {
+            x,
+            low,
+            high,
+            attribute,
+            is_zero: false,
+        }
+    }
+
+    /// The sprites picked for this scanline, drawn into a line of pixels
+    pub(super) fn sprite_line(&self) -> [SpritePixel; Frame::WIDTH] {
+        let mut line = [SpritePixel::default(); Frame::WIDTH];
+        if (self.mask & MASK_SPRITES) == 0 {
+            return line;
+        }
+
+        // Earlier sprites are in front, so draw back to front
+        for sprite in self.sprites.iter().rev() {
+            for i in 0..8 {
+                let x = sprite.x as usize + i;
+                if x >= Frame::WIDTH || (x < 8 && (self.mask & MASK_SPRITES_LEFT) == 0) {
+                    continue;
+                }
+
+                let bit = 7 - i;
+                let colour = (((sprite.high >> bit) & 1) << 1) | ((sprite.low >> bit) & 1);
+                if colour == 0 {
+                    continue;
+                }
+                line[x] = SpritePixel {
+                    index: 0x10 | ((sprite.attribute & ATTRIBUTE_PALETTE) << 2) | colour,
+                    is_behind: (sprite.attribute & ATTRIBUTE_BEHIND) != 0,
+                    is_zero: sprite.is_zero,
+                };
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cartridge::MirrorDirection;
+    use crate::nes::mapper::{Nrom, VRAM_SIZE};
+
+    fn setup() -> (Ppu, Nrom, Vec<u8>) {
+        // Tile 1 is a diagonal line from the top left, tile 2 solid colour 1
+        let mut chr = vec![0; 0x2000];
+        for row in 0..8 {
+            chr[16 + row] = 0x80 >> row;
+        }
+        chr[32..40].iter_mut().for_each(|b| *b = 0xff);
+        let mapper = Nrom::new(vec![0; 0x4000], chr, MirrorDirection::Vertical);
+
+        let mut ppu = Ppu::new();
+        ppu.mask = MASK_BG | MASK_SPRITES | MASK_BG_LEFT | MASK_SPRITES_LEFT;
+        ppu.oam.iter_mut().for_each(|b| *b = 0xff);
+        (ppu, mapper, vec![0; VRAM_SIZE])
+    }
+
+    #[test]
+    fn test_sprite_evaluation() {
+        let (mut ppu, mapper, vram) = setup();
+
+        // Nine sprites on scanline 20, flipped both ways
+        for n in 0..9 {
+            ppu.oam[n * 4..n * 4 + 4].copy_from_slice(&[18, 1, 0b1100_0000, 10 * n as u8]);
+        }
+        ppu.scanline = 20;
+        ppu.evaluate_sprites(&mapper, &vram);
+        assert_eq!(Ppu::MAX_SPRITES, ppu.sprites.len());
+        assert_ne!(0, ppu.status.get() & STATUS_OVERFLOW);
+
+        // Row 2 of the diagonal, flipped up and across, is row 5's bit
+        let row = ppu.sprites[1];
+        assert_eq!((10, 0x80 >> 2), (row.x, row.low));
+        assert!(ppu.sprites[0].is_zero && !row.is_zero);
+
+        let line = ppu.sprite_line();
+        assert_eq!(0x11, line[12].index);
+        assert!(!line[13].is_opaque());
+    }
+
+    #[test]
+    fn test_overflow_bug() {
+        let (mut ppu, mapper, vram) = setup();
+
+        // Eight sprites on the line, and the tenth is missed too: by the time
+        // the search gets to it, it's comparing tile numbers rather than Y
+        for n in 0..8 {
+            ppu.oam[n * 4] = 18;
+        }
+        ppu.oam[9 * 4] = 18;
+        ppu.scanline = 20;
+        ppu.evaluate_sprites(&mapper, &vram);
+        assert_eq!(0, ppu.status.get() & STATUS_OVERFLOW);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let (mut ppu, mut mapper, mut vram) = setup();
+        ppu.palette[0x01] = 0x2a;
+        ppu.palette[0x11] = 0x16;
+
+        // Solid background on the second row of tiles, and sprite 0's
+        // diagonal first crossing it at (2, 8)
+        vram[32] = 2;
+        ppu.oam[0..4].copy_from_slice(&[5, 1, 0, 0]);
+
+        ppu.tick(Ppu::DOTS * 8 + 3, &mut mapper, &vram);
+        assert_eq!(0, ppu.status.get() & STATUS_SPRITE_ZERO);
+        ppu.tick(2, &mut mapper, &vram);
+        assert_ne!(0, ppu.status.get() & STATUS_SPRITE_ZERO);
+        assert_eq!(0x16, ppu.back.pixel(2, 8));
+
+        // Behind the background it's still a hit, but the background shows
+        ppu.oam[2] = ATTRIBUTE_BEHIND;
+        ppu.tick(Timing::NTSC.scanlines * Ppu::DOTS, &mut mapper, &vram);
+        assert_ne!(0, ppu.status.get() & STATUS_SPRITE_ZERO);
+        assert_eq!(0x2a, ppu.back.pixel(2, 8));
+    }
+}
@@ -0,0 +1,110 @@
+//! Clocks and frame shapes of the consoles each region sold
+
+use crate::nes::cartridge::CartridgeRegion;
+
+/**
+ * How a console divides its master clock between the CPU and PPU, and how long
+ * its frames are. NTSC runs three PPU dots to every CPU cycle, PAL 3.2.
+ *
+ * Dendy, the Famicom clone sold in Russia, mixes the two: PAL's clock and
+ * frame length, so it shows 50 frames a second, with the CPU divided down to
+ * NTSC's 3:1 and vblank held back until scanline 291 so NTSC games have their
+ * usual time in vblank.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub region: CartridgeRegion,
+    /// Master clock, in Hz
+    pub master_clock: f64,
+    /// Master clocks to a CPU cycle
+    pub cpu_divider: usize,
+    /// Master clocks to a PPU dot
+    pub ppu_divider: usize,
+    /// Scanlines in a frame, the last being the pre-render line
+    pub scanlines: usize,
+    pub vblank_scanline: usize,
+    /// Whether odd frames are a dot shorter while rendering
+    pub skips_odd_dot: bool,
+}
+
+impl Timing {
+    pub const NTSC: Self = Self {
+        region: CartridgeRegion::NTSC,
+        master_clock: 21_477_272.0,
+        cpu_divider: 12,
+        ppu_divider: 4,
+        scanlines: 262,
+        vblank_scanline: 241,
+        skips_odd_dot: true,
+    };
+
+    pub const PAL: Self = Self {
+        region: CartridgeRegion::PAL,
+        master_clock: 26_601_712.0,
+        cpu_divider: 16,
+        ppu_divider: 5,
+        scanlines: 312,
+        vblank_scanline: 241,
+        skips_odd_dot: false,
+    };
+
+    pub const DENDY: Self = Self {
+        region: CartridgeRegion::Dendy,
+        master_clock: 26_601_712.0,
+        cpu_divider: 15,
+        ppu_divider: 5,
+        scanlines: 312,
+        vblank_scanline: 291,
+        skips_odd_dot: false,
+    };
+
+    pub fn from_region(region: CartridgeRegion) -> Self {
+        match region {
+            CartridgeRegion::NTSC => Self::NTSC,
+            CartridgeRegion::PAL => Self::PAL,
+            CartridgeRegion::Dendy => Self::DENDY,
+        }
+    }
+
+    pub fn pre_render_scanline(&self) -> usize {
+        self.scanlines - 1
+    }
+
+    /// CPU clock, in Hz
+    pub fn cpu_frequency(&self) -> f64 {
+        self.master_clock / self.cpu_divider as f64
+    }
+
+    /// Frames a second, going by the long frames
+    pub fn frame_rate(&self) -> f64 {
+        let dots = (self.scanlines * crate::nes::ppu::Ppu::DOTS) as f64;
+        self.master_clock / self.ppu_divider as f64 / dots
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::NTSC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing() {
+        let ntsc = Timing::NTSC;
+        assert!((ntsc.cpu_frequency() - 1_789_772.7).abs() < 1.0);
+        assert!((ntsc.frame_rate() - 60.1).abs() < 0.01);
+
+        let pal = Timing::from_region(CartridgeRegion::PAL);
+        assert!((pal.cpu_frequency() - 1_662_607.0).abs() < 1.0);
+        assert!((pal.frame_rate() - 50.0).abs() < 0.01);
+        assert_eq!(311, pal.pre_render_scanline());
+
+        let dendy = Timing::DENDY;
+        assert_eq!(3, dendy.cpu_divider / dendy.ppu_divider);
+        assert_eq!(pal.frame_rate(), dendy.frame_rate());
+    }
+}
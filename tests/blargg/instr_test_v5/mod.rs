@@ -4,7 +4,9 @@ use std::io::{prelude::*, BufReader};
 
 use iron_nes::nes::cpu;
 
-pub fn get_golden<'a>(golden_file: &'a str) -> impl Iterator<Item = (usize, cpu::Registers)> {
+pub fn get_golden<'a>(
+    golden_file: &'a str,
+) -> impl Iterator<Item = (usize, Option<(usize, usize)>, cpu::Registers)> {
     let re = Regex::new(r"([0-9A-F]{4})  ([0-9A-Z]{2})  A:([0-9A-F]{2}).*X:([0-9A-F]{2}).*Y:([0-9A-F]{2}).*P:([0-9A-F]{2}).*SP:([0-9A-F]{2}).*CYC:.*([0-9]+)").unwrap();
 
     let file = File::open(golden_file).unwrap();
@@ -26,6 +28,6 @@ pub fn get_golden<'a>(golden_file: &'a str) -> impl Iterator<Item = (usize, cpu:
         reg.sp = u16::from_str_radix(caps.get(7).unwrap().as_str(), 16).unwrap();
         let cyc: usize = caps.get(8).unwrap().as_str().parse().unwrap();
 
-        (cyc, reg)
+        (cyc, None, reg)
     })
 }
@@ -38,7 +38,7 @@ fn get_filename(parts: &[&str]) -> String {
 // load: where to preload the PC
 fn run_test(
     rom: String,
-    golden: impl Iterator<Item = (usize, cpu::Registers)>,
+    golden: impl Iterator<Item = (usize, Option<(usize, usize)>, cpu::Registers)>,
     load: Option<memory::Addr>,
     can_count_cycles: bool,
 ) -> IronNesResult<IronNes> {
@@ -51,7 +51,7 @@ fn run_test(
         nes.jsr(x)?;
     }
 
-    golden.for_each(|(golden_cyc, golden_reg)| {
+    golden.for_each(|(golden_cyc, golden_ppu, golden_reg)| {
         let regs = nes.get_cpu_registers();
         let reg_p = regs.get_status();
         let cpu_cycles = nes.get_cycles();
@@ -95,6 +95,18 @@ fn run_test(
                 golden_cyc, cpu_cycles
             );
         }
+        if let Some((scanline, dot)) = golden_ppu {
+            let ppu = nes.ppu();
+            assert_eq!(
+                (scanline, dot),
+                (ppu.scanline(), ppu.dot()),
+                "PPU position mismatch expected: {},{} actual: {},{}",
+                scanline,
+                dot,
+                ppu.scanline(),
+                ppu.dot()
+            );
+        }
 
         nes.step().unwrap();
     });
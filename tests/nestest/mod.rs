@@ -4,8 +4,10 @@ use std::io::{prelude::*, BufReader};
 
 use iron_nes::nes::cpu;
 
-pub fn get_golden<'a>(golden_file: &'a str) -> impl Iterator<Item = (usize, cpu::Registers)> {
-    let re = Regex::new(r"([0-9A-F]{4}).*([A-Z]{3}).*A:([0-9A-F]{2}) X:([0-9A-F]{2}) Y:([0-9A-F]{2}) P:([0-9A-F]{2}) SP:([0-9A-F]{2}) PPU:.*([0-9]+),.*([0-9]+) CYC:([0-9]+)").unwrap();
+pub fn get_golden<'a>(
+    golden_file: &'a str,
+) -> impl Iterator<Item = (usize, Option<(usize, usize)>, cpu::Registers)> {
+    let re = Regex::new(r"([0-9A-F]{4}).*([A-Z]{3}).*A:([0-9A-F]{2}) X:([0-9A-F]{2}) Y:([0-9A-F]{2}) P:([0-9A-F]{2}) SP:([0-9A-F]{2}) PPU:\s*([0-9]+),\s*([0-9]+) CYC:([0-9]+)").unwrap();
 
     let file = File::open(golden_file).unwrap();
     let reader = BufReader::new(file);
@@ -24,9 +26,10 @@ pub fn get_golden<'a>(golden_file: &'a str) -> impl Iterator<Item = (usize, cpu:
         let flags = u8::from_str_radix(caps.get(6).unwrap().as_str(), 16).unwrap();
         reg.set_status(flags);
         reg.sp = u16::from_str_radix(caps.get(7).unwrap().as_str(), 16).unwrap();
+        let scanline: usize = caps.get(8).unwrap().as_str().parse().unwrap();
+        let dot: usize = caps.get(9).unwrap().as_str().parse().unwrap();
         let cyc: usize = caps.get(10).unwrap().as_str().parse().unwrap();
-        let cyc = cyc - 1; // Off by 1
 
-        (cyc, reg)
+        (cyc, Some((scanline, dot)), reg)
     })
 }